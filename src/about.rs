@@ -0,0 +1,175 @@
+use crate::cache::{Cache, CacheEntryInfo};
+use crate::character_reference::CharacterReference;
+use crate::config::Config;
+use crate::history::{History, Visit};
+use crate::http_date::HttpDate;
+use crate::proxy::ProxySettings;
+
+// The internal about: pages. They are generated on every visit, so they always show the current
+// state. Pages can't contain controls yet, hence about:cache has no purge button, the cache is
+// emptied with the --clear-cache option instead.
+pub struct AboutPage {}
+
+impl AboutPage {
+    pub fn render(name: &str) -> String {
+        match name {
+            "blank" => String::new(),
+            "cache" => Self::cache_page(&Cache::global().lock().unwrap().list(), HttpDate::now()),
+            "history" => Self::history_page(History::global().lock().unwrap().visits()),
+            "config" => Self::config_page(
+                &Config::global().entries(),
+                &ProxySettings::global().entries(),
+            ),
+            _ => Self::page(
+                "Unknown page",
+                &format!(
+                    "<p>There is no page about:{}</p>",
+                    CharacterReference::escape(name)
+                ),
+            ),
+        }
+    }
+
+    fn cache_page(entries: &[CacheEntryInfo], now: u64) -> String {
+        let mut content = format!(
+            "<p>{} cached responses, {} bytes</p>\
+             <p>To empty the cache, start rbrowser with --clear-cache.</p>",
+            entries.len(),
+            entries.iter().map(|entry| entry.size).sum::<u64>()
+        );
+
+        let rows = entries.iter().map(|entry| {
+            let state = if entry.expires > now {
+                "fresh"
+            } else {
+                "stale"
+            };
+
+            vec![
+                CharacterReference::escape(&entry.url),
+                entry.size.to_string(),
+                HttpDate::format(entry.response_time),
+                format!("{} ({})", HttpDate::format(entry.expires), state),
+            ]
+        });
+        content += &Self::table(&["URL", "Size", "Received", "Expires"], rows);
+
+        Self::page("Cache", &content)
+    }
+
+    // The most recent visits first
+    fn history_page(visits: &[Visit]) -> String {
+        let rows = visits.iter().rev().map(|visit| {
+            let url = CharacterReference::escape(&visit.url);
+            vec![
+                HttpDate::format(visit.time),
+                format!("<a href=\"{}\">{}</a>", url, url),
+            ]
+        });
+
+        Self::page("History", &Self::table(&["Visited", "URL"], rows))
+    }
+
+    fn config_page(config: &[(&str, String)], proxies: &[(&str, String)]) -> String {
+        let rows = |entries: &[(&str, String)]| {
+            entries
+                .iter()
+                .map(|(name, value)| vec![name.to_string(), CharacterReference::escape(value)])
+                .collect::<Vec<Vec<String>>>()
+        };
+
+        let content = format!(
+            "<h2>Settings</h2>{}<h2>Proxies in use (from settings or environment)</h2>{}",
+            Self::table(&["Name", "Value"], rows(config)),
+            Self::table(&["Name", "Value"], rows(proxies))
+        );
+
+        Self::page("Configuration", &content)
+    }
+
+    // The cells are expected to be escaped already
+    fn table(headings: &[&str], rows: impl IntoIterator<Item = Vec<String>>) -> String {
+        let mut table = String::from("<table><tr>");
+
+        for heading in headings {
+            table += &format!("<th>{}</th>", heading);
+        }
+        table += "</tr>";
+
+        for row in rows {
+            table += "<tr>";
+            for cell in row {
+                table += &format!("<td>{}</td>", cell);
+            }
+            table += "</tr>";
+        }
+
+        table + "</table>"
+    }
+
+    fn page(title: &str, content: &str) -> String {
+        format!(
+            "<html><head><title>{}</title></head><body><h1>{}</h1>{}</body></html>",
+            title, title, content
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blank() {
+        assert_eq!("", AboutPage::render("blank"));
+    }
+
+    #[test]
+    fn unknown_page() {
+        assert!(AboutPage::render("<x>").contains("There is no page about:&lt;x&gt;"));
+    }
+
+    #[test]
+    fn cache_page() {
+        let entries = [CacheEntryInfo {
+            url: String::from("http://example.com:80/?a&b"),
+            size: 42,
+            response_time: 0,
+            expires: 60,
+        }];
+        let page = AboutPage::cache_page(&entries, 100);
+        assert!(page.contains("<p>1 cached responses, 42 bytes</p>"));
+        assert!(page.contains("--clear-cache"));
+        assert!(page.contains(
+            "<tr><td>http://example.com:80/?a&amp;b</td><td>42</td>\
+             <td>Thu, 01 Jan 1970 00:00:00 GMT</td>\
+             <td>Thu, 01 Jan 1970 00:01:00 GMT (stale)</td></tr>"
+        ));
+    }
+
+    #[test]
+    fn history_page() {
+        let visits = [
+            Visit {
+                url: String::from("http://a.com/"),
+                time: 0,
+            },
+            Visit {
+                url: String::from("http://b.com/"),
+                time: 1,
+            },
+        ];
+        let page = AboutPage::history_page(&visits);
+        assert!(page.find("http://b.com/").unwrap() < page.find("http://a.com/").unwrap());
+    }
+
+    #[test]
+    fn config_page() {
+        let page = AboutPage::config_page(
+            &[("connect_timeout", String::from("10"))],
+            &[("no_proxy", String::from("localhost"))],
+        );
+        assert!(page.contains("<tr><td>connect_timeout</td><td>10</td></tr>"));
+        assert!(page.contains("<tr><td>no_proxy</td><td>localhost</td></tr>"));
+    }
+}
@@ -0,0 +1,414 @@
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+use crate::content_encoding::{ContentEncoding, Decoder};
+use crate::headers::Headers;
+use crate::network_error::{NetworkError, SizeLimit};
+
+// Where a chunked body is at, see https://www.rfc-editor.org/rfc/rfc9112#section-7.1
+#[derive(Debug, PartialEq)]
+enum ChunkState {
+    // reading the line with the chunk size, which is collected until it is complete
+    Size(Vec<u8>),
+    Data(u64),
+    // the line break after the chunk data
+    DataEnd,
+    // optional trailers after the last chunk, up to the empty line
+    Trailers(Vec<u8>),
+    Done,
+}
+
+// Removes the chunked transfer coding from a body received in arbitrary pieces. It is used both
+// to find the end of a chunked body while reading it and to decode it.
+#[derive(Debug)]
+pub struct Dechunker {
+    state: ChunkState,
+    trailers_size: usize,
+}
+
+impl Dechunker {
+    // chunk size lines and trailers are short, anything longer is not a valid chunked body
+    const MAX_LINE_SIZE: usize = 8 * 1024;
+
+    pub const fn new() -> Self {
+        Self {
+            state: ChunkState::Size(Vec::new()),
+            trailers_size: 0,
+        }
+    }
+
+    // Appends the chunk data contained in the bytes to data and returns how many of the bytes
+    // belong to the chunked body, which are less than all of them only after the last chunk
+    pub fn feed(&mut self, mut bytes: &[u8], data: &mut Vec<u8>) -> Result<usize, NetworkError> {
+        const INVALID: NetworkError = NetworkError::MalformedResponse("invalid chunk size");
+        let total = bytes.len();
+
+        while !bytes.is_empty() {
+            let trailers = matches!(self.state, ChunkState::Trailers(_));
+            match &mut self.state {
+                ChunkState::Size(line) | ChunkState::Trailers(line) => {
+                    let end = bytes.iter().position(|&b| b == b'\n');
+                    let (part, rest) = bytes.split_at(end.map_or(bytes.len(), |end| end + 1));
+                    line.extend_from_slice(part);
+                    bytes = rest;
+
+                    if trailers {
+                        self.trailers_size += part.len();
+                    }
+                    if line.len() > Self::MAX_LINE_SIZE || self.trailers_size > Self::MAX_LINE_SIZE
+                    {
+                        return Err(INVALID);
+                    } else if end.is_none() {
+                        continue;
+                    }
+
+                    let line = String::from_utf8_lossy(line).trim().to_string();
+                    self.state = match trailers {
+                        true if line.is_empty() => ChunkState::Done,
+                        true => ChunkState::Trailers(Vec::new()),
+                        false => {
+                            let size = line.split(';').next().unwrap_or_default().trim();
+                            match u64::from_str_radix(size, 16).map_err(|_| INVALID)? {
+                                0 => ChunkState::Trailers(Vec::new()),
+                                size => ChunkState::Data(size),
+                            }
+                        }
+                    };
+                }
+                ChunkState::Data(remaining) => {
+                    let length =
+                        usize::try_from(*remaining).map_or(bytes.len(), |r| bytes.len().min(r));
+                    data.extend_from_slice(&bytes[..length]);
+                    bytes = &bytes[length..];
+                    *remaining -= length as u64;
+
+                    if *remaining == 0 {
+                        self.state = ChunkState::DataEnd;
+                    }
+                }
+                ChunkState::DataEnd => {
+                    let byte = bytes[0];
+                    bytes = &bytes[1..];
+
+                    if byte == b'\n' {
+                        self.state = ChunkState::Size(Vec::new());
+                    } else if byte != b'\r' {
+                        return Err(NetworkError::MalformedResponse("invalid chunk"));
+                    }
+                }
+                // anything after the last chunk doesn't belong to the body
+                ChunkState::Done => break,
+            }
+        }
+
+        Ok(total - bytes.len())
+    }
+
+    // A body is only chunked if chunked is its last transfer coding, otherwise it ends when the
+    // connection is closed, see https://www.rfc-editor.org/rfc/rfc9112#section-6.3
+    pub fn is_chunked(transfer_encoding: &str) -> bool {
+        transfer_encoding
+            .trim()
+            .to_ascii_lowercase()
+            .ends_with("chunked")
+    }
+
+    // Whether the last chunk and the trailers have been received
+    pub fn is_done(&self) -> bool {
+        self.state == ChunkState::Done
+    }
+}
+
+// The end of the decoder chain, it collects the decoded body and enforces its size limit
+struct Output {
+    body: Rc<RefCell<Vec<u8>>>,
+    max_size: u64,
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut body = self.body.borrow_mut();
+
+        if (body.len() + buf.len()) as u64 > self.max_size {
+            return Err(io::Error::other(NetworkError::TooLarge(
+                SizeLimit::DecodedBodySize(self.max_size),
+            )));
+        }
+
+        body.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Decodes a body piece by piece while it is being received: first the chunked transfer coding is
+// removed, then the content codings in the reverse order they were applied. The decoded body grows
+// with every piece, and is complete once the decoder is finished.
+pub struct BodyDecoder {
+    dechunker: Option<Dechunker>,
+    decoder: Decoder,
+    // the body without transfer coding, but still with its content codings
+    raw: Vec<u8>,
+    body: Rc<RefCell<Vec<u8>>>,
+    // a body without any bytes, like the one of a response to HEAD, is complete even if chunked
+    received: bool,
+}
+
+impl BodyDecoder {
+    pub fn new(headers: &Headers, max_size: u64) -> Result<Self, NetworkError> {
+        let body = Rc::new(RefCell::new(Vec::new()));
+        let mut decoder = Decoder::Output(Box::new(Output {
+            body: Rc::clone(&body),
+            max_size,
+        }));
+
+        // the coding applied last has to be removed first, i.e. its decoder is the outermost
+        for encoding in ContentEncoding::from_headers(headers)? {
+            decoder = encoding.decoder(decoder);
+        }
+
+        Ok(Self {
+            dechunker: headers
+                .get_all("Transfer-Encoding")
+                .last()
+                .map_or(false, Dechunker::is_chunked)
+                .then(Dechunker::new),
+            decoder,
+            raw: Vec::new(),
+            body,
+            received: false,
+        })
+    }
+
+    pub fn feed(&mut self, bytes: &[u8]) -> Result<(), NetworkError> {
+        let start = self.raw.len();
+        match &mut self.dechunker {
+            Some(dechunker) => {
+                dechunker.feed(bytes, &mut self.raw)?;
+            }
+            None => self.raw.extend_from_slice(bytes),
+        }
+        self.received |= !bytes.is_empty();
+
+        let decoder = &mut self.decoder;
+        decoder
+            .write_all(&self.raw[start..])
+            .and_then(|()| decoder.flush())
+            .map_err(Self::decoding_error)
+    }
+
+    // The body decoded so far
+    pub fn body(&self) -> std::cell::Ref<'_, Vec<u8>> {
+        self.body.borrow()
+    }
+
+    // Returns the body without transfer coding and the decoded body, or an error if the body
+    // ended early
+    pub fn finish(mut self) -> Result<(Vec<u8>, Vec<u8>), NetworkError> {
+        let chunked_body_complete = self
+            .dechunker
+            .as_ref()
+            .map_or(true, |dechunker| dechunker.is_done() || !self.received);
+        if !chunked_body_complete {
+            return Err(NetworkError::MalformedResponse("truncated chunked body"));
+        }
+
+        // responses to HEAD, 204 and 304 have no body even with a Content-Encoding, and neither
+        // has anything else without a single byte of content coding data
+        if !self.raw.is_empty() {
+            self.decoder.finish().map_err(Self::decoding_error)?;
+        }
+        Ok((self.raw, self.body.take()))
+    }
+
+    fn decoding_error(e: io::Error) -> NetworkError {
+        match NetworkError::from(e) {
+            e @ NetworkError::TooLarge(_) => e,
+            _ => NetworkError::Decoding("invalid data"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::{GzEncoder, ZlibEncoder};
+    use flate2::Compression;
+
+    fn headers(pairs: &[(&str, &str)]) -> Headers {
+        let mut headers = Headers::new();
+        for (name, value) in pairs {
+            headers.append(name, value);
+        }
+        headers
+    }
+
+    fn compress(mut encoder: impl Write, data: &[u8]) {
+        encoder.write_all(data).unwrap();
+        encoder.flush().unwrap();
+    }
+
+    #[test]
+    fn dechunk_in_pieces() {
+        let body =
+            b"4\r\nWiki\r\n6;ext=1\r\npedia \r\nE\r\nin \r\n\r\nchunks.\r\n0\r\nA: b\r\n\r\n";
+
+        for piece_size in [1, 3, body.len()] {
+            let mut dechunker = Dechunker::new();
+            let mut data = Vec::new();
+            for piece in body.chunks(piece_size) {
+                dechunker.feed(piece, &mut data).unwrap();
+            }
+
+            assert_eq!(b"Wikipedia in \r\n\r\nchunks.".to_vec(), data);
+            assert_eq!(ChunkState::Done, dechunker.state);
+        }
+    }
+
+    #[test]
+    fn dechunk_up_to_the_last_chunk() {
+        let mut dechunker = Dechunker::new();
+        let mut data = Vec::new();
+
+        assert_eq!(9, dechunker.feed(b"4\r\nWiki\r\n", &mut data).unwrap());
+        assert!(!dechunker.is_done());
+        assert_eq!(5, dechunker.feed(b"0\r\n\r\nNext", &mut data).unwrap());
+        assert!(dechunker.is_done());
+        assert_eq!(b"Wiki".to_vec(), data);
+    }
+
+    #[test]
+    fn dechunk_too_large_trailers() {
+        let trailers = "0\r\n".to_string() + &"A: b\r\n".repeat(2000);
+        assert!(Dechunker::new()
+            .feed(trailers.as_bytes(), &mut Vec::new())
+            .is_err());
+    }
+
+    #[test]
+    fn dechunk_invalid_size() {
+        assert!(Dechunker::new().feed(b"xyz\r\n", &mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn finish() {
+        let chunked = headers(&[("Transfer-Encoding", "chunked")]);
+
+        let mut decoder = BodyDecoder::new(&chunked, 1024).unwrap();
+        decoder.feed(b"4\r\nWiki\r\n0\r\n\r\n").unwrap();
+        assert_eq!(
+            (b"Wiki".to_vec(), b"Wiki".to_vec()),
+            decoder.finish().unwrap()
+        );
+
+        let mut decoder = BodyDecoder::new(&chunked, 1024).unwrap();
+        decoder.feed(b"4\r\nWiki\r\n").unwrap();
+        assert!(matches!(
+            decoder.finish(),
+            Err(NetworkError::MalformedResponse("truncated chunked body"))
+        ));
+
+        // e.g. the response to a HEAD request
+        let decoder = BodyDecoder::new(&chunked, 1024).unwrap();
+        assert_eq!((Vec::new(), Vec::new()), decoder.finish().unwrap());
+    }
+
+    #[test]
+    fn feed_with_other_transfer_encoding() {
+        let mut decoder =
+            BodyDecoder::new(&headers(&[("Transfer-Encoding", "identity")]), 1024).unwrap();
+        decoder.feed(b"4\r\nWiki").unwrap();
+        assert_eq!(
+            (b"4\r\nWiki".to_vec(), b"4\r\nWiki".to_vec()),
+            decoder.finish().unwrap()
+        );
+    }
+
+    #[test]
+    fn finish_without_body() {
+        for encoding in ["gzip", "deflate", "br"] {
+            let decoder = BodyDecoder::new(&headers(&[("Content-Encoding", encoding)]), 1024);
+            assert_eq!((Vec::new(), Vec::new()), decoder.unwrap().finish().unwrap());
+        }
+    }
+
+    #[test]
+    fn finish_reports_truncated_content_coding() {
+        let mut compressed = Vec::new();
+        compress(
+            GzEncoder::new(&mut compressed, Compression::default()),
+            b"Hello World",
+        );
+
+        // the end of the gzip trailer is missing
+        let mut decoder =
+            BodyDecoder::new(&headers(&[("Content-Encoding", "gzip")]), 1024).unwrap();
+        decoder.feed(&compressed[..compressed.len() - 4]).unwrap();
+        assert!(matches!(decoder.finish(), Err(NetworkError::Decoding(_))));
+    }
+
+    #[test]
+    fn decode_gzip_in_pieces() {
+        let mut compressed = Vec::new();
+        compress(
+            GzEncoder::new(&mut compressed, Compression::default()),
+            b"Hello World",
+        );
+
+        let mut decoder =
+            BodyDecoder::new(&headers(&[("Content-Encoding", "gzip")]), 1024).unwrap();
+        for piece in compressed.chunks(5) {
+            decoder.feed(piece).unwrap();
+        }
+        assert_eq!(b"Hello World".to_vec(), *decoder.body());
+    }
+
+    #[test]
+    fn decode_chunked_zlib() {
+        let mut compressed = Vec::new();
+        compress(
+            ZlibEncoder::new(&mut compressed, Compression::default()),
+            b"Hello World",
+        );
+        let mut body = format!("{:x}\r\n", compressed.len()).into_bytes();
+        body.extend_from_slice(&compressed);
+        body.extend_from_slice(b"\r\n0\r\n\r\n");
+
+        let headers = headers(&[
+            ("Transfer-Encoding", "chunked"),
+            ("Content-Encoding", "deflate"),
+        ]);
+        let mut decoder = BodyDecoder::new(&headers, 1024).unwrap();
+        for piece in body.chunks(1) {
+            decoder.feed(piece).unwrap();
+        }
+        assert_eq!(b"Hello World".to_vec(), *decoder.body());
+    }
+
+    #[test]
+    fn decode_brotli() {
+        let mut decoder = BodyDecoder::new(&headers(&[("Content-Encoding", "br")]), 1024).unwrap();
+        decoder.feed(b"\x0b\x05\x80Hello World\x03").unwrap();
+        assert_eq!(b"Hello World".to_vec(), *decoder.body());
+    }
+
+    #[test]
+    fn decode_too_large() {
+        let mut compressed = Vec::new();
+        compress(
+            GzEncoder::new(&mut compressed, Compression::default()),
+            &[0; 4096],
+        );
+
+        let mut decoder =
+            BodyDecoder::new(&headers(&[("Content-Encoding", "gzip")]), 1024).unwrap();
+        assert!(matches!(
+            decoder.feed(&compressed),
+            Err(NetworkError::TooLarge(SizeLimit::DecodedBodySize(1024)))
+        ));
+    }
+}
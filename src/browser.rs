@@ -1,10 +1,25 @@
 use gtk::gio::ApplicationFlags;
-use gtk::{prelude::*, DrawingArea};
+use gtk::{gdk, glib};
+use gtk::{prelude::*, DrawingArea, EventControllerKey};
 use gtk::{Application, ApplicationWindow};
-use std::io;
+use std::cell::RefCell;
+use std::error::Error;
+use std::rc::Rc;
 use std::str;
-
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::about::AboutPage;
+use crate::cancellation::Cancellation;
+use crate::dom::{Document, Element, NodeData, NodeId};
+use crate::history::History;
+use crate::network_error::NetworkError;
+use crate::request::Method;
 use crate::request_handler::RequestHandler;
+use crate::response::{Response, StatusClass};
+use crate::response_stream::ResponseStream;
+use crate::tree_builder::TreeBuilder;
 use crate::url_parser::UrlType;
 
 #[derive(Clone)]
@@ -12,49 +27,258 @@ struct Position(f64, f64);
 
 type DisplayList = Vec<(Position, char)>;
 
+// What is shown of a page
+#[derive(Debug, PartialEq)]
+struct Page {
+    // the window title, if the page has one
+    title: Option<String>,
+    text: String,
+}
+
+impl Page {
+    const fn untitled(text: String) -> Self {
+        Self { title: None, text }
+    }
+}
+
+// What the background thread sends to the UI
+enum Output {
+    // the page rendered from the part of the response received so far
+    Partial(Page),
+    Complete(Page),
+}
+
 #[derive(Debug)]
 pub struct Browser {}
 
 impl Browser {
-    pub fn load(url_type: &UrlType) -> io::Result<()> {
-        let output = match url_type {
-            UrlType::Http(url) => Self::lex(&RequestHandler::request(url)?),
-            UrlType::ViewSource(url) => RequestHandler::request(url)?,
+    // Shown as window title for pages without title
+    const DEFAULT_TITLE: &'static str = "rbrowser";
+    // There is no JavaScript support, hence the content of <noscript> elements is shown
+    const SCRIPTING: bool = false;
+    // How often the UI checks whether the page has been loaded
+    const LOAD_POLL_INTERVAL: Duration = Duration::from_millis(50);
+    // How often a partially received page is rendered again, each time the whole page received so
+    // far is rendered
+    const PARTIAL_RENDER_INTERVAL: Duration = Duration::from_millis(250);
+
+    // The page is loaded in the background so the window stays responsive, pressing Escape
+    // cancels the request. The method and body are only used for http(s) urls. While the page is
+    // received, the part received so far is shown.
+    pub fn load(url_type: UrlType, method: Method, body: Option<Vec<u8>>) {
+        let cancellation = Cancellation::new();
+        let (sender, receiver) = mpsc::channel();
+
+        let worker_cancellation = cancellation.clone();
+        thread::spawn(move || {
+            let mut partial = |output| {
+                let _ = sender.send(Output::Partial(output));
+            };
+            let output = Self::fetch(&url_type, method, body, &worker_cancellation, &mut partial);
+            // the window might have been closed in the meantime
+            let _ = sender.send(Output::Complete(output));
+        });
+
+        Self::build_ui(receiver, cancellation);
+    }
+
+    // Loads the page without showing it and returns the text of the elements matching the
+    // selectors, for scripts extracting data from pages. Like curl does, the body of error
+    // responses is used as well.
+    pub fn select(
+        url_type: &UrlType,
+        method: Method,
+        body: Option<Vec<u8>>,
+        selectors: &str,
+    ) -> Result<Vec<String>, Box<dyn Error>> {
+        let html = match url_type {
+            UrlType::Http(url) | UrlType::File(url) | UrlType::ViewSource(url) => {
+                RequestHandler::request(method, url, body, &Cancellation::new(), &mut |_| {})?.body
+            }
+            UrlType::Data {
+                mediatype: _,
+                base64: _,
+                data,
+            } => data.clone(),
+            UrlType::About(page) => AboutPage::render(page),
+        };
+
+        let document = TreeBuilder::build(&html);
+        Ok(document
+            .query_selector_all(selectors)?
+            .into_iter()
+            .map(|id| document.text_content(id).trim().to_string())
+            .collect())
+    }
+
+    fn fetch(
+        url_type: &UrlType,
+        method: Method,
+        body: Option<Vec<u8>>,
+        cancellation: &Cancellation,
+        partial: &mut dyn FnMut(Page),
+    ) -> Page {
+        match url_type {
+            UrlType::Http(url) | UrlType::File(url) => {
+                History::global().lock().unwrap().add(&url.to_string());
+
+                // error responses are replaced by an error page, hence only successful ones are
+                // shown while they are received
+                let mut progress = Self::partial_renderer(partial, |stream| {
+                    stream
+                        .status()
+                        .map_or(false, |status| status.class() == StatusClass::Success)
+                        .then(|| Self::lex(&stream.body()))
+                });
+
+                match RequestHandler::request(method, url, body, cancellation, &mut progress) {
+                    Ok(response) if response.status.is_error() => {
+                        Self::lex(&Self::error_page(&response))
+                    }
+                    Ok(response) => Self::lex(&response.body),
+                    Err(e) => Self::lex(&Self::network_error_page(&e)),
+                }
+            }
+            UrlType::ViewSource(url) => {
+                let mut progress =
+                    Self::partial_renderer(partial, |stream| Some(Page::untitled(stream.body())));
+
+                Page::untitled(
+                    match RequestHandler::request(method, url, body, cancellation, &mut progress) {
+                        Ok(response) => response.body,
+                        Err(e) => e.to_string(),
+                    },
+                )
+            }
             UrlType::Data {
                 mediatype: _,
                 base64: _,
                 data,
             } => Self::lex(data),
+            UrlType::About(page) => Self::lex(&AboutPage::render(page)),
+        }
+    }
+
+    // Renders a response while it is received and passes the output on, but at most once per
+    // PARTIAL_RENDER_INTERVAL. The render function returns None for responses not to be shown.
+    fn partial_renderer<'a>(
+        partial: &'a mut dyn FnMut(Page),
+        render: impl Fn(&ResponseStream) -> Option<Page> + 'a,
+    ) -> impl FnMut(&ResponseStream) + 'a {
+        let mut last_render: Option<Instant> = None;
+
+        move |stream| {
+            if last_render.map_or(false, |last| last.elapsed() < Self::PARTIAL_RENDER_INTERVAL) {
+                return;
+            }
+
+            if let Some(output) = render(stream) {
+                partial(output);
+                last_render = Some(Instant::now());
+            }
+        }
+    }
+
+    // Replaces the body of 4xx and 5xx responses, which often is empty or a bare server default
+    fn error_page(response: &Response) -> String {
+        let explanation = match response.status.class() {
+            StatusClass::ClientError => "The server could not handle the request.",
+            _ => "The server failed to handle the request.",
         };
 
-        Self::build_ui(Self::layout(&output));
-        Ok(())
+        let retry = response
+            .header("Retry-After")
+            .map_or_else(String::new, |retry_after| {
+                format!("<p>Retry after: {}</p>", retry_after)
+            });
+
+        format!(
+            "<html><body><h1>{}</h1><p>{}</p>{}</body></html>",
+            response.status, explanation, retry
+        )
     }
 
-    fn build_ui(display_list: DisplayList) {
+    // Shown if there is no response to display, e.g. because the server could not be reached
+    fn network_error_page(error: &NetworkError) -> String {
+        match error {
+            NetworkError::Certificate(host, _) => format!(
+                "<html><body><h1>Insecure connection</h1><p>{}</p>\
+                 <p>If you trust {}, you can add it to certificate_exceptions in the config file \
+                 to accept its certificate anyway.</p></body></html>",
+                error, host
+            ),
+            _ => format!(
+                "<html><body><h1>Unable to load the page</h1><p>{}</p></body></html>",
+                error
+            ),
+        }
+    }
+
+    fn build_ui(receiver: Receiver<Output>, cancellation: Cancellation) {
         let app = Application::new(
             Some("com.github.cakebaker.rbrowser"),
             ApplicationFlags::default(),
         );
+        // the receiver can only be polled by one window, activate might be called more than once
+        let receiver = Rc::new(RefCell::new(Some(receiver)));
+
         app.connect_activate(move |app| {
-            let display_list = display_list.clone();
+            let display_list = Rc::new(RefCell::new(Self::layout("Loading...")));
             let window = ApplicationWindow::builder()
                 .application(app)
                 .default_width(800)
                 .default_height(600)
-                .title("rbrowser")
+                .title(Self::DEFAULT_TITLE)
                 .build();
 
             let area = DrawingArea::new();
+            let draw_display_list = display_list.clone();
             #[allow(unused_must_use)]
             area.set_draw_func(move |_, ctx, _, _| {
-                for (Position(x, y), ch) in &display_list {
+                for (Position(x, y), ch) in draw_display_list.borrow().iter() {
                     ctx.move_to(*x, *y);
                     ctx.show_text(&ch.to_string());
                 }
             });
             window.set_child(Some(&area));
 
+            let key_controller = EventControllerKey::new();
+            let key_cancellation = cancellation.clone();
+            key_controller.connect_key_pressed(move |_, key, _, _| {
+                if key == gdk::keys::constants::Escape {
+                    key_cancellation.cancel();
+                }
+                gtk::Inhibit(false)
+            });
+            window.add_controller(&key_controller);
+
+            if let Some(receiver) = receiver.borrow_mut().take() {
+                let window = window.clone();
+                glib::timeout_add_local(Self::LOAD_POLL_INTERVAL, move || {
+                    // only the latest output is laid out, older ones are outdated already
+                    let mut latest = None;
+                    let continue_polling = loop {
+                        match receiver.try_recv() {
+                            Ok(Output::Partial(page)) => latest = Some(page),
+                            Ok(Output::Complete(page)) => {
+                                latest = Some(page);
+                                break false;
+                            }
+                            Err(TryRecvError::Empty) => break true,
+                            Err(TryRecvError::Disconnected) => break false,
+                        }
+                    };
+
+                    if let Some(page) = latest {
+                        window
+                            .set_title(Some(page.title.as_deref().unwrap_or(Self::DEFAULT_TITLE)));
+                        *display_list.borrow_mut() = Self::layout(&page.text);
+                        area.queue_draw();
+                    }
+                    glib::Continue(continue_polling)
+                });
+            }
+
             window.show();
         });
 
@@ -84,98 +308,169 @@ impl Browser {
         display_list
     }
 
-    fn lex(s: &str) -> String {
-        let body = Self::get_body(s);
-        let body = Self::remove_tags(body);
-        Self::replace_entities(&body)
-    }
-
-    // Returns either the input string if there is no body tag, or the content between the body
-    // tags. The closing body tag is optional.
-    fn get_body(s: &str) -> &str {
-        let mut start_pos = 0;
-        let mut end_pos = s.len();
-
-        if let Some(pos) = s.find("<body>") {
-            start_pos = pos + "<body>".len();
-
-            if let Some(pos) = s.find("</body>") {
-                end_pos = pos;
-            }
+    // Returns the title and the text of the body, without the content of elements which are not
+    // rendered
+    fn lex(s: &str) -> Page {
+        let document = TreeBuilder::build(s);
+        let text = document
+            .body()
+            .map_or_else(String::new, |body| Self::rendered_text(&document, body));
+
+        Page {
+            title: document.title(),
+            text,
         }
-
-        &s[start_pos..end_pos]
     }
 
-    fn remove_tags(s: &str) -> String {
-        let mut result = String::from("");
-        let mut in_angle = false;
-
-        for c in s.chars() {
-            match c {
-                '<' => in_angle = true,
-                '>' => in_angle = false,
-                _ if !in_angle => result.push(c),
-                _ => {}
+    // The tree is walked with an explicit stack like Document::descendants does, as documents can
+    // be nested deeper than the call stack allows
+    fn rendered_text(document: &Document, id: NodeId) -> String {
+        let mut text = String::new();
+        let mut stack = vec![id];
+
+        while let Some(id) = stack.pop() {
+            match &document.node(id).data {
+                NodeData::Text(s) => text.push_str(s),
+                // the subtrees of hidden elements are skipped
+                NodeData::Element(element) if !Self::is_rendered(element) => {}
+                _ => stack.extend(document.node(id).children.iter().rev()),
             }
         }
 
-        result
+        text
     }
 
-    fn replace_entities(s: &str) -> String {
-        s.replace("&lt;", "<").replace("&gt;", ">")
+    // see https://html.spec.whatwg.org/multipage/rendering.html#hidden-elements
+    fn is_rendered(element: &Element) -> bool {
+        let hidden = match element.name.as_str() {
+            "head" | "title" | "script" | "style" | "template" | "base" | "link" | "meta" => true,
+            "noscript" => Self::SCRIPTING,
+            _ => false,
+        };
+
+        !hidden && element.attribute("hidden").is_none()
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::network_error::CertificateProblem;
+
+    #[test]
+    fn error_page() {
+        let response =
+            Response::new(b"HTTP/1.1 503 Service Unavailable\r\nRetry-After: 120\r\n\r\n").unwrap();
+        assert_eq!(
+            "503 Service UnavailableThe server failed to handle the request.Retry after: 120",
+            Browser::lex(&Browser::error_page(&response)).text
+        );
+    }
 
     #[test]
-    fn get_body() {
-        let result = Browser::get_body("start<body>text</body>end");
-        assert_eq!("text", result);
+    fn network_error_page() {
+        assert_eq!(
+            "Unable to load the pageToo many redirects",
+            Browser::lex(&Browser::network_error_page(
+                &NetworkError::TooManyRedirects
+            ))
+            .text
+        );
     }
 
     #[test]
-    fn get_body_from_empty_string() {
-        assert_eq!("", Browser::get_body(""));
+    fn network_error_page_for_certificate() {
+        let error =
+            NetworkError::Certificate(String::from("example.com"), CertificateProblem::Expired);
+        assert_eq!(
+            "Insecure connectionThe certificate of example.com has expired\
+             If you trust example.com, you can add it to certificate_exceptions in the config \
+             file to accept its certificate anyway.",
+            Browser::lex(&Browser::network_error_page(&error)).text
+        );
     }
 
     #[test]
-    fn get_body_from_string_without_body_tags() {
-        assert_eq!("test", Browser::get_body("test"));
+    fn partial_renderer_renders_at_most_once_per_interval() {
+        let mut stream = ResponseStream::new();
+        stream.receive(b"HTTP/1.1 200 OK\r\n\r\nHello");
+
+        let mut outputs = Vec::new();
+        let mut partial = |output| outputs.push(output);
+        let mut renderer =
+            Browser::partial_renderer(&mut partial, |stream| Some(Page::untitled(stream.body())));
+        renderer(&stream);
+        renderer(&stream);
+        drop(renderer);
+
+        assert_eq!(vec![Page::untitled(String::from("Hello"))], outputs);
+    }
+
+    fn text(s: &str) -> String {
+        Browser::lex(s).text
     }
 
     #[test]
-    fn get_body_from_string_without_closed_body() {
-        let result = Browser::get_body("start<body>text");
-        assert_eq!("text", result);
+    fn lex() {
+        assert_eq!("test", text("<b>test</b>"));
+        assert_eq!("", text(""));
+        assert_eq!("<>", text("&lt;&gt;"));
     }
 
     #[test]
-    fn remove_tags() {
-        assert_eq!("test", Browser::remove_tags("<b>test</b>"));
+    fn lex_only_body() {
+        assert_eq!(
+            "text",
+            text("<html><head><title>T</title></head><body>text</body></html>")
+        );
+        assert_eq!("starttext", text("start<body class=\"a\">text"));
+        assert_eq!("test", text("test"));
     }
 
     #[test]
-    fn remove_tags_from_empty_string() {
-        assert_eq!("", Browser::remove_tags(""));
+    fn lex_less_than_in_text_and_greater_than_in_attributes() {
+        assert_eq!(
+            "a < b, link",
+            text("a < b, <a title=\"x > y\">link</a><!-- <p>comment</p> -->")
+        );
     }
 
     #[test]
-    fn replace_greater_than_entities() {
-        assert_eq!(">", Browser::replace_entities("&gt;"));
+    fn lex_hides_elements_which_are_not_rendered() {
+        assert_eq!(
+            "a b c d",
+            text(
+                "<title>T</title><script>var x = 1 < 2;</script><style>p { color: red }</style>\
+                 a <template><p>t</p></template>b <p hidden>h</p><noscript>c</noscript> \
+                 <div><script>x()</script>d</div>"
+            )
+        );
     }
 
     #[test]
-    fn replace_less_than_entities() {
-        assert_eq!("<", Browser::replace_entities("&lt;"));
+    fn rendered_text_of_deeply_nested_elements() {
+        // built directly, as the parser doesn't nest elements this deep
+        let mut document = Document::new();
+        let body = (0..100_000).fold(Document::ROOT, |parent, _| {
+            document.append(
+                parent,
+                NodeData::Element(Element {
+                    name: String::from("div"),
+                    attributes: Vec::new(),
+                }),
+            )
+        });
+        document.append(body, NodeData::Text(String::from("text")));
+
+        assert_eq!("text", Browser::rendered_text(&document, Document::ROOT));
     }
 
     #[test]
-    fn replace_entities_in_empty_string() {
-        assert_eq!("", Browser::replace_entities(""));
+    fn lex_title() {
+        assert_eq!(
+            Some(String::from("A & B")),
+            Browser::lex("<title>\n  A &amp;\n B </title><p>text").title
+        );
+        assert_eq!(None, Browser::lex("<p>text").title);
     }
 }
@@ -0,0 +1,1074 @@
+use ring::digest;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use tracing::{info, warn};
+
+use crate::config::Config;
+use crate::headers::Headers;
+use crate::http_date::HttpDate;
+use crate::network_error::NetworkError;
+use crate::request::Request;
+use crate::response::{HttpStatus, Response};
+use crate::url::Url;
+
+pub enum Lookup {
+    Fresh(Response),
+    // a stale response with validators, which can be revalidated with a conditional request
+    Stale(CachedResponse),
+    Miss,
+}
+
+pub struct CachedResponse {
+    response: Response,
+    // when the response was received, in seconds since the Unix Epoch
+    response_time: u64,
+    // the values of the request headers listed in the Vary header of the response
+    vary: Headers,
+}
+
+// A stored response as listed on about:cache
+pub struct CacheEntryInfo {
+    pub url: String,
+    pub size: u64,
+    // in seconds since the Unix Epoch
+    pub response_time: u64,
+    pub expires: u64,
+}
+
+impl CachedResponse {
+    // Adds the validators of the stored response to the request, so the server can answer with
+    // 304 Not Modified if the stored response is still valid
+    pub fn add_validators(&self, request: &mut Request) {
+        if let Some(etag) = self.response.header("ETag") {
+            request.header("If-None-Match", etag);
+        }
+
+        if let Some(last_modified) = self.response.header("Last-Modified") {
+            request.header("If-Modified-Since", last_modified);
+        }
+    }
+
+    fn is_fresh(&self, now: u64) -> bool {
+        !CacheControl::parse(self.response.headers()).no_cache
+            && self.freshness_lifetime() > self.current_age(now)
+    }
+
+    // When the response becomes stale, in seconds since the Unix Epoch
+    fn expires(&self) -> u64 {
+        let age_when_received = self.current_age(self.response_time);
+        self.response_time
+            .saturating_add(self.freshness_lifetime().saturating_sub(age_when_received))
+    }
+
+    fn has_validators(&self) -> bool {
+        self.response.header("ETag").is_some() || self.response.header("Last-Modified").is_some()
+    }
+
+    // see https://www.rfc-editor.org/rfc/rfc9111#section-4.2.1
+    fn freshness_lifetime(&self) -> u64 {
+        if let Some(max_age) = CacheControl::parse(self.response.headers()).max_age {
+            return max_age;
+        }
+
+        // an invalid Expires header means the response is already expired
+        match self.response.header("Expires") {
+            Some(expires) => {
+                HttpDate::parse(expires).map_or(0, |expires| expires.saturating_sub(self.date()))
+            }
+            None => 0,
+        }
+    }
+
+    // see https://www.rfc-editor.org/rfc/rfc9111#section-4.2.3
+    fn current_age(&self, now: u64) -> u64 {
+        let apparent_age = self.response_time.saturating_sub(self.date());
+        let age = self
+            .response
+            .header("Age")
+            .and_then(CacheControl::delta_seconds)
+            .unwrap_or(0);
+        let resident_time = now.saturating_sub(self.response_time);
+
+        apparent_age.max(age).saturating_add(resident_time)
+    }
+
+    fn date(&self) -> u64 {
+        self.response
+            .header("Date")
+            .and_then(HttpDate::parse)
+            .unwrap_or(self.response_time)
+    }
+
+    fn matches_vary(&self, request: &Request) -> bool {
+        self.response
+            .headers()
+            .get_all("Vary")
+            .flat_map(|vary| vary.split(','))
+            .map(str::trim)
+            .all(|name| self.vary.get(name) == request.headers().get(name))
+    }
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct CacheControl {
+    no_store: bool,
+    no_cache: bool,
+    max_age: Option<u64>,
+}
+
+impl CacheControl {
+    // larger delta-seconds are treated as this value, see
+    // https://www.rfc-editor.org/rfc/rfc9111#section-1.2.2
+    const MAX_DELTA_SECONDS: u64 = 1 << 31;
+
+    // Combines the directives of all Cache-Control headers. The private directive doesn't
+    // need any handling as this is a private cache, which may store such responses.
+    fn parse(headers: &Headers) -> Self {
+        let mut cache_control = Self::default();
+
+        for directive in headers.get_all("Cache-Control").flat_map(|v| v.split(',')) {
+            let (name, value) = directive.split_once('=').unwrap_or((directive, ""));
+
+            match name.trim().to_ascii_lowercase().as_str() {
+                "no-store" => cache_control.no_store = true,
+                "no-cache" => cache_control.no_cache = true,
+                "max-age" => cache_control.max_age = Self::delta_seconds(value.trim_matches('"')),
+                _ => {}
+            }
+        }
+
+        // Pragma is only considered for HTTP/1.0 servers which don't send Cache-Control
+        if !headers.contains("Cache-Control") && headers.get("Pragma") == Some("no-cache") {
+            cache_control.no_cache = true;
+        }
+
+        cache_control
+    }
+
+    // A number of seconds as used by max-age and the Age header
+    fn delta_seconds(value: &str) -> Option<u64> {
+        if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+
+        // only numbers too large for u64 fail to parse
+        Some(
+            value
+                .parse()
+                .map_or(Self::MAX_DELTA_SECONDS, |seconds: u64| {
+                    seconds.min(Self::MAX_DELTA_SECONDS)
+                }),
+        )
+    }
+}
+
+struct IndexEntry {
+    // the url without fragment
+    key: String,
+    // the SHA-256 of the stored response, which is also the name of the blob file
+    blob: String,
+    size: u64,
+    response_time: u64,
+    last_access: u64,
+    vary: Headers,
+}
+
+impl IndexEntry {
+    // Index entries are stored one per line with tab-separated fields: key, blob, size, response
+    // time, last access, followed by one field per varying request header in the form
+    // "<name>: <value>"
+    fn to_line(&self) -> String {
+        let mut line = format!(
+            "{}\t{}\t{}\t{}\t{}",
+            self.key, self.blob, self.size, self.response_time, self.last_access
+        );
+
+        for (name, value) in self.vary.iter() {
+            line += &format!("\t{}: {}", name, value.replace('\t', " "));
+        }
+
+        line
+    }
+
+    fn from_line(line: &str) -> Option<Self> {
+        let mut fields = line.split('\t');
+        let key = fields.next()?.to_string();
+        let blob = fields.next()?.to_string();
+        let size = fields.next()?.parse().ok()?;
+        let response_time = fields.next()?.parse().ok()?;
+        let last_access = fields.next()?.parse().ok()?;
+
+        let mut vary = Headers::new();
+        for field in fields {
+            let (name, value) = field.split_once(": ")?;
+            vary.append(name, value);
+        }
+
+        Some(Self {
+            key,
+            blob,
+            size,
+            response_time,
+            last_access,
+            vary,
+        })
+    }
+}
+
+// The cache lives in a versioned folder in the user's cache folder. It contains an index, which
+// maps urls to the stored responses, and the responses themselves as content-addressed blobs.
+// If the total size of the blobs exceeds the configured maximum, the least recently used
+// responses are removed.
+pub struct Cache {
+    // None if there is no cache folder, in which case nothing is cached
+    directory: Option<PathBuf>,
+    entries: HashMap<String, IndexEntry>,
+    max_size: u64,
+    // access times are only updated in memory by lookups, and saved with the next change of the
+    // index or by flush
+    index_changed: bool,
+}
+
+impl Cache {
+    const CACHE_DIRECTORY_NAME: &'static str = "rbrowser";
+    const VERSION: &'static str = "v1";
+    const INDEX_FILE_NAME: &'static str = "index";
+    const BLOB_DIRECTORY_NAME: &'static str = "blobs";
+
+    // Status codes which can be cached, see https://www.rfc-editor.org/rfc/rfc9110#section-15.1
+    const CACHEABLE_STATUS_CODES: [u16; 7] = [200, 203, 300, 301, 308, 404, 410];
+
+    // Headers which are not stored: the stored body has neither transfer encoding nor a known
+    // length, and cookies must only be set by responses from the network
+    const UNSTORED_HEADERS: [&'static str; 3] =
+        ["Content-Length", "Set-Cookie", "Transfer-Encoding"];
+
+    pub fn global() -> &'static Mutex<Self> {
+        static CACHE: OnceLock<Mutex<Cache>> = OnceLock::new();
+
+        CACHE.get_or_init(|| {
+            let directory = Self::base_directory().map(|mut dir| {
+                Self::remove_legacy_files(&dir);
+                dir.push(Self::VERSION);
+                dir
+            });
+
+            Mutex::new(Self::open(directory, Config::global().cache_max_size))
+        })
+    }
+
+    // Removes everything rbrowser has cached, including caches in older formats
+    pub fn clear() -> io::Result<()> {
+        match Self::base_directory() {
+            Some(dir) if dir.exists() => fs::remove_dir_all(dir),
+            _ => Ok(()),
+        }
+    }
+
+    fn open(directory: Option<PathBuf>, max_size: u64) -> Self {
+        let entries = directory
+            .as_ref()
+            .and_then(|dir| fs::read_to_string(dir.join(Self::INDEX_FILE_NAME)).ok())
+            .map(|content| {
+                content
+                    .lines()
+                    .filter_map(IndexEntry::from_line)
+                    .map(|entry| (entry.key.clone(), entry))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Self {
+            directory,
+            entries,
+            max_size,
+            index_changed: false,
+        }
+    }
+
+    pub fn lookup(&mut self, request: &Request) -> Lookup {
+        let url = &request.url;
+        let key = Self::key(url);
+
+        info!(%url, "Trying to load from cache");
+
+        let cached_response = self
+            .entries
+            .get(&key)
+            .and_then(|entry| self.load(entry))
+            .filter(|cached_response| cached_response.matches_vary(request));
+
+        let lookup = match cached_response {
+            Some(cached_response) if cached_response.is_fresh(HttpDate::now()) => {
+                info!(%url, "Loaded from cache");
+                Lookup::Fresh(cached_response.response)
+            }
+            Some(cached_response) if cached_response.has_validators() => {
+                info!(%url, "Cached response is stale, revalidating");
+                Lookup::Stale(cached_response)
+            }
+            _ => {
+                info!(%url, "Not cached");
+                Lookup::Miss
+            }
+        };
+
+        if !matches!(lookup, Lookup::Miss) {
+            if let Some(entry) = self.entries.get_mut(&key) {
+                entry.last_access = HttpDate::now();
+                self.index_changed = true;
+            }
+        }
+
+        lookup
+    }
+
+    // Saves the response if it may be stored
+    pub fn store(&mut self, request: &Request, response: &Response) {
+        if Self::is_storable(response) {
+            let blob = Self::message(&response.status, response.headers(), response.raw_body());
+            self.save(request, &blob, Self::vary(request, response));
+        }
+    }
+
+    // Updates the stored response with the headers of a 304 Not Modified response and returns it
+    // see https://www.rfc-editor.org/rfc/rfc9111#section-4.3.4
+    pub fn refresh(
+        &mut self,
+        request: &Request,
+        cached_response: CachedResponse,
+        not_modified: &Response,
+    ) -> Result<Response, NetworkError> {
+        let response = cached_response.response;
+        let headers = Self::updated_headers(response.headers(), not_modified.headers());
+        let blob = Self::message(&response.status, &headers, response.raw_body());
+
+        self.save(request, &blob, cached_response.vary);
+        info!(url = %request.url, "Refreshed cached response");
+
+        Response::new(&blob)
+    }
+
+    // Saves the access times updated by lookups since the index was saved last
+    pub fn flush(&mut self) {
+        if self.index_changed {
+            self.save_index();
+        }
+    }
+
+    // Responses to unsafe requests, like POST, invalidate the stored response
+    pub fn invalidate(&mut self, url: &Url) {
+        if let Some(entry) = self.entries.remove(&Self::key(url)) {
+            self.remove_blob_if_unused(&entry.blob);
+            self.save_index();
+            info!(%url, "Removed response from cache");
+        }
+    }
+
+    // All stored responses, sorted by url. Responses whose blob can't be read are skipped.
+    pub fn list(&self) -> Vec<CacheEntryInfo> {
+        let mut entries: Vec<CacheEntryInfo> = self
+            .entries
+            .values()
+            .filter_map(|entry| {
+                let cached_response = self.load(entry)?;
+
+                Some(CacheEntryInfo {
+                    url: entry.key.clone(),
+                    size: entry.size,
+                    response_time: entry.response_time,
+                    expires: cached_response.expires(),
+                })
+            })
+            .collect();
+        entries.sort_by(|a, b| a.url.cmp(&b.url));
+
+        entries
+    }
+
+    fn load(&self, entry: &IndexEntry) -> Option<CachedResponse> {
+        let blob = fs::read(self.blob_file(&entry.blob)?).ok()?;
+
+        Some(CachedResponse {
+            // a corrupted blob is treated like a missing one
+            response: Response::new(&blob).ok()?,
+            response_time: entry.response_time,
+            vary: entry.vary.clone(),
+        })
+    }
+
+    fn save(&mut self, request: &Request, blob: &[u8], vary: Headers) {
+        let size = blob.len() as u64;
+
+        if size > self.max_size {
+            return;
+        }
+
+        let blob_name = Self::calculate_hash(blob);
+        let blob_file = match self.blob_file(&blob_name) {
+            Some(file) => file,
+            None => return,
+        };
+
+        let result = blob_file
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| fs::write(&blob_file, blob));
+
+        if let Err(e) = result {
+            warn!(file = ?blob_file, error = %e, "Unable to write response to cache");
+            return;
+        }
+
+        let now = HttpDate::now();
+        let key = Self::key(&request.url);
+        let previous = self.entries.insert(
+            key.clone(),
+            IndexEntry {
+                key,
+                blob: blob_name,
+                size,
+                response_time: now,
+                last_access: now,
+                vary,
+            },
+        );
+
+        if let Some(previous) = previous {
+            self.remove_blob_if_unused(&previous.blob);
+        }
+
+        self.evict();
+        self.save_index();
+
+        info!(file = ?blob_file, "Wrote response to cache");
+    }
+
+    // Removes the least recently used responses until the blobs fit into the maximum size
+    fn evict(&mut self) {
+        let mut blob_sizes: HashMap<&str, u64> = HashMap::new();
+        for entry in self.entries.values() {
+            blob_sizes.insert(&entry.blob, entry.size);
+        }
+        let mut total_size: u64 = blob_sizes.values().sum();
+
+        let mut entries: Vec<(u64, String)> = self
+            .entries
+            .values()
+            .map(|entry| (entry.last_access, entry.key.clone()))
+            .collect();
+        entries.sort();
+
+        for (_, key) in entries {
+            if total_size <= self.max_size {
+                break;
+            }
+
+            if let Some(entry) = self.entries.remove(&key) {
+                if self.remove_blob_if_unused(&entry.blob) {
+                    total_size -= entry.size;
+                }
+                info!(url = %entry.key, "Evicted response from cache");
+            }
+        }
+    }
+
+    // Blobs are shared by all entries with the same response, hence a blob is only removed if no
+    // entry uses it anymore. Returns whether the blob was removed.
+    fn remove_blob_if_unused(&self, blob: &str) -> bool {
+        if self.entries.values().any(|entry| entry.blob == blob) {
+            return false;
+        }
+
+        if let Some(file) = self.blob_file(blob) {
+            // the blob might not exist if writing it failed, there is nothing to do then
+            let _ = fs::remove_file(file);
+        }
+
+        true
+    }
+
+    fn save_index(&mut self) {
+        self.index_changed = false;
+
+        let file = match &self.directory {
+            Some(dir) => dir.join(Self::INDEX_FILE_NAME),
+            None => return,
+        };
+
+        let mut entries: Vec<&IndexEntry> = self.entries.values().collect();
+        entries.sort_by(|a, b| a.key.cmp(&b.key));
+        let content: String = entries.iter().map(|entry| entry.to_line() + "\n").collect();
+
+        let result = file
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| fs::write(&file, content));
+
+        if let Err(e) = result {
+            warn!(?file, error = %e, "Unable to write cache index");
+        }
+    }
+
+    // see https://www.rfc-editor.org/rfc/rfc9111#section-3
+    fn is_storable(response: &Response) -> bool {
+        let cache_control = CacheControl::parse(response.headers());
+        let has_explicit_expiration =
+            cache_control.max_age.is_some() || response.header("Expires").is_some();
+        let has_validators =
+            response.header("ETag").is_some() || response.header("Last-Modified").is_some();
+
+        Self::CACHEABLE_STATUS_CODES.contains(&response.status.code)
+            && !cache_control.no_store
+            && !response
+                .headers()
+                .get_all("Vary")
+                .any(|vary| vary.trim() == "*")
+            && (has_explicit_expiration || has_validators)
+    }
+
+    // Headers of the 304 response replace all stored headers with the same name
+    fn updated_headers(stored: &Headers, not_modified: &Headers) -> Headers {
+        let mut headers = stored.clone();
+        let mut updated_names: Vec<&str> = Vec::new();
+
+        for (name, value) in not_modified.iter() {
+            if Self::is_unstored_header(name) {
+                continue;
+            }
+
+            if !updated_names.iter().any(|n| n.eq_ignore_ascii_case(name)) {
+                headers.remove(name);
+                updated_names.push(name);
+            }
+
+            headers.append(name, value);
+        }
+
+        headers
+    }
+
+    fn vary(request: &Request, response: &Response) -> Headers {
+        let mut vary = Headers::new();
+
+        for name in response
+            .headers()
+            .get_all("Vary")
+            .flat_map(|vary| vary.split(','))
+            .map(str::trim)
+        {
+            if let Some(value) = request.headers().get(name) {
+                vary.append(name, value);
+            }
+        }
+
+        vary
+    }
+
+    // The stored form of a response: status line, headers and the body with its content encoding
+    fn message(status: &HttpStatus, headers: &Headers, body: &[u8]) -> Vec<u8> {
+        let mut message = format!("HTTP/1.1 {}\r\n", status);
+
+        for (name, value) in headers.iter() {
+            if !Self::is_unstored_header(name) {
+                message += &format!("{}: {}\r\n", name, value);
+            }
+        }
+
+        let mut message = (message + "\r\n").into_bytes();
+        message.extend_from_slice(body);
+        message
+    }
+
+    fn is_unstored_header(name: &str) -> bool {
+        Self::UNSTORED_HEADERS
+            .iter()
+            .any(|unstored| unstored.eq_ignore_ascii_case(name))
+    }
+
+    // the fragment is not sent to the server, hence it's not part of the key
+    fn key(url: &Url) -> String {
+        let mut url = url.clone();
+        url.fragment = None;
+        url.to_string()
+    }
+
+    fn base_directory() -> Option<PathBuf> {
+        dirs::cache_dir().map(|mut dir| {
+            dir.push(Self::CACHE_DIRECTORY_NAME);
+            dir
+        })
+    }
+
+    fn blob_file(&self, blob: &str) -> Option<PathBuf> {
+        self.directory
+            .as_ref()
+            .map(|dir| dir.join(Self::BLOB_DIRECTORY_NAME).join(blob))
+    }
+
+    // Earlier versions stored each response as "<hash>.txt" directly in the cache folder
+    fn remove_legacy_files(dir: &Path) {
+        if let Ok(files) = fs::read_dir(dir) {
+            for file in files.flatten().map(|entry| entry.path()) {
+                if file
+                    .extension()
+                    .map_or(false, |extension| extension == "txt")
+                {
+                    let _ = fs::remove_file(file);
+                }
+            }
+        }
+    }
+
+    // SHA-256 as hex string, unlike DefaultHasher its output is stable across Rust releases
+    fn calculate_hash(bytes: &[u8]) -> String {
+        digest::digest(&digest::SHA256, bytes)
+            .as_ref()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: u64 = 1_000_000;
+
+    fn cached(response: &[u8], response_time: u64) -> CachedResponse {
+        CachedResponse {
+            response: Response::new(response).unwrap(),
+            response_time,
+            vary: Headers::new(),
+        }
+    }
+
+    fn request(headers: &[(&str, &str)]) -> Request {
+        let mut request = Request::new(Url::new("http://example.com").unwrap());
+        for (name, value) in headers {
+            request.header(name, value);
+        }
+        request
+    }
+
+    #[test]
+    fn cache_control_max_age() {
+        let response =
+            Response::new(b"HTTP/1.1 200 OK\r\nCache-Control: max-age=600\r\n\r\n").unwrap();
+        assert_eq!(Some(600), CacheControl::parse(response.headers()).max_age);
+    }
+
+    #[test]
+    fn cache_control_max_age_with_other_directive() {
+        let response =
+            Response::new(b"HTTP/1.1 200 OK\r\nCache-Control: private, max-age=600\r\n\r\n")
+                .unwrap();
+        assert_eq!(Some(600), CacheControl::parse(response.headers()).max_age);
+    }
+
+    #[test]
+    fn cache_control_with_missing_cache_control_header() {
+        let response = Response::new(b"HTTP/1.1 200 OK\r\n\r\n").unwrap();
+        assert_eq!(
+            CacheControl::default(),
+            CacheControl::parse(response.headers())
+        );
+    }
+
+    #[test]
+    fn cache_control_with_missing_max_age() {
+        let response =
+            Response::new(b"HTTP/1.1 200 OK\r\nCache-Control: no-store\r\n\r\n").unwrap();
+        let cache_control = CacheControl::parse(response.headers());
+        assert_eq!(None, cache_control.max_age);
+        assert!(cache_control.no_store);
+    }
+
+    #[test]
+    fn cache_control_from_multiple_headers() {
+        let response = Response::new(
+            b"HTTP/1.1 200 OK\r\n\
+            Cache-Control: no-cache\r\n\
+            Cache-Control: max-age=60\r\n\r\n",
+        )
+        .unwrap();
+        let cache_control = CacheControl::parse(response.headers());
+        assert!(cache_control.no_cache);
+        assert_eq!(Some(60), cache_control.max_age);
+    }
+
+    #[test]
+    fn cache_control_with_huge_max_age() {
+        let response = Response::new(
+            b"HTTP/1.1 200 OK\r\nCache-Control: max-age=99999999999999999999999\r\n\r\n",
+        )
+        .unwrap();
+        assert_eq!(
+            Some(CacheControl::MAX_DELTA_SECONDS),
+            CacheControl::parse(response.headers()).max_age
+        );
+
+        let response =
+            Response::new(b"HTTP/1.1 200 OK\r\nCache-Control: max-age=-1\r\n\r\n").unwrap();
+        assert_eq!(None, CacheControl::parse(response.headers()).max_age);
+    }
+
+    #[test]
+    fn cache_control_with_pragma() {
+        let response = Response::new(b"HTTP/1.1 200 OK\r\nPragma: no-cache\r\n\r\n").unwrap();
+        assert!(CacheControl::parse(response.headers()).no_cache);
+    }
+
+    #[test]
+    fn is_fresh_with_max_age() {
+        let cached = cached(b"HTTP/1.1 200 OK\r\nCache-Control: max-age=60\r\n\r\n", NOW);
+        assert!(cached.is_fresh(NOW + 59));
+        assert!(!cached.is_fresh(NOW + 60));
+    }
+
+    #[test]
+    fn is_fresh_with_age() {
+        let cached = cached(
+            b"HTTP/1.1 200 OK\r\nCache-Control: max-age=60\r\nAge: 50\r\n\r\n",
+            NOW,
+        );
+        assert!(cached.is_fresh(NOW + 9));
+        assert!(!cached.is_fresh(NOW + 10));
+    }
+
+    #[test]
+    fn is_fresh_with_huge_age() {
+        let cached = cached(
+            b"HTTP/1.1 200 OK\r\nCache-Control: max-age=60\r\nAge: 18446744073709551615\r\n\r\n",
+            NOW,
+        );
+        assert!(!cached.is_fresh(u64::MAX));
+    }
+
+    #[test]
+    fn expires() {
+        let received_now = cached(
+            b"HTTP/1.1 200 OK\r\nCache-Control: max-age=60\r\nAge: 10\r\n\r\n",
+            NOW,
+        );
+        assert_eq!(NOW + 50, received_now.expires());
+
+        let received_late = cached(
+            b"HTTP/1.1 200 OK\r\nCache-Control: max-age=60\r\n\r\n",
+            u64::MAX - 10,
+        );
+        assert_eq!(u64::MAX, received_late.expires());
+    }
+
+    #[test]
+    fn is_fresh_with_expires() {
+        // Date is 1994-11-06 08:49:37, Expires one hour later
+        let cached = cached(
+            b"HTTP/1.1 200 OK\r\n\
+            Date: Sun, 06 Nov 1994 08:49:37 GMT\r\n\
+            Expires: Sun, 06 Nov 1994 09:49:37 GMT\r\n\r\n",
+            784_111_777,
+        );
+        assert!(cached.is_fresh(784_111_777 + 3599));
+        assert!(!cached.is_fresh(784_111_777 + 3600));
+    }
+
+    #[test]
+    fn is_fresh_with_invalid_expires() {
+        let cached = cached(b"HTTP/1.1 200 OK\r\nExpires: 0\r\n\r\n", NOW);
+        assert!(!cached.is_fresh(NOW));
+    }
+
+    #[test]
+    fn is_fresh_with_no_cache() {
+        let cached = cached(
+            b"HTTP/1.1 200 OK\r\nCache-Control: no-cache, max-age=60\r\n\r\n",
+            NOW,
+        );
+        assert!(!cached.is_fresh(NOW));
+    }
+
+    #[test]
+    fn is_storable() {
+        let storable = [
+            &b"HTTP/1.1 200 OK\r\nCache-Control: max-age=60\r\n\r\n"[..],
+            b"HTTP/1.1 200 OK\r\nCache-Control: private, max-age=60\r\n\r\n",
+            b"HTTP/1.1 200 OK\r\nExpires: Sun, 06 Nov 1994 08:49:37 GMT\r\n\r\n",
+            b"HTTP/1.1 200 OK\r\nETag: \"abc\"\r\n\r\n",
+            b"HTTP/1.1 301 Moved Permanently\r\nCache-Control: max-age=60\r\n\r\n",
+        ];
+        for response in storable {
+            assert!(Cache::is_storable(&Response::new(response).unwrap()));
+        }
+
+        let not_storable = [
+            &b"HTTP/1.1 200 OK\r\n\r\n"[..],
+            b"HTTP/1.1 200 OK\r\nCache-Control: no-store, max-age=60\r\n\r\n",
+            b"HTTP/1.1 200 OK\r\nCache-Control: max-age=60\r\nVary: *\r\n\r\n",
+            b"HTTP/1.1 500 Internal Server Error\r\nCache-Control: max-age=60\r\n\r\n",
+        ];
+        for response in not_storable {
+            assert!(!Cache::is_storable(&Response::new(response).unwrap()));
+        }
+    }
+
+    #[test]
+    fn add_validators() {
+        let cached = cached(
+            b"HTTP/1.1 200 OK\r\n\
+            ETag: \"abc\"\r\n\
+            Last-Modified: Sun, 06 Nov 1994 08:49:37 GMT\r\n\r\n",
+            NOW,
+        );
+        let mut request = request(&[]);
+        cached.add_validators(&mut request);
+        assert_eq!(Some("\"abc\""), request.headers().get("If-None-Match"));
+        assert_eq!(
+            Some("Sun, 06 Nov 1994 08:49:37 GMT"),
+            request.headers().get("If-Modified-Since")
+        );
+    }
+
+    #[test]
+    fn matches_vary() {
+        let response =
+            Response::new(b"HTTP/1.1 200 OK\r\nVary: Accept-Encoding, Cookie\r\n\r\n").unwrap();
+        let stored_request = request(&[("Accept-Encoding", "gzip"), ("Cookie", "a=1")]);
+        let cached = CachedResponse {
+            vary: Cache::vary(&stored_request, &response),
+            response,
+            response_time: NOW,
+        };
+
+        assert!(cached.matches_vary(&stored_request));
+        assert!(!cached.matches_vary(&request(&[("Accept-Encoding", "gzip")])));
+        assert!(!cached.matches_vary(&request(&[("Accept-Encoding", "gzip"), ("Cookie", "a=2")])));
+    }
+
+    #[test]
+    fn updated_headers() {
+        let stored = Response::new(
+            b"HTTP/1.1 200 OK\r\n\
+            Cache-Control: max-age=60\r\n\
+            ETag: \"abc\"\r\n\
+            Link: <a>\r\n\r\n",
+        )
+        .unwrap();
+        let not_modified = Response::new(
+            b"HTTP/1.1 304 Not Modified\r\n\
+            Cache-Control: max-age=120\r\n\
+            Content-Length: 0\r\n\
+            Link: <b>\r\n\
+            Link: <c>\r\n\r\n",
+        )
+        .unwrap();
+        let headers = Cache::updated_headers(stored.headers(), not_modified.headers());
+        assert_eq!(
+            vec![
+                ("ETag", "\"abc\""),
+                ("Cache-Control", "max-age=120"),
+                ("Link", "<b>"),
+                ("Link", "<c>")
+            ],
+            headers.iter().collect::<Vec<(&str, &str)>>()
+        );
+    }
+
+    fn temp_cache(name: &str, max_size: u64) -> Cache {
+        let dir =
+            std::env::temp_dir().join(format!("rbrowser-cache-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        Cache::open(Some(dir), max_size)
+    }
+
+    fn request_for(url: &str) -> Request {
+        Request::new(Url::new(url).unwrap())
+    }
+
+    #[test]
+    fn message_without_unstored_headers() {
+        let response = Response::new(
+            b"HTTP/1.1 200 OK\r\n\
+            Content-Length: 4\r\n\
+            Set-Cookie: a=1\r\n\
+            ETag: \"abc\"\r\n\r\nBody",
+        )
+        .unwrap();
+        let message = Cache::message(&response.status, response.headers(), response.raw_body());
+        assert_eq!(
+            b"HTTP/1.1 200 OK\r\nETag: \"abc\"\r\n\r\nBody".to_vec(),
+            message
+        );
+    }
+
+    #[test]
+    fn index_entry_to_line_and_from_line() {
+        let mut vary = Headers::new();
+        vary.append("Accept-Encoding", "gzip");
+        let entry = IndexEntry {
+            key: String::from("http://example.com:80/"),
+            blob: String::from("abc"),
+            size: 10,
+            response_time: NOW,
+            last_access: NOW + 1,
+            vary,
+        };
+
+        let line = entry.to_line();
+        assert_eq!(
+            "http://example.com:80/\tabc\t10\t1000000\t1000001\tAccept-Encoding: gzip",
+            line
+        );
+
+        let parsed = IndexEntry::from_line(&line).unwrap();
+        assert_eq!(entry.key, parsed.key);
+        assert_eq!(entry.blob, parsed.blob);
+        assert_eq!(entry.size, parsed.size);
+        assert_eq!(entry.response_time, parsed.response_time);
+        assert_eq!(entry.last_access, parsed.last_access);
+        assert_eq!(entry.vary, parsed.vary);
+    }
+
+    #[test]
+    fn index_entry_from_invalid_line() {
+        assert!(IndexEntry::from_line("http://example.com:80/\tabc\tten\t1\t1").is_none());
+        assert!(IndexEntry::from_line("http://example.com:80/").is_none());
+    }
+
+    #[test]
+    fn calculate_hash() {
+        assert_eq!(
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+            Cache::calculate_hash(b"")
+        );
+    }
+
+    #[test]
+    fn store_and_lookup() {
+        let mut cache = temp_cache("store", 1024);
+        let request = request_for("http://example.com/#top");
+        let response =
+            Response::new(b"HTTP/1.1 200 OK\r\nCache-Control: max-age=60\r\n\r\nBody").unwrap();
+        cache.store(&request, &response);
+
+        match cache.lookup(&request_for("http://example.com/")) {
+            Lookup::Fresh(response) => assert_eq!("Body", response.body),
+            _ => panic!("expected a fresh response"),
+        }
+
+        // the index is persisted
+        let directory = cache.directory.clone();
+        let mut cache = Cache::open(directory.clone(), 1024);
+        assert!(matches!(cache.lookup(&request), Lookup::Fresh(_)));
+
+        cache.invalidate(&request.url);
+        assert!(matches!(cache.lookup(&request), Lookup::Miss));
+        let _ = fs::remove_dir_all(directory.unwrap());
+    }
+
+    #[test]
+    fn store_shares_blobs() {
+        let mut cache = temp_cache("share", 1024);
+        let response =
+            Response::new(b"HTTP/1.1 200 OK\r\nCache-Control: max-age=60\r\n\r\nBody").unwrap();
+        cache.store(&request_for("http://example.com/a"), &response);
+        cache.store(&request_for("http://example.com/b"), &response);
+
+        let blob = cache.entries.values().next().unwrap().blob.clone();
+        cache.invalidate(&Url::new("http://example.com/a").unwrap());
+        assert!(cache.blob_file(&blob).unwrap().exists());
+
+        cache.invalidate(&Url::new("http://example.com/b").unwrap());
+        assert!(!cache.blob_file(&blob).unwrap().exists());
+        let _ = fs::remove_dir_all(cache.directory.unwrap());
+    }
+
+    #[test]
+    fn list() {
+        let mut cache = temp_cache("list", 1024);
+        let response =
+            Response::new(b"HTTP/1.1 200 OK\r\nCache-Control: max-age=60\r\n\r\nBody").unwrap();
+        cache.store(&request_for("http://example.com/b"), &response);
+        cache.store(&request_for("http://example.com/a"), &response);
+
+        let entries = cache.list();
+        assert_eq!(
+            vec!["http://example.com:80/a", "http://example.com:80/b"],
+            entries
+                .iter()
+                .map(|e| e.url.as_str())
+                .collect::<Vec<&str>>()
+        );
+        assert_eq!(entries[0].response_time + 60, entries[0].expires);
+        let _ = fs::remove_dir_all(cache.directory.unwrap());
+    }
+
+    #[test]
+    fn store_evicts_least_recently_used() {
+        let response_a =
+            Response::new(b"HTTP/1.1 200 OK\r\nCache-Control: max-age=60\r\n\r\nA").unwrap();
+        let response_b =
+            Response::new(b"HTTP/1.1 200 OK\r\nCache-Control: max-age=60\r\n\r\nB").unwrap();
+        let size = Cache::message(
+            &response_a.status,
+            response_a.headers(),
+            response_a.raw_body(),
+        )
+        .len() as u64;
+
+        let mut cache = temp_cache("evict", size);
+        let request_a = request_for("http://example.com/a");
+        let request_b = request_for("http://example.com/b");
+        cache.store(&request_a, &response_a);
+        cache
+            .entries
+            .get_mut(&Cache::key(&request_a.url))
+            .unwrap()
+            .last_access = 0;
+        cache.store(&request_b, &response_b);
+
+        assert!(matches!(cache.lookup(&request_a), Lookup::Miss));
+        assert!(matches!(cache.lookup(&request_b), Lookup::Fresh(_)));
+        let _ = fs::remove_dir_all(cache.directory.unwrap());
+    }
+
+    #[test]
+    fn lookup_saves_access_time_on_flush() {
+        let mut cache = temp_cache("flush", 1024);
+        let request = request_for("http://example.com/");
+        let response =
+            Response::new(b"HTTP/1.1 200 OK\r\nCache-Control: max-age=60\r\n\r\nBody").unwrap();
+        cache.store(&request, &response);
+        cache
+            .entries
+            .get_mut(&Cache::key(&request.url))
+            .unwrap()
+            .last_access = 0;
+        cache.save_index();
+
+        let directory = cache.directory.clone();
+        let last_access =
+            || Cache::open(directory.clone(), 1024).entries[&Cache::key(&request.url)].last_access;
+
+        assert!(matches!(cache.lookup(&request), Lookup::Fresh(_)));
+        assert_eq!(0, last_access());
+
+        cache.flush();
+        assert!(last_access() > 0);
+        let _ = fs::remove_dir_all(cache.directory.unwrap());
+    }
+
+    #[test]
+    fn store_too_large_response() {
+        let mut cache = temp_cache("large", 10);
+        let request = request_for("http://example.com/");
+        let response =
+            Response::new(b"HTTP/1.1 200 OK\r\nCache-Control: max-age=60\r\n\r\nBody").unwrap();
+        cache.store(&request, &response);
+        assert!(matches!(cache.lookup(&request), Lookup::Miss));
+    }
+}
@@ -0,0 +1,38 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+// A handle to cancel a running request from another thread, e.g. when the user presses Escape.
+// All clones share the same state.
+#[derive(Clone, Debug, Default)]
+pub struct Cancellation {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl Cancellation {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cancel() {
+        let cancellation = Cancellation::new();
+        let clone = cancellation.clone();
+        assert!(!clone.is_cancelled());
+
+        cancellation.cancel();
+        assert!(clone.is_cancelled());
+    }
+}
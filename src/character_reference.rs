@@ -0,0 +1,175 @@
+use crate::named_character_references::NAMED_CHARACTER_REFERENCES;
+
+// Resolves character references like &amp; and &#x41; in HTML, see
+// https://html.spec.whatwg.org/multipage/syntax.html#character-references, and escapes text for
+// the pages the browser generates itself
+pub struct CharacterReference {}
+
+impl CharacterReference {
+    // the longest name in the table, "CounterClockwiseContourIntegral;"
+    const MAX_NAME_LENGTH: usize = 32;
+
+    // Replacements for references to C1 control characters, which pages use for the characters
+    // windows-1252 has at their positions
+    const C1_REPLACEMENTS: [(u32, char); 27] = [
+        (0x80, '\u{20ac}'),
+        (0x82, '\u{201a}'),
+        (0x83, '\u{192}'),
+        (0x84, '\u{201e}'),
+        (0x85, '\u{2026}'),
+        (0x86, '\u{2020}'),
+        (0x87, '\u{2021}'),
+        (0x88, '\u{2c6}'),
+        (0x89, '\u{2030}'),
+        (0x8a, '\u{160}'),
+        (0x8b, '\u{2039}'),
+        (0x8c, '\u{152}'),
+        (0x8e, '\u{17d}'),
+        (0x91, '\u{2018}'),
+        (0x92, '\u{2019}'),
+        (0x93, '\u{201c}'),
+        (0x94, '\u{201d}'),
+        (0x95, '\u{2022}'),
+        (0x96, '\u{2013}'),
+        (0x97, '\u{2014}'),
+        (0x98, '\u{2dc}'),
+        (0x99, '\u{2122}'),
+        (0x9a, '\u{161}'),
+        (0x9b, '\u{203a}'),
+        (0x9c, '\u{153}'),
+        (0x9e, '\u{17e}'),
+        (0x9f, '\u{178}'),
+    ];
+
+    // Returns the longest name the input starts with, as the number of characters of the name,
+    // and its replacement. Names without a semicolon are only known for legacy references like
+    // "&amp", whereas "&hellip" is not a reference.
+    pub fn named(input: &[char]) -> Option<(usize, &'static str)> {
+        // all names are ASCII, hence a match is as many characters long as it has bytes
+        let candidate: String = input
+            .iter()
+            .take(Self::MAX_NAME_LENGTH)
+            .take_while(|c| c.is_ascii())
+            .collect();
+
+        (1..=candidate.len()).rev().find_map(|length| {
+            NAMED_CHARACTER_REFERENCES
+                .binary_search_by(|(name, _)| name.cmp(&&candidate[..length]))
+                .ok()
+                .map(|index| (length, NAMED_CHARACTER_REFERENCES[index].1))
+        })
+    }
+
+    // Code points which are not valid characters are replaced with U+FFFD, C1 controls with the
+    // windows-1252 characters they were meant to be. Noncharacters and other controls are errors,
+    // but are kept.
+    pub fn numeric(code: u32) -> char {
+        let replacement = Self::C1_REPLACEMENTS
+            .iter()
+            .find(|(c1, _)| *c1 == code)
+            .map(|(_, replacement)| *replacement);
+
+        match code {
+            0 => '\u{fffd}',
+            // surrogates and code points beyond U+10FFFF are no characters
+            code => replacement.unwrap_or_else(|| char::from_u32(code).unwrap_or('\u{fffd}')),
+        }
+    }
+
+    // Replaces the characters which have a meaning in text and attribute values
+    pub fn escape(s: &str) -> String {
+        s.replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+            .replace('"', "&quot;")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chars(s: &str) -> Vec<char> {
+        s.chars().collect()
+    }
+
+    #[test]
+    fn table_is_sorted() {
+        assert!(NAMED_CHARACTER_REFERENCES
+            .windows(2)
+            .all(|pair| pair[0].0 < pair[1].0));
+    }
+
+    #[test]
+    fn escape() {
+        assert_eq!(
+            "&lt;a href=&quot;?a&amp;b&quot;&gt;",
+            CharacterReference::escape("<a href=\"?a&b\">")
+        );
+    }
+
+    #[test]
+    fn named() {
+        assert_eq!(
+            Some((4, "&")),
+            CharacterReference::named(&chars("amp;rest"))
+        );
+        assert_eq!(None, CharacterReference::named(&chars("unknown;")));
+        assert_eq!(
+            Some((6, "\u{2014}")),
+            CharacterReference::named(&chars("mdash;"))
+        );
+        assert_eq!(
+            Some((4, "\u{1d504}")),
+            CharacterReference::named(&chars("Afr;"))
+        );
+        assert_eq!(
+            Some((6, "\u{2242}\u{338}")),
+            CharacterReference::named(&chars("nesim;"))
+        );
+        assert_eq!(None, CharacterReference::named(&chars("\u{e4}")));
+    }
+
+    #[test]
+    fn named_legacy_without_semicolon() {
+        assert_eq!(Some((3, "&")), CharacterReference::named(&chars("amp")));
+        assert_eq!(
+            Some((4, "\u{a9}")),
+            CharacterReference::named(&chars("copy "))
+        );
+        // the longest match wins, "not" is a legacy name, "notit;" is no name
+        assert_eq!(
+            Some((3, "\u{ac}")),
+            CharacterReference::named(&chars("notit;"))
+        );
+        assert_eq!(
+            Some((6, "\u{2209}")),
+            CharacterReference::named(&chars("notin;"))
+        );
+        assert_eq!(None, CharacterReference::named(&chars("hellip")));
+    }
+
+    #[test]
+    fn numeric() {
+        assert_eq!('A', CharacterReference::numeric(0x41));
+        assert_eq!('\u{1f600}', CharacterReference::numeric(0x1f600));
+        assert_eq!('\u{fffd}', CharacterReference::numeric(0));
+        assert_eq!('\u{fffd}', CharacterReference::numeric(0xd800));
+        assert_eq!('\u{fffd}', CharacterReference::numeric(0x11_0000));
+        assert_eq!('\u{fffd}', CharacterReference::numeric(u32::MAX));
+    }
+
+    #[test]
+    fn numeric_c1_controls() {
+        assert_eq!('\u{20ac}', CharacterReference::numeric(0x80));
+        assert_eq!('\u{2014}', CharacterReference::numeric(0x97));
+        // no replacement defined
+        assert_eq!('\u{81}', CharacterReference::numeric(0x81));
+    }
+
+    #[test]
+    fn numeric_noncharacters_are_kept() {
+        assert_eq!('\u{fdd0}', CharacterReference::numeric(0xfdd0));
+        assert_eq!('\u{ffff}', CharacterReference::numeric(0xffff));
+    }
+}
@@ -0,0 +1,288 @@
+use encoding::all::{UTF_16BE, UTF_16LE, UTF_8, WINDOWS_1252};
+use encoding::label::encoding_from_whatwg_label;
+use encoding::{DecoderTrap, EncodingRef};
+
+use crate::headers::Headers;
+
+// Determines the character encoding of a body and decodes it, loosely following
+// https://html.spec.whatwg.org/multipage/parsing.html#determining-the-character-encoding
+//
+// A byte order mark takes precedence over the charset parameter of the Content-Type header, which
+// in turn takes precedence over a <meta> tag at the beginning of the document. Without any of
+// them the body is decoded as UTF-8 if it's valid UTF-8, and as windows-1252 otherwise.
+pub struct Charset {}
+
+impl Charset {
+    // only the beginning of a document is searched for <meta> tags
+    const PRESCAN_LENGTH: usize = 1024;
+
+    pub fn decode(body: &[u8], headers: &Headers) -> String {
+        Self::decode_body(body, headers, false)
+    }
+
+    // Like decode, but for the beginning of a body which is still being received. A UTF-8
+    // sequence at its end might be incomplete, it is left out until the rest has arrived.
+    pub fn decode_partial(body: &[u8], headers: &Headers) -> String {
+        Self::decode_body(body, headers, true)
+    }
+
+    fn decode_body(body: &[u8], headers: &Headers, partial: bool) -> String {
+        let (encoding, bom_length) = Self::detect(body, headers, partial);
+        let mut body = &body[bom_length..];
+        if partial && encoding.name() == "utf-8" {
+            body = &body[..Self::complete_utf8_length(body)];
+        }
+
+        // malformed sequences are replaced with U+FFFD like browsers do, hence decoding can't fail
+        encoding
+            .decode(body, DecoderTrap::Replace)
+            .unwrap_or_else(std::borrow::Cow::into_owned)
+    }
+
+    // Returns the encoding and the length of the byte order mark
+    fn detect(body: &[u8], headers: &Headers, partial: bool) -> (EncodingRef, usize) {
+        if let Some(bom) = Self::from_bom(body) {
+            return bom;
+        }
+
+        let encoding = headers
+            .get("Content-Type")
+            .and_then(Self::from_content_type)
+            .or_else(|| Self::prescan(&body[..body.len().min(Self::PRESCAN_LENGTH)]))
+            .unwrap_or_else(|| {
+                let length = if partial {
+                    Self::complete_utf8_length(body)
+                } else {
+                    body.len()
+                };
+
+                if std::str::from_utf8(&body[..length]).is_ok() {
+                    UTF_8
+                } else {
+                    WINDOWS_1252
+                }
+            });
+
+        (encoding, 0)
+    }
+
+    // The length of the body without an incomplete UTF-8 sequence at its end
+    fn complete_utf8_length(body: &[u8]) -> usize {
+        match std::str::from_utf8(body) {
+            Err(e) if e.error_len().is_none() => e.valid_up_to(),
+            _ => body.len(),
+        }
+    }
+
+    fn from_bom(body: &[u8]) -> Option<(EncodingRef, usize)> {
+        if body.starts_with(b"\xef\xbb\xbf") {
+            Some((UTF_8, 3))
+        } else if body.starts_with(b"\xfe\xff") {
+            Some((UTF_16BE, 2))
+        } else if body.starts_with(b"\xff\xfe") {
+            Some((UTF_16LE, 2))
+        } else {
+            None
+        }
+    }
+
+    // e.g. "text/html; charset=Shift_JIS"
+    fn from_content_type(content_type: &str) -> Option<EncodingRef> {
+        content_type
+            .split(';')
+            .skip(1)
+            .filter_map(|parameter| parameter.split_once('='))
+            .find(|(name, _)| name.trim().eq_ignore_ascii_case("charset"))
+            .and_then(|(_, value)| encoding_from_whatwg_label(value.trim().trim_matches('"')))
+    }
+
+    // Looks for <meta charset="..."> or <meta http-equiv="Content-Type" content="...; charset=...">
+    fn prescan(bytes: &[u8]) -> Option<EncodingRef> {
+        // the markup before the charset is ASCII in all supported encodings except UTF-16, which
+        // is only detected by its byte order mark
+        let document = String::from_utf8_lossy(bytes).to_ascii_lowercase();
+        let mut rest = document.as_str();
+
+        while let Some(start) = rest.find("<meta") {
+            let tag = &rest[(start + "<meta".len())..];
+            let end = tag.find('>').unwrap_or(tag.len());
+            let attributes = Self::parse_attributes(&tag[..end]);
+            rest = &tag[end..];
+
+            let attribute = |name: &str| {
+                attributes
+                    .iter()
+                    .find(|(n, _)| n == name)
+                    .map(|(_, v)| v.as_str())
+            };
+
+            let label = match (
+                attribute("charset"),
+                attribute("http-equiv"),
+                attribute("content"),
+            ) {
+                (Some(charset), _, _) => Some(charset),
+                (None, Some("content-type"), Some(content)) => content
+                    .split_once("charset=")
+                    .map(|(_, charset)| charset.split(';').next().unwrap_or_default()),
+                _ => None,
+            };
+
+            if let Some(encoding) = label.and_then(|label| {
+                encoding_from_whatwg_label(label.trim().trim_matches(|c| c == '"' || c == '\''))
+            }) {
+                // a document which could be read this far is not UTF-16
+                return Some(match encoding.whatwg_name() {
+                    Some("utf-16be" | "utf-16le") => UTF_8,
+                    _ => encoding,
+                });
+            }
+        }
+
+        None
+    }
+
+    // Parses attributes in the forms name="value", name='value', name=value and name
+    fn parse_attributes(s: &str) -> Vec<(String, String)> {
+        let mut attributes = Vec::new();
+        let mut chars = s.chars().peekable();
+
+        loop {
+            while chars.next_if(|c| c.is_whitespace() || *c == '/').is_some() {}
+
+            let name: String = std::iter::from_fn(|| {
+                chars.next_if(|c| !c.is_whitespace() && *c != '=' && *c != '/')
+            })
+            .collect();
+
+            if name.is_empty() {
+                break;
+            }
+
+            while chars.next_if(|c| c.is_whitespace()).is_some() {}
+
+            let value = if chars.next_if_eq(&'=').is_some() {
+                while chars.next_if(|c| c.is_whitespace()).is_some() {}
+
+                match chars.next_if(|c| *c == '"' || *c == '\'') {
+                    Some(quote) => {
+                        let value = std::iter::from_fn(|| chars.next_if(|c| *c != quote)).collect();
+                        chars.next();
+                        value
+                    }
+                    None => std::iter::from_fn(|| chars.next_if(|c| !c.is_whitespace())).collect(),
+                }
+            } else {
+                String::new()
+            };
+
+            attributes.push((name, value));
+        }
+
+        attributes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(content_type: &str) -> Headers {
+        let mut headers = Headers::new();
+        headers.append("Content-Type", content_type);
+        headers
+    }
+
+    // "日本" in Shift_JIS
+    const SHIFT_JIS_NIHON: &[u8] = b"\x93\xfa\x96\x7b";
+
+    #[test]
+    fn decode_with_content_type_charset() {
+        let body = Charset::decode(SHIFT_JIS_NIHON, &headers("text/html; charset=Shift_JIS"));
+        assert_eq!("日本", body);
+
+        let body = Charset::decode(b"\xb0\xa1", &headers("text/html;charset=\"EUC-KR\""));
+        assert_eq!("가", body);
+    }
+
+    #[test]
+    fn decode_with_bom() {
+        assert_eq!("a", Charset::decode(b"\xef\xbb\xbfa", &Headers::new()));
+        assert_eq!("a", Charset::decode(b"\xff\xfea\x00", &Headers::new()));
+        assert_eq!("a", Charset::decode(b"\xfe\xff\x00a", &Headers::new()));
+    }
+
+    #[test]
+    fn decode_with_bom_overriding_content_type() {
+        let body = Charset::decode(b"\xef\xbb\xbf\xc3\xa9", &headers("text/html; charset=GBK"));
+        assert_eq!("é", body);
+    }
+
+    #[test]
+    fn decode_with_meta_charset() {
+        let mut body = b"<html><head><meta charset=\"shift_jis\"></head><body>".to_vec();
+        body.extend_from_slice(SHIFT_JIS_NIHON);
+        assert!(Charset::decode(&body, &headers("text/html")).ends_with("日本"));
+    }
+
+    #[test]
+    fn decode_with_meta_http_equiv() {
+        let mut body =
+            b"<meta http-equiv='Content-Type' content='text/html; charset=gbk'>".to_vec();
+        body.extend_from_slice(b"\xc4\xe3\xba\xc3");
+        assert!(Charset::decode(&body, &Headers::new()).ends_with("你好"));
+    }
+
+    #[test]
+    fn decode_with_meta_utf_16() {
+        let body = "<meta charset=utf-16>é".as_bytes();
+        assert_eq!(
+            "<meta charset=utf-16>é",
+            Charset::decode(body, &Headers::new())
+        );
+    }
+
+    #[test]
+    fn decode_without_charset() {
+        assert_eq!("é", Charset::decode("é".as_bytes(), &Headers::new()));
+        assert_eq!("é€", Charset::decode(b"\xe9\x80", &Headers::new()));
+    }
+
+    #[test]
+    fn decode_partial() {
+        // "日本" without the last byte of "本"
+        let body = &"日本".as_bytes()[..5];
+        assert_eq!("日", Charset::decode_partial(body, &Headers::new()));
+        assert_eq!(
+            "日",
+            Charset::decode_partial(body, &headers("text/html; charset=utf-8"))
+        );
+        assert_eq!(
+            "\u{e6}\u{2014}\u{a5}\u{e6}\u{153}",
+            Charset::decode(body, &Headers::new())
+        );
+
+        // invalid UTF-8 is still decoded as windows-1252
+        assert_eq!("ét", Charset::decode_partial(b"\xe9t", &Headers::new()));
+    }
+
+    #[test]
+    fn decode_with_unknown_charset() {
+        let body = Charset::decode(b"abc", &headers("text/html; charset=unknown"));
+        assert_eq!("abc", body);
+    }
+
+    #[test]
+    fn parse_attributes() {
+        let attributes = Charset::parse_attributes(" a=\"1\" b = '2' c=3 d/");
+        assert_eq!(
+            vec![
+                (String::from("a"), String::from("1")),
+                (String::from("b"), String::from("2")),
+                (String::from("c"), String::from("3")),
+                (String::from("d"), String::new()),
+            ],
+            attributes
+        );
+    }
+}
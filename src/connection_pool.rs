@@ -0,0 +1,892 @@
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use tracing::info;
+
+use crate::body_decoder::Dechunker;
+use crate::cancellation::Cancellation;
+use crate::config::{Config, SizeLimits};
+use crate::http2::Http2Connection;
+use crate::network_error::{NetworkError, SizeLimit, TimeoutPhase};
+use crate::proxy::{Proxy, ProxyKind, ProxySettings};
+use crate::request::Method;
+use crate::tls::Tls;
+use crate::url::{Scheme, Url};
+
+// Connections are shared per origin, i.e. per (scheme, host, port)
+type PoolKey = (Scheme, String, u16);
+
+// A TCP stream which fails reads and writes once a deadline has passed or the request has been
+// cancelled. The socket itself only blocks for a short interval, so deadlines and cancellation
+// are noticed even if the server doesn't send anything.
+struct TimedStream {
+    stream: TcpStream,
+    cancellation: Cancellation,
+    deadlines: Vec<(Instant, TimeoutPhase)>,
+}
+
+impl TimedStream {
+    const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+    fn new(stream: TcpStream, cancellation: Cancellation) -> io::Result<Self> {
+        stream.set_read_timeout(Some(Self::POLL_INTERVAL))?;
+        stream.set_write_timeout(Some(Self::POLL_INTERVAL))?;
+
+        Ok(Self {
+            stream,
+            cancellation,
+            deadlines: Vec::new(),
+        })
+    }
+
+    // Runs the operation until it doesn't time out anymore, the errors for deadlines and
+    // cancellation are turned into NetworkErrors by NetworkError::from
+    fn retry<T>(
+        &mut self,
+        mut operation: impl FnMut(&mut TcpStream) -> io::Result<T>,
+    ) -> io::Result<T> {
+        loop {
+            if self.cancellation.is_cancelled() {
+                return Err(io::Error::other(NetworkError::Cancelled));
+            }
+
+            let now = Instant::now();
+            if let Some((_, phase)) = self.deadlines.iter().find(|(deadline, _)| now >= *deadline) {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    NetworkError::Timeout(*phase),
+                ));
+            }
+
+            match operation(&mut self.stream) {
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                    ) => {}
+                result => return result,
+            }
+        }
+    }
+}
+
+impl Read for TimedStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.retry(|stream| stream.read(buf))?;
+
+        if n > 0 {
+            self.deadlines
+                .retain(|(_, phase)| *phase != TimeoutPhase::FirstByte);
+        }
+
+        Ok(n)
+    }
+}
+
+impl Write for TimedStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.retry(|stream| stream.write(buf))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.retry(TcpStream::flush)
+    }
+}
+
+enum Stream {
+    Plain(TimedStream),
+    Tls(Box<rustls::StreamOwned<rustls::ClientConnection, TimedStream>>),
+}
+
+impl Stream {
+    fn timed_stream(&mut self) -> &mut TimedStream {
+        match self {
+            Self::Plain(stream) => stream,
+            Self::Tls(stream) => &mut stream.sock,
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::Plain(stream) => stream.read(buf),
+            Self::Tls(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Plain(stream) => stream.write(buf),
+            Self::Tls(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Plain(stream) => stream.flush(),
+            Self::Tls(stream) => stream.flush(),
+        }
+    }
+}
+
+pub struct Connection {
+    key: PoolKey,
+    reader: BufReader<Stream>,
+    last_used: Instant,
+    // the HTTP proxy plain HTTP requests are sent to, if any
+    forward_proxy: Option<Proxy>,
+}
+
+impl Connection {
+    pub fn open(url: &Url, cancellation: &Cancellation) -> Result<Self, NetworkError> {
+        let config = Config::global();
+        let proxy = ProxySettings::global().proxy_for(url);
+
+        let (host, socket_address) = match proxy {
+            Some(proxy) => (&proxy.host, proxy.socket_address()),
+            None => (&url.host, url.socket_address()),
+        };
+        let addresses: Vec<SocketAddr> = socket_address
+            .to_socket_addrs()
+            .map_err(|e| NetworkError::Dns(host.clone(), e))?
+            .collect();
+        let stream = Self::connect(&addresses, config.connect_timeout, cancellation)?;
+        let mut stream = TimedStream::new(stream, cancellation.clone())?;
+
+        // plain HTTP requests are sent directly to HTTP proxies, everything else is tunnelled
+        let forward_proxy = match proxy {
+            Some(proxy) if proxy.kind == ProxyKind::Http && url.scheme == Scheme::Http => {
+                Some(proxy.clone())
+            }
+            Some(proxy) => {
+                stream.deadlines = Self::deadline(
+                    Instant::now(),
+                    config.connect_timeout,
+                    TimeoutPhase::Connect,
+                )
+                .into_iter()
+                .collect();
+                proxy.open_tunnel(&mut stream, url)?;
+                stream.deadlines.clear();
+                None
+            }
+            None => None,
+        };
+
+        let stream = if url.scheme == Scheme::Https {
+            let hostname = url
+                .host
+                .as_str()
+                .try_into()
+                .map_err(|_| NetworkError::Tls(format!("Invalid server name {}", url.host)))?;
+            let client = rustls::ClientConnection::new(Tls::client_config(), hostname)
+                .map_err(|e| NetworkError::Tls(e.to_string()))?;
+
+            let mut stream = rustls::StreamOwned::new(client, stream);
+            stream.sock.deadlines = Self::deadline(
+                Instant::now(),
+                config.tls_handshake_timeout,
+                TimeoutPhase::TlsHandshake,
+            )
+            .into_iter()
+            .collect();
+
+            while stream.conn.is_handshaking() {
+                stream
+                    .conn
+                    .complete_io(&mut stream.sock)
+                    .map_err(|e| NetworkError::from_handshake_error(e, &url.host))?;
+            }
+            stream.sock.deadlines.clear();
+
+            Stream::Tls(Box::new(stream))
+        } else {
+            Stream::Plain(stream)
+        };
+
+        info!(%url, "Opened new connection");
+
+        Ok(Self {
+            key: Self::key(url),
+            reader: BufReader::new(stream),
+            last_used: Instant::now(),
+            forward_proxy,
+        })
+    }
+
+    // Sends the request, the first byte and transfer timeouts start now
+    pub fn send(
+        &mut self,
+        request: &[u8],
+        cancellation: &Cancellation,
+    ) -> Result<(), NetworkError> {
+        let config = Config::global();
+        let now = Instant::now();
+        let deadlines = [
+            Self::deadline(now, config.first_byte_timeout, TimeoutPhase::FirstByte),
+            Self::deadline(now, config.transfer_timeout, TimeoutPhase::Transfer),
+        ];
+        self.set_deadlines(cancellation, deadlines.into_iter().flatten().collect());
+
+        let stream = self.reader.get_mut();
+        stream.write_all(request)?;
+        Ok(stream.flush()?)
+    }
+
+    // Reads and writes fail with a timeout error once one of the deadlines has passed
+    pub fn set_deadlines(
+        &mut self,
+        cancellation: &Cancellation,
+        deadlines: Vec<(Instant, TimeoutPhase)>,
+    ) {
+        let timed_stream = self.reader.get_mut().timed_stream();
+        timed_stream.cancellation = cancellation.clone();
+        timed_stream.deadlines = deadlines;
+    }
+
+    // The time the phase times out, there is no deadline if the timeout is too large to be
+    // represented
+    pub fn deadline(
+        start: Instant,
+        timeout: Duration,
+        phase: TimeoutPhase,
+    ) -> Option<(Instant, TimeoutPhase)> {
+        start.checked_add(timeout).map(|deadline| (deadline, phase))
+    }
+
+    // Whether the server chose HTTP/2 during the TLS handshake
+    pub fn is_http2(&self) -> bool {
+        match self.reader.get_ref() {
+            Stream::Tls(stream) => stream.conn.alpn_protocol() == Some(b"h2"),
+            Stream::Plain(_) => false,
+        }
+    }
+
+    pub const fn forward_proxy(&self) -> Option<&Proxy> {
+        self.forward_proxy.as_ref()
+    }
+
+    // Reads exactly one response from the connection and returns whether the connection can be
+    // used for another request afterwards. The raw bytes of the response are passed to progress
+    // while they are received.
+    pub fn receive(
+        &mut self,
+        method: Method,
+        progress: &mut dyn FnMut(&[u8]),
+    ) -> Result<bool, NetworkError> {
+        let limits = Config::global().size_limits;
+        let reusable = ResponseReader::read(&mut self.reader, method, &limits, progress)?;
+        self.reader.get_mut().timed_stream().deadlines.clear();
+        self.last_used = Instant::now();
+
+        // left-over bytes mean the response framing is not what we expected
+        Ok(reusable && self.reader.buffer().is_empty())
+    }
+
+    // Tries the addresses in order until a connection can be established
+    fn connect(
+        addresses: &[SocketAddr],
+        timeout: Duration,
+        cancellation: &Cancellation,
+    ) -> Result<TcpStream, NetworkError> {
+        let deadline = Instant::now().checked_add(timeout);
+        let mut last_error = None;
+
+        for address in addresses {
+            if cancellation.is_cancelled() {
+                return Err(NetworkError::Cancelled);
+            }
+
+            let remaining = deadline.map_or(timeout, |deadline| {
+                deadline.saturating_duration_since(Instant::now())
+            });
+            if remaining.is_zero() {
+                break;
+            }
+
+            match TcpStream::connect_timeout(address, remaining) {
+                Ok(stream) => return Ok(stream),
+                Err(e) => last_error = Some(e),
+            }
+        }
+
+        Err(match last_error {
+            Some(e) if e.kind() != io::ErrorKind::TimedOut => NetworkError::Connect(e),
+            _ => NetworkError::Timeout(TimeoutPhase::Connect),
+        })
+    }
+
+    fn key(url: &Url) -> PoolKey {
+        (url.scheme.clone(), url.host.clone(), url.port)
+    }
+}
+
+// HTTP/2 connections read and write frames, which are buffered by the connection
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reader.read(buf)
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.reader.get_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.reader.get_mut().flush()
+    }
+}
+
+// Keeps idle keep-alive connections around so subsequent requests to the same origin can skip the
+// TCP and TLS handshakes. HTTP/2 connections are not checked out, as they are shared by all
+// requests to an origin.
+pub struct ConnectionPool {
+    idle: HashMap<PoolKey, Vec<Connection>>,
+    http2: HashMap<PoolKey, Arc<Mutex<Http2Connection>>>,
+}
+
+impl ConnectionPool {
+    const IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+    const MAX_IDLE_PER_ORIGIN: usize = 6;
+
+    pub fn global() -> &'static Mutex<Self> {
+        static POOL: OnceLock<Mutex<ConnectionPool>> = OnceLock::new();
+
+        POOL.get_or_init(|| Mutex::new(Self::new()))
+    }
+
+    fn new() -> Self {
+        Self {
+            idle: HashMap::new(),
+            http2: HashMap::new(),
+        }
+    }
+
+    pub fn checkout(&mut self, url: &Url) -> Option<Connection> {
+        self.evict_idle(Instant::now());
+
+        let connection = self.idle.get_mut(&Connection::key(url))?.pop();
+
+        if connection.is_some() {
+            info!(%url, "Reusing pooled connection");
+        }
+
+        connection
+    }
+
+    pub fn checkin(&mut self, connection: Connection) {
+        let connections = self.idle.entry(connection.key.clone()).or_default();
+
+        if connections.len() < Self::MAX_IDLE_PER_ORIGIN {
+            connections.push(connection);
+        }
+    }
+
+    pub fn http2_connection(&self, url: &Url) -> Option<Arc<Mutex<Http2Connection>>> {
+        self.http2.get(&Connection::key(url)).cloned()
+    }
+
+    pub fn add_http2(&mut self, url: &Url, connection: Arc<Mutex<Http2Connection>>) {
+        self.http2.insert(Connection::key(url), connection);
+    }
+
+    // Removes the connection unless it has already been replaced by another one
+    pub fn remove_http2(&mut self, url: &Url, connection: &Arc<Mutex<Http2Connection>>) {
+        let key = Connection::key(url);
+
+        if self
+            .http2
+            .get(&key)
+            .map_or(false, |pooled| Arc::ptr_eq(pooled, connection))
+        {
+            self.http2.remove(&key);
+        }
+    }
+
+    fn evict_idle(&mut self, now: Instant) {
+        self.idle.retain(|_, connections| {
+            connections.retain(|c| now.saturating_duration_since(c.last_used) < Self::IDLE_TIMEOUT);
+            !connections.is_empty()
+        });
+    }
+}
+
+struct ResponseReader {}
+
+impl ResponseReader {
+    // Reads the status line and headers, then as much body as the headers announce: either
+    // Content-Length bytes, a chunked body (kept in its chunked form), or everything until the
+    // server closes the connection. Responses to HEAD requests never have a body. The limits are
+    // checked while reading, so an oversized response is rejected early. Progress gets the headers
+    // once they are complete, and then every piece of the body. Returns whether the connection
+    // can be kept alive.
+    pub fn read<R: BufRead>(
+        reader: &mut R,
+        method: Method,
+        limits: &SizeLimits,
+        progress: &mut dyn FnMut(&[u8]),
+    ) -> Result<bool, NetworkError> {
+        // the limit for the header size includes interim responses, which are skipped
+        let mut bytes = Vec::new();
+
+        let (version, status_code, content_length, chunked, keep_alive) = loop {
+            let head_start = bytes.len();
+            let mut status_line = String::new();
+            let mut header_count = 0;
+            let mut content_length = None;
+            let mut chunked = false;
+            let mut keep_alive = None;
+
+            loop {
+                let mut line = Vec::new();
+                let remaining = limits.header_size.saturating_sub(bytes.len() as u64);
+                let length = Self::read_line(reader, &mut line, remaining)?;
+                if length as u64 == remaining && !line.ends_with(b"\n") {
+                    return Err(NetworkError::TooLarge(SizeLimit::HeaderSize(
+                        limits.header_size,
+                    )));
+                } else if length == 0 {
+                    return Err(NetworkError::Io(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "Connection closed before the end of the headers",
+                    )));
+                }
+                bytes.extend_from_slice(&line);
+
+                let line = String::from_utf8_lossy(&line);
+                let line = line.trim_end();
+
+                if line.is_empty() {
+                    break;
+                } else if status_line.is_empty() {
+                    status_line = line.to_string();
+                } else if let Some((name, value)) = line.split_once(':') {
+                    header_count += 1;
+                    if header_count > limits.header_count {
+                        return Err(NetworkError::TooLarge(SizeLimit::HeaderCount(
+                            limits.header_count,
+                        )));
+                    }
+
+                    let value = value.trim().to_ascii_lowercase();
+
+                    match name.to_ascii_lowercase().as_str() {
+                        "content-length" => content_length = value.parse::<u64>().ok(),
+                        "transfer-encoding" => chunked = Dechunker::is_chunked(&value),
+                        "connection" => keep_alive = Some(value != "close"),
+                        _ => {}
+                    }
+                }
+            }
+
+            let mut status = status_line.split(' ');
+            let version = status.next().unwrap_or_default().to_string();
+            let status_code = status.next().unwrap_or_default().to_string();
+
+            // interim responses like 100 Continue or 103 Early Hints are followed by the final
+            // response, except for 101 Switching Protocols
+            if status_code.starts_with('1') && status_code != "101" {
+                continue;
+            }
+
+            progress(&bytes[head_start..]);
+            break (version, status_code, content_length, chunked, keep_alive);
+        };
+
+        let keep_alive = keep_alive.unwrap_or(version == "HTTP/1.1");
+        let too_large = NetworkError::TooLarge(SizeLimit::BodySize(limits.body_size));
+
+        if status_code == "101" {
+            // the connection doesn't speak HTTP/1.1 anymore
+            Ok(false)
+        } else if method == Method::Head || status_code == "204" || status_code == "304" {
+            Ok(keep_alive)
+        } else if chunked {
+            Self::read_chunked(reader, limits.body_size, progress)?;
+            Ok(keep_alive)
+        } else if let Some(content_length) = content_length {
+            // rejected before reading, the server announced too much
+            if content_length > limits.body_size {
+                return Err(too_large);
+            }
+
+            Self::read_exact(reader, content_length, progress)?;
+            Ok(keep_alive)
+        } else {
+            let limit = limits.body_size.saturating_add(1);
+            if Self::read_body(reader, limit, progress)? > limits.body_size {
+                return Err(too_large);
+            }
+            Ok(false)
+        }
+    }
+
+    // Reads the body until length bytes are read or the connection is closed, and returns how many
+    // bytes were read. Each piece is passed to progress as soon as it is available.
+    fn read_body<R: BufRead>(
+        reader: &mut R,
+        length: u64,
+        progress: &mut dyn FnMut(&[u8]),
+    ) -> io::Result<u64> {
+        let mut read = 0;
+
+        while read < length {
+            let buffer = reader.fill_buf()?;
+            if buffer.is_empty() {
+                break;
+            }
+
+            let piece = &buffer[..buffer.len().min((length - read) as usize)];
+            progress(piece);
+
+            let piece_length = piece.len();
+            reader.consume(piece_length);
+            read += piece_length as u64;
+        }
+
+        Ok(read)
+    }
+
+    fn read_exact<R: BufRead>(
+        reader: &mut R,
+        length: u64,
+        progress: &mut dyn FnMut(&[u8]),
+    ) -> io::Result<()> {
+        if Self::read_body(reader, length, progress)? < length {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Connection closed before the end of the body",
+            ));
+        }
+
+        Ok(())
+    }
+
+    // Reads a line, but at most max_size bytes. The line lacks the trailing newline if it is
+    // longer.
+    fn read_line<R: BufRead>(
+        reader: &mut R,
+        line: &mut Vec<u8>,
+        max_size: u64,
+    ) -> io::Result<usize> {
+        (&mut *reader).take(max_size).read_until(b'\n', line)
+    }
+
+    // The body is read up to the end of its last chunk, which the dechunker finds. The chunk data
+    // of all chunks together may not exceed max_size bytes.
+    fn read_chunked<R: BufRead>(
+        reader: &mut R,
+        max_size: u64,
+        progress: &mut dyn FnMut(&[u8]),
+    ) -> Result<(), NetworkError> {
+        let mut dechunker = Dechunker::new();
+        let mut body_size: u64 = 0;
+
+        while !dechunker.is_done() {
+            let buffer = reader.fill_buf()?;
+            if buffer.is_empty() {
+                return Err(NetworkError::Io(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "Connection closed before the end of the body",
+                )));
+            }
+
+            let mut data = Vec::new();
+            let length = dechunker.feed(buffer, &mut data)?;
+            body_size += data.len() as u64;
+            if body_size > max_size {
+                return Err(NetworkError::TooLarge(SizeLimit::BodySize(max_size)));
+            }
+
+            progress(&buffer[..length]);
+            reader.consume(length);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    fn limits() -> SizeLimits {
+        SizeLimits {
+            header_size: 1024,
+            header_count: 4,
+            body_size: 16,
+            decoded_body_size: 16,
+        }
+    }
+
+    fn read(input: &mut &[u8], method: Method) -> Result<(Vec<u8>, bool), NetworkError> {
+        let mut bytes = Vec::new();
+        let reusable = ResponseReader::read(input, method, &limits(), &mut |piece| {
+            bytes.extend_from_slice(piece);
+        })?;
+        Ok((bytes, reusable))
+    }
+
+    #[test]
+    fn read_response_with_content_length() {
+        let mut input: &[u8] = b"HTTP/1.1 200 OK\r\n\
+                                 Content-Length: 5\r\n\
+                                 \r\n\
+                                 HelloNext";
+        let (bytes, reusable) = read(&mut input, Method::Get).unwrap();
+        assert_eq!(
+            b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nHello",
+            &bytes[..]
+        );
+        assert!(reusable);
+        assert_eq!(b"Next", input);
+    }
+
+    #[test]
+    fn read_response_after_interim_responses() {
+        let mut input: &[u8] = b"HTTP/1.1 100 Continue\r\n\r\n\
+                                 HTTP/1.1 103 Early Hints\r\n\
+                                 Link: </style.css>; rel=preload\r\n\
+                                 \r\n\
+                                 HTTP/1.1 200 OK\r\n\
+                                 Content-Length: 5\r\n\
+                                 \r\n\
+                                 HelloNext";
+        let (bytes, reusable) = read(&mut input, Method::Get).unwrap();
+        assert_eq!(
+            b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nHello",
+            &bytes[..]
+        );
+        assert!(reusable);
+        assert_eq!(b"Next", input);
+    }
+
+    #[test]
+    fn read_chunked_response() {
+        let mut input: &[u8] = b"HTTP/1.1 200 OK\r\n\
+                                 Transfer-Encoding: chunked\r\n\
+                                 \r\n\
+                                 4\r\n\
+                                 Wiki\r\n\
+                                 0\r\n\
+                                 \r\n\
+                                 Next";
+        let (bytes, reusable) = read(&mut input, Method::Get).unwrap();
+        assert!(bytes.ends_with(b"4\r\nWiki\r\n0\r\n\r\n"));
+        assert!(reusable);
+        assert_eq!(b"Next", input);
+    }
+
+    #[test]
+    fn read_truncated_chunked_response() {
+        let mut input: &[u8] = b"HTTP/1.1 200 OK\r\n\
+                                 Transfer-Encoding: chunked\r\n\
+                                 \r\n\
+                                 4\r\n\
+                                 Wi";
+        assert!(matches!(
+            read(&mut input, Method::Get),
+            Err(NetworkError::Io(_))
+        ));
+    }
+
+    #[test]
+    fn read_response_with_progress() {
+        let mut input: &[u8] = b"HTTP/1.1 200 OK\r\n\
+                                 Transfer-Encoding: chunked\r\n\
+                                 \r\n\
+                                 4\r\n\
+                                 Wiki\r\n\
+                                 0\r\n\
+                                 \r\n";
+        let mut pieces = Vec::new();
+        ResponseReader::read(&mut input, Method::Get, &limits(), &mut |piece| {
+            pieces.push(piece.to_vec());
+        })
+        .unwrap();
+
+        assert_eq!(b"4\r\nWiki\r\n0\r\n\r\n".to_vec(), pieces[1..].concat());
+        assert_eq!(
+            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n".to_vec(),
+            pieces[0]
+        );
+    }
+
+    #[test]
+    fn read_response_without_length() {
+        let mut input: &[u8] = b"HTTP/1.1 200 OK\r\n\r\nUntil the end";
+        let (bytes, reusable) = read(&mut input, Method::Get).unwrap();
+        assert!(bytes.ends_with(b"Until the end"));
+        assert!(!reusable);
+    }
+
+    #[test]
+    fn read_response_with_connection_close() {
+        let mut input: &[u8] = b"HTTP/1.1 200 OK\r\n\
+                                 Connection: close\r\n\
+                                 Content-Length: 0\r\n\r\n";
+        let (_, reusable) = read(&mut input, Method::Get).unwrap();
+        assert!(!reusable);
+    }
+
+    #[test]
+    fn read_http_1_0_response() {
+        let mut input: &[u8] = b"HTTP/1.0 200 OK\r\nContent-Length: 0\r\n\r\n";
+        let (_, reusable) = read(&mut input, Method::Get).unwrap();
+        assert!(!reusable);
+    }
+
+    #[test]
+    fn read_not_modified_response() {
+        let mut input: &[u8] = b"HTTP/1.1 304 Not Modified\r\n\r\nNext";
+        let (_, reusable) = read(&mut input, Method::Get).unwrap();
+        assert!(reusable);
+        assert_eq!(b"Next", input);
+    }
+
+    #[test]
+    fn read_head_response() {
+        let mut input: &[u8] = b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nNext";
+        let (bytes, reusable) = read(&mut input, Method::Head).unwrap();
+        assert!(bytes.ends_with(b"Content-Length: 5\r\n\r\n"));
+        assert!(reusable);
+        assert_eq!(b"Next", input);
+    }
+
+    #[test]
+    fn read_from_closed_connection() {
+        let mut input: &[u8] = b"";
+        assert!(read(&mut input, Method::Get).is_err());
+    }
+
+    #[test]
+    fn read_response_with_too_large_headers() {
+        let response = format!("HTTP/1.1 200 OK\r\nX-Large: {}\r\n\r\n", "a".repeat(2000));
+        let result = read(&mut response.as_bytes(), Method::Get);
+        assert!(matches!(
+            result,
+            Err(NetworkError::TooLarge(SizeLimit::HeaderSize(1024)))
+        ));
+
+        let response = "HTTP/1.1 200 OK\r\n".to_string() + &"A: b\r\n".repeat(5) + "\r\n";
+        let result = read(&mut response.as_bytes(), Method::Get);
+        assert!(matches!(
+            result,
+            Err(NetworkError::TooLarge(SizeLimit::HeaderCount(4)))
+        ));
+    }
+
+    #[test]
+    fn read_response_with_too_large_body() {
+        let responses: [&[u8]; 3] = [
+            b"HTTP/1.1 200 OK\r\nContent-Length: 1000000000\r\n\r\n",
+            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+              10\r\n0123456789abcdef\r\n1\r\nx\r\n0\r\n\r\n",
+            b"HTTP/1.1 200 OK\r\n\r\n0123456789abcdefx",
+        ];
+
+        for mut input in responses {
+            let result = read(&mut input, Method::Get);
+            assert!(matches!(
+                result,
+                Err(NetworkError::TooLarge(SizeLimit::BodySize(16)))
+            ));
+        }
+
+        let mut input: &[u8] = b"HTTP/1.1 200 OK\r\n\r\n0123456789abcdef";
+        assert!(read(&mut input, Method::Get).is_ok());
+    }
+
+    #[test]
+    fn checkout_returns_checked_in_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let url = Url::new(&format!("http://127.0.0.1:{}/", port)).unwrap();
+        let other_url = Url::new("http://example.com/").unwrap();
+
+        let mut pool = ConnectionPool::new();
+        pool.checkin(Connection::open(&url, &Cancellation::new()).unwrap());
+
+        assert!(pool.checkout(&other_url).is_none());
+        assert!(pool.checkout(&url).is_some());
+        assert!(pool.checkout(&url).is_none());
+    }
+
+    #[test]
+    fn checkout_evicts_idle_connections() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let url = Url::new(&format!("http://127.0.0.1:{}/", port)).unwrap();
+
+        let mut pool = ConnectionPool::new();
+        pool.checkin(Connection::open(&url, &Cancellation::new()).unwrap());
+        pool.evict_idle(Instant::now());
+        assert!(!pool.idle.is_empty());
+
+        pool.evict_idle(Instant::now() + ConnectionPool::IDLE_TIMEOUT);
+        assert!(pool.checkout(&url).is_none());
+    }
+
+    #[test]
+    fn deadline() {
+        let now = Instant::now();
+        assert_eq!(
+            Some((now + Duration::from_secs(1), TimeoutPhase::Connect)),
+            Connection::deadline(now, Duration::from_secs(1), TimeoutPhase::Connect)
+        );
+        assert_eq!(
+            None,
+            Connection::deadline(now, Duration::MAX, TimeoutPhase::Connect)
+        );
+    }
+
+    #[test]
+    fn connect_without_deadline() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+
+        assert!(Connection::connect(&[address], Duration::MAX, &Cancellation::new()).is_ok());
+    }
+
+    #[test]
+    fn receive_times_out_without_response() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let url = Url::new(&format!("http://127.0.0.1:{}/", port)).unwrap();
+
+        let mut connection = Connection::open(&url, &Cancellation::new()).unwrap();
+        connection.reader.get_mut().timed_stream().deadlines = vec![(
+            Instant::now() + Duration::from_millis(200),
+            TimeoutPhase::FirstByte,
+        )];
+
+        assert!(matches!(
+            connection.receive(Method::Get, &mut |_| {}),
+            Err(NetworkError::Timeout(TimeoutPhase::FirstByte))
+        ));
+    }
+
+    #[test]
+    fn receive_cancelled() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let url = Url::new(&format!("http://127.0.0.1:{}/", port)).unwrap();
+
+        let cancellation = Cancellation::new();
+        let mut connection = Connection::open(&url, &cancellation).unwrap();
+        cancellation.cancel();
+
+        assert!(matches!(
+            connection.receive(Method::Get, &mut |_| {}),
+            Err(NetworkError::Cancelled)
+        ));
+    }
+}
@@ -0,0 +1,331 @@
+use flate2::write;
+use std::io::{self, Write};
+
+use crate::headers::Headers;
+use crate::network_error::NetworkError;
+
+// The content codings we can decode, see https://www.rfc-editor.org/rfc/rfc9110#section-8.4.1
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ContentEncoding {
+    Identity,
+    Gzip,
+    Deflate,
+    Brotli,
+}
+
+impl ContentEncoding {
+    // In order of preference, identity is always acceptable and therefore not listed
+    const SUPPORTED: [Self; 3] = [Self::Brotli, Self::Gzip, Self::Deflate];
+
+    // Brotli's window can be up to 16 MiB, a smaller buffer just means more reads
+    const BROTLI_BUFFER_SIZE: usize = 4096;
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "identity" => Some(Self::Identity),
+            "gzip" | "x-gzip" => Some(Self::Gzip),
+            "deflate" => Some(Self::Deflate),
+            "br" => Some(Self::Brotli),
+            _ => None,
+        }
+    }
+
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Identity => "identity",
+            Self::Gzip => "gzip",
+            Self::Deflate => "deflate",
+            Self::Brotli => "br",
+        }
+    }
+
+    // The value of the Accept-Encoding request header
+    pub fn accept_encoding() -> String {
+        Self::SUPPORTED
+            .iter()
+            .map(|encoding| encoding.name())
+            .collect::<Vec<&str>>()
+            .join(", ")
+    }
+
+    // The content codings listed in the Content-Encoding headers, in the order they were applied
+    pub fn from_headers(headers: &Headers) -> Result<Vec<Self>, NetworkError> {
+        headers
+            .get_all("Content-Encoding")
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(|name| {
+                Self::from_name(name)
+                    .ok_or_else(|| NetworkError::UnsupportedContentEncoding(name.to_string()))
+            })
+            .collect()
+    }
+
+    // Wraps the decoder in a decoder for this content coding, which decodes the data written to
+    // it as far as possible and writes the result to the given decoder
+    pub fn decoder(self, inner: Decoder) -> Decoder {
+        match self {
+            Self::Identity => inner,
+            Self::Gzip => Decoder::Gzip(Box::new(write::GzDecoder::new(inner))),
+            Self::Deflate => {
+                Decoder::Deflate(Box::new(DeflateWriter::Undecided(Vec::new(), Some(inner))))
+            }
+            Self::Brotli => Decoder::Brotli(Box::new(
+                brotli_decompressor::DecompressorWriter::new(inner, Self::BROTLI_BUFFER_SIZE),
+            )),
+        }
+    }
+}
+
+// A chain of decoders for the content codings of a body, which ends in the writer the decoded
+// body goes to. Flushing passes on everything decoded so far, hence a body can be decoded while it
+// is being received. Only finishing tells whether the data was complete.
+pub enum Decoder {
+    Output(Box<dyn Write>),
+    Gzip(Box<write::GzDecoder<Decoder>>),
+    Deflate(Box<DeflateWriter>),
+    Brotli(Box<brotli_decompressor::DecompressorWriter<Decoder>>),
+}
+
+impl Decoder {
+    // Fails if the data of any content coding ended early
+    pub fn finish(&mut self) -> io::Result<()> {
+        match self {
+            Self::Output(output) => output.flush(),
+            Self::Gzip(decoder) => {
+                decoder.try_finish()?;
+                decoder.get_mut().finish()
+            }
+            Self::Deflate(writer) => writer.finish(),
+            Self::Brotli(decoder) => {
+                decoder.close()?;
+                decoder.get_mut().finish()
+            }
+        }
+    }
+}
+
+impl Write for Decoder {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Output(output) => output.write(buf),
+            Self::Gzip(decoder) => decoder.write(buf),
+            Self::Deflate(writer) => writer.write(buf),
+            Self::Brotli(decoder) => decoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Output(output) => output.flush(),
+            Self::Gzip(decoder) => decoder.flush(),
+            Self::Deflate(writer) => writer.flush(),
+            Self::Brotli(decoder) => decoder.flush(),
+        }
+    }
+}
+
+// Decodes "deflate" data written to it. "deflate" should be zlib-wrapped, but some servers send
+// raw deflate data: the first two bytes tell them apart, as they form a zlib header only for
+// zlib-wrapped data.
+pub enum DeflateWriter {
+    Undecided(Vec<u8>, Option<Decoder>),
+    Zlib(write::ZlibDecoder<Decoder>),
+    Raw(write::DeflateDecoder<Decoder>),
+}
+
+impl DeflateWriter {
+    // see https://www.rfc-editor.org/rfc/rfc1950#section-2.2
+    fn is_zlib_header(bytes: &[u8]) -> bool {
+        const DEFLATE: u8 = 8;
+        bytes[0] & 0x0f == DEFLATE && (u16::from(bytes[0]) << 8 | u16::from(bytes[1])) % 31 == 0
+    }
+
+    fn decide(&mut self) -> io::Result<()> {
+        if let Self::Undecided(buffered, inner) = self {
+            let inner = inner
+                .take()
+                .expect("undecided deflate writer without inner writer");
+            let buffered = std::mem::take(buffered);
+
+            *self = if buffered.len() >= 2 && Self::is_zlib_header(&buffered) {
+                Self::Zlib(write::ZlibDecoder::new(inner))
+            } else {
+                Self::Raw(write::DeflateDecoder::new(inner))
+            };
+            self.write_all(&buffered)?;
+        }
+
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        // data shorter than a zlib header can only be raw deflate data
+        self.decide()?;
+
+        match self {
+            Self::Undecided(..) => Ok(()),
+            Self::Zlib(decoder) => {
+                decoder.try_finish()?;
+                decoder.get_mut().finish()
+            }
+            Self::Raw(decoder) => {
+                decoder.try_finish()?;
+                decoder.get_mut().finish()
+            }
+        }
+    }
+}
+
+impl Write for DeflateWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Undecided(buffered, _) => {
+                buffered.extend_from_slice(buf);
+                if buffered.len() >= 2 {
+                    self.decide()?;
+                }
+                Ok(buf.len())
+            }
+            Self::Zlib(decoder) => decoder.write(buf),
+            Self::Raw(decoder) => decoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            // less than two bytes, which can't be decoded yet
+            Self::Undecided(..) => Ok(()),
+            Self::Zlib(decoder) => decoder.flush(),
+            Self::Raw(decoder) => decoder.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::body_decoder::BodyDecoder;
+    use crate::network_error::SizeLimit;
+    use flate2::write::{DeflateEncoder, GzEncoder, ZlibEncoder};
+    use flate2::Compression;
+    use std::io::Write;
+
+    const MAX: u64 = 1024;
+    const BROTLI_HELLO_WORLD: &[u8] = b"\x0b\x05\x80Hello World\x03";
+
+    fn headers(content_encoding: &str) -> Headers {
+        let mut headers = Headers::new();
+        headers.append("Content-Encoding", content_encoding);
+        headers
+    }
+
+    fn decode(body: &[u8], headers: &Headers, max_size: u64) -> Result<Vec<u8>, NetworkError> {
+        let mut decoder = BodyDecoder::new(headers, max_size)?;
+        decoder.feed(body)?;
+        decoder.finish().map(|(_, decoded)| decoded)
+    }
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn accept_encoding() {
+        assert_eq!("br, gzip, deflate", ContentEncoding::accept_encoding());
+    }
+
+    #[test]
+    fn from_name() {
+        assert_eq!(
+            Some(ContentEncoding::Gzip),
+            ContentEncoding::from_name("x-gzip")
+        );
+        assert_eq!(
+            Some(ContentEncoding::Brotli),
+            ContentEncoding::from_name("BR")
+        );
+        assert_eq!(None, ContentEncoding::from_name("compress"));
+    }
+
+    #[test]
+    fn decode_without_content_encoding() {
+        let body = decode(b"Hello", &Headers::new(), MAX).unwrap();
+        assert_eq!(b"Hello".to_vec(), body);
+    }
+
+    #[test]
+    fn decode_with_identity() {
+        let body = decode(b"Hello", &headers("identity"), MAX).unwrap();
+        assert_eq!(b"Hello".to_vec(), body);
+    }
+
+    #[test]
+    fn decode_with_gzip() {
+        let body = decode(&gzip(b"Hello"), &headers("gzip"), MAX).unwrap();
+        assert_eq!(b"Hello".to_vec(), body);
+    }
+
+    #[test]
+    fn decode_with_zlib_and_raw_deflate() {
+        let mut zlib = ZlibEncoder::new(Vec::new(), Compression::default());
+        zlib.write_all(b"Hello").unwrap();
+        let mut raw = DeflateEncoder::new(Vec::new(), Compression::default());
+        raw.write_all(b"Hello").unwrap();
+
+        for data in [zlib.finish().unwrap(), raw.finish().unwrap()] {
+            let body = decode(&data, &headers("deflate"), MAX).unwrap();
+            assert_eq!(b"Hello".to_vec(), body);
+        }
+    }
+
+    #[test]
+    fn decode_with_brotli() {
+        let body = decode(BROTLI_HELLO_WORLD, &headers("br"), MAX).unwrap();
+        assert_eq!(b"Hello World".to_vec(), body);
+    }
+
+    #[test]
+    fn decode_with_stacked_encodings() {
+        // brotli was applied first, then gzip
+        let body = decode(&gzip(BROTLI_HELLO_WORLD), &headers("br, gzip"), MAX).unwrap();
+        assert_eq!(b"Hello World".to_vec(), body);
+
+        let mut multiple_headers = headers("br");
+        multiple_headers.append("Content-Encoding", "gzip");
+        let body = decode(&gzip(BROTLI_HELLO_WORLD), &multiple_headers, MAX).unwrap();
+        assert_eq!(b"Hello World".to_vec(), body);
+    }
+
+    #[test]
+    fn decode_with_unknown_encoding() {
+        let result = decode(b"Hello", &headers("gzip, compress"), MAX);
+        assert!(matches!(
+            result,
+            Err(NetworkError::UnsupportedContentEncoding(name)) if name == "compress"
+        ));
+    }
+
+    #[test]
+    fn decode_with_invalid_data() {
+        let result = decode(b"Hello", &headers("br"), MAX);
+        assert!(matches!(result, Err(NetworkError::Decoding(_))));
+    }
+
+    #[test]
+    fn decode_with_decompression_bomb() {
+        let bomb = gzip(&vec![0; 1024 * 1024]);
+        assert!(bomb.len() < 2048);
+
+        let result = decode(&bomb, &headers("gzip"), MAX);
+        assert!(matches!(
+            result,
+            Err(NetworkError::TooLarge(SizeLimit::DecodedBodySize(MAX)))
+        ));
+        let result = decode(b"Hello", &headers("identity"), 4);
+        assert!(matches!(result, Err(NetworkError::TooLarge(_))));
+    }
+}
@@ -0,0 +1,642 @@
+use std::fs;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock};
+use tracing::{info, warn};
+
+use crate::http_date::HttpDate;
+use crate::request::Method;
+use crate::response::Response;
+use crate::url::{Scheme, Url};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+impl SameSite {
+    const fn name(self) -> &'static str {
+        match self {
+            Self::Strict => "Strict",
+            Self::Lax => "Lax",
+            Self::None => "None",
+        }
+    }
+
+    // Cookies without a (valid) SameSite attribute are treated as Lax
+    fn from_name(name: &str) -> Self {
+        match name.to_ascii_lowercase().as_str() {
+            "strict" => Self::Strict,
+            "none" => Self::None,
+            _ => Self::Lax,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cookie {
+    pub name: String,
+    pub value: String,
+    pub domain: String,
+    // a host-only cookie is only sent to the host which set it, not to its subdomains
+    pub host_only: bool,
+    pub path: String,
+    // in seconds since the Unix Epoch, session cookies have no expiry time
+    pub expires: Option<u64>,
+    pub secure: bool,
+    pub http_only: bool,
+    pub same_site: SameSite,
+}
+
+impl Cookie {
+    // Parses the value of a Set-Cookie header received from the given url. Returns None if the
+    // header is invalid or the cookie must be ignored, e.g. because its domain doesn't match.
+    // see https://datatracker.ietf.org/doc/html/rfc6265#section-5.2
+    pub fn parse(set_cookie: &str, url: &Url, now: u64) -> Option<Self> {
+        let mut parts = set_cookie.split(';');
+        let (name, value) = parts.next()?.split_once('=')?;
+        let name = name.trim();
+
+        if name.is_empty() {
+            return None;
+        }
+
+        let host = url.host.to_ascii_lowercase();
+        let mut cookie = Self {
+            name: name.to_string(),
+            value: value.trim().to_string(),
+            domain: host.clone(),
+            host_only: true,
+            path: Self::default_path(&url.path),
+            expires: None,
+            secure: false,
+            http_only: false,
+            same_site: SameSite::Lax,
+        };
+        let mut max_age = None;
+
+        for attribute in parts {
+            let (attribute_name, attribute_value) = attribute
+                .split_once('=')
+                .map_or((attribute, ""), |(k, v)| (k, v));
+            let attribute_value = attribute_value.trim();
+
+            match attribute_name.trim().to_ascii_lowercase().as_str() {
+                "domain" if !attribute_value.is_empty() => {
+                    let domain = attribute_value.trim_start_matches('.').to_ascii_lowercase();
+
+                    // without a public suffix list we can at least reject top-level domains
+                    if !Self::domain_matches(&host, &domain) || !domain.contains('.') {
+                        return None;
+                    }
+
+                    cookie.domain = domain;
+                    cookie.host_only = false;
+                }
+                "path" if attribute_value.starts_with('/') => {
+                    cookie.path = attribute_value.to_string();
+                }
+                "expires" => {
+                    if let Some(expires) = HttpDate::parse(attribute_value) {
+                        cookie.expires = Some(expires);
+                    }
+                }
+                "max-age" => {
+                    if let Ok(seconds) = attribute_value.parse::<i64>() {
+                        max_age = Some(seconds);
+                    }
+                }
+                "secure" => cookie.secure = true,
+                "httponly" => cookie.http_only = true,
+                "samesite" => cookie.same_site = SameSite::from_name(attribute_value),
+                _ => {}
+            }
+        }
+
+        // Max-Age has precedence over Expires
+        if let Some(seconds) = max_age {
+            cookie.expires = Some(if seconds > 0 {
+                now.saturating_add(seconds.unsigned_abs())
+            } else {
+                0
+            });
+        }
+
+        // secure cookies can only be set over https, and SameSite=None requires Secure
+        if (cookie.secure && url.scheme != Scheme::Https)
+            || (cookie.same_site == SameSite::None && !cookie.secure)
+        {
+            return None;
+        }
+
+        Some(cookie)
+    }
+
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires.map_or(false, |expires| expires <= now)
+    }
+
+    fn matches(&self, url: &Url) -> bool {
+        let host = url.host.to_ascii_lowercase();
+        let domain_matches = if self.host_only {
+            host == self.domain
+        } else {
+            Self::domain_matches(&host, &self.domain)
+        };
+
+        domain_matches
+            && Self::path_matches(&url.path, &self.path)
+            && (!self.secure || url.scheme == Scheme::Https)
+    }
+
+    // see https://datatracker.ietf.org/doc/html/rfc6265#section-5.1.3
+    fn domain_matches(host: &str, domain: &str) -> bool {
+        host == domain
+            || (host.ends_with(domain)
+                && host[..host.len() - domain.len()].ends_with('.')
+                && host.parse::<IpAddr>().is_err())
+    }
+
+    // see https://datatracker.ietf.org/doc/html/rfc6265#section-5.1.4
+    fn path_matches(request_path: &str, cookie_path: &str) -> bool {
+        let request_path = request_path.split(['?', '#']).next().unwrap_or_default();
+
+        request_path == cookie_path
+            || (request_path.starts_with(cookie_path)
+                && (cookie_path.ends_with('/')
+                    || request_path[cookie_path.len()..].starts_with('/')))
+    }
+
+    // The directory of the request path, e.g. "/a/b" for "/a/b/c"
+    fn default_path(request_path: &str) -> String {
+        let request_path = request_path.split(['?', '#']).next().unwrap_or_default();
+
+        match request_path.rfind('/') {
+            Some(0) | None => "/".to_string(),
+            Some(pos) => request_path[..pos].to_string(),
+        }
+    }
+
+    // Cookies are persisted one per line with tab-separated fields, similar to the cookies.txt
+    // format of curl and wget: domain, host-only, path, secure, expires, name, value, HttpOnly,
+    // SameSite
+    fn to_line(&self) -> String {
+        format!(
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            self.domain,
+            Self::flag(self.host_only),
+            self.path,
+            Self::flag(self.secure),
+            self.expires.unwrap_or(0),
+            self.name,
+            self.value,
+            Self::flag(self.http_only),
+            self.same_site.name()
+        )
+    }
+
+    fn from_line(line: &str) -> Option<Self> {
+        let fields: Vec<&str> = line.split('\t').collect();
+
+        if fields.len() != 9 {
+            return None;
+        }
+
+        Some(Self {
+            domain: fields[0].to_string(),
+            host_only: fields[1] == "TRUE",
+            path: fields[2].to_string(),
+            secure: fields[3] == "TRUE",
+            expires: Some(fields[4].parse().ok()?),
+            name: fields[5].to_string(),
+            value: fields[6].to_string(),
+            http_only: fields[7] == "TRUE",
+            same_site: SameSite::from_name(fields[8]),
+        })
+    }
+
+    const fn flag(value: bool) -> &'static str {
+        if value {
+            "TRUE"
+        } else {
+            "FALSE"
+        }
+    }
+}
+
+pub struct CookieJar {
+    // in the order the cookies were created
+    cookies: Vec<Cookie>,
+    file: Option<PathBuf>,
+}
+
+impl CookieJar {
+    const DATA_DIRECTORY_NAME: &'static str = "rbrowser";
+    const FILE_NAME: &'static str = "cookies.txt";
+
+    pub fn global() -> &'static Mutex<Self> {
+        static JAR: OnceLock<Mutex<CookieJar>> = OnceLock::new();
+
+        JAR.get_or_init(|| {
+            let file = dirs::data_dir().map(|mut dir| {
+                dir.push(Self::DATA_DIRECTORY_NAME);
+                dir.push(Self::FILE_NAME);
+                dir
+            });
+
+            Mutex::new(Self::load(file))
+        })
+    }
+
+    fn load(file: Option<PathBuf>) -> Self {
+        let now = HttpDate::now();
+        let cookies = file
+            .as_ref()
+            .and_then(|file| fs::read_to_string(file).ok())
+            .map(|content| {
+                content
+                    .lines()
+                    .filter_map(Cookie::from_line)
+                    .filter(|cookie| !cookie.is_expired(now))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Self { cookies, file }
+    }
+
+    // Stores the cookies of all Set-Cookie headers of the response to a request for the given url
+    pub fn store_from_response(&mut self, url: &Url, response: &Response) {
+        let now = HttpDate::now();
+        let mut persistent_cookies_changed = false;
+
+        for set_cookie in response.headers().get_all("Set-Cookie") {
+            if let Some(cookie) = Cookie::parse(set_cookie, url, now) {
+                info!(%url, name = %cookie.name, "Storing cookie");
+                persistent_cookies_changed |= self.insert(cookie, now);
+            }
+        }
+
+        if persistent_cookies_changed {
+            self.save();
+        }
+    }
+
+    // Replaces a cookie with the same name, domain and path, keeping its position. An expired
+    // cookie removes the cookie it replaces. Returns whether the persistent cookies changed, i.e.
+    // whether a persistent cookie was added, replaced or removed.
+    pub fn insert(&mut self, cookie: Cookie, now: u64) -> bool {
+        let existing = self.cookies.iter().position(|c| {
+            c.name == cookie.name && c.domain == cookie.domain && c.path == cookie.path
+        });
+        let persistent = cookie.expires.is_some();
+
+        match (existing, cookie.is_expired(now)) {
+            (Some(i), true) => self.cookies.remove(i).expires.is_some(),
+            (Some(i), false) => {
+                std::mem::replace(&mut self.cookies[i], cookie)
+                    .expires
+                    .is_some()
+                    || persistent
+            }
+            (None, true) => false,
+            (None, false) => {
+                self.cookies.push(cookie);
+                persistent
+            }
+        }
+    }
+
+    // Returns the value for the Cookie header of a request to the given url. The initiator is the
+    // url which started the navigation, e.g. the first url of a redirect chain, and is used to
+    // decide whether SameSite cookies are sent.
+    // see https://datatracker.ietf.org/doc/html/rfc6265#section-5.4
+    pub fn cookie_header(&mut self, url: &Url, initiator: &Url, method: Method) -> Option<String> {
+        let now = HttpDate::now();
+        self.cookies.retain(|cookie| !cookie.is_expired(now));
+
+        let same_site = Self::site(&url.host) == Self::site(&initiator.host);
+        let safe_method = matches!(method, Method::Get | Method::Head);
+
+        let mut cookies: Vec<&Cookie> = self
+            .cookies
+            .iter()
+            .filter(|cookie| cookie.matches(url))
+            .filter(|cookie| match cookie.same_site {
+                SameSite::Strict => same_site,
+                SameSite::Lax => same_site || safe_method,
+                SameSite::None => true,
+            })
+            .collect();
+
+        if cookies.is_empty() {
+            return None;
+        }
+
+        // longer paths first, otherwise in creation order (the sort is stable)
+        cookies.sort_by(|a, b| b.path.len().cmp(&a.path.len()));
+
+        Some(
+            cookies
+                .iter()
+                .map(|cookie| format!("{}={}", cookie.name, cookie.value))
+                .collect::<Vec<String>>()
+                .join("; "),
+        )
+    }
+
+    // Approximates the registrable domain with the last two labels of the host, as we don't have
+    // the public suffix list
+    fn site(host: &str) -> String {
+        if host.parse::<IpAddr>().is_ok() {
+            return host.to_string();
+        }
+
+        let labels: Vec<&str> = host.rsplitn(3, '.').take(2).collect();
+        labels
+            .into_iter()
+            .rev()
+            .collect::<Vec<&str>>()
+            .join(".")
+            .to_ascii_lowercase()
+    }
+
+    // Session cookies are not persisted
+    fn save(&self) {
+        let file = match &self.file {
+            Some(file) => file,
+            None => return,
+        };
+
+        let content: String = self
+            .cookies
+            .iter()
+            .filter(|cookie| cookie.expires.is_some())
+            .map(|cookie| cookie.to_line() + "\n")
+            .collect();
+
+        let result = file
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| fs::write(file, content));
+
+        if let Err(e) = result {
+            warn!(file = ?file, error = %e, "Unable to save cookies");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: u64 = 1_000_000;
+
+    fn url(s: &str) -> Url {
+        Url::new(s).unwrap()
+    }
+
+    fn jar_with(cookies: &[(&str, &str)]) -> CookieJar {
+        let mut jar = CookieJar {
+            cookies: Vec::new(),
+            file: None,
+        };
+
+        for (set_cookie, from) in cookies {
+            let cookie = Cookie::parse(set_cookie, &url(from), NOW).unwrap();
+            jar.insert(cookie, NOW);
+        }
+
+        jar
+    }
+
+    #[test]
+    fn parse_cookie() {
+        let cookie = Cookie::parse("id=a3fWa", &url("http://example.com/a/b"), NOW).unwrap();
+        assert_eq!("id", cookie.name);
+        assert_eq!("a3fWa", cookie.value);
+        assert_eq!("example.com", cookie.domain);
+        assert!(cookie.host_only);
+        assert_eq!("/a", cookie.path);
+        assert_eq!(None, cookie.expires);
+        assert_eq!(SameSite::Lax, cookie.same_site);
+    }
+
+    #[test]
+    fn parse_cookie_with_attributes() {
+        let cookie = Cookie::parse(
+            "id=1; Domain=.example.com; Path=/docs; Expires=Sun, 06 Nov 1994 08:49:37 GMT; \
+             Secure; HttpOnly; SameSite=Strict",
+            &url("https://www.example.com/"),
+            NOW,
+        )
+        .unwrap();
+        assert_eq!("example.com", cookie.domain);
+        assert!(!cookie.host_only);
+        assert_eq!("/docs", cookie.path);
+        assert_eq!(Some(784_111_777), cookie.expires);
+        assert!(cookie.secure);
+        assert!(cookie.http_only);
+        assert_eq!(SameSite::Strict, cookie.same_site);
+    }
+
+    #[test]
+    fn parse_cookie_with_max_age() {
+        let cookie = Cookie::parse(
+            "id=1; Max-Age=60; Expires=Sun, 06 Nov 1994 08:49:37 GMT",
+            &url("http://example.com/"),
+            NOW,
+        )
+        .unwrap();
+        assert_eq!(Some(NOW + 60), cookie.expires);
+
+        let cookie = Cookie::parse("id=1; Max-Age=-1", &url("http://example.com/"), NOW).unwrap();
+        assert!(cookie.is_expired(NOW));
+    }
+
+    #[test]
+    fn parse_cookie_with_foreign_domain() {
+        let from = url("http://example.com/");
+        assert_eq!(None, Cookie::parse("id=1; Domain=other.com", &from, NOW));
+        assert_eq!(None, Cookie::parse("id=1; Domain=com", &from, NOW));
+        assert_eq!(
+            None,
+            Cookie::parse("id=1; Domain=www.example.com", &from, NOW)
+        );
+    }
+
+    #[test]
+    fn parse_secure_cookie_from_http() {
+        let from = url("http://example.com/");
+        assert_eq!(None, Cookie::parse("id=1; Secure", &from, NOW));
+        assert_eq!(None, Cookie::parse("id=1; SameSite=None", &from, NOW));
+    }
+
+    #[test]
+    fn parse_invalid_cookie() {
+        let from = url("http://example.com/");
+        assert_eq!(None, Cookie::parse("novalue", &from, NOW));
+        assert_eq!(None, Cookie::parse("=1", &from, NOW));
+    }
+
+    #[test]
+    fn path_matches() {
+        assert!(Cookie::path_matches("/docs", "/docs"));
+        assert!(Cookie::path_matches("/docs/web", "/docs"));
+        assert!(Cookie::path_matches("/docs/web", "/docs/"));
+        assert!(Cookie::path_matches("/docs?q=1", "/docs"));
+        assert!(!Cookie::path_matches("/docsets", "/docs"));
+        assert!(!Cookie::path_matches("/", "/docs"));
+    }
+
+    #[test]
+    fn domain_matches() {
+        assert!(Cookie::domain_matches("example.com", "example.com"));
+        assert!(Cookie::domain_matches("www.example.com", "example.com"));
+        assert!(!Cookie::domain_matches("wwwexample.com", "example.com"));
+        assert!(!Cookie::domain_matches("1.2.3.4", "2.3.4"));
+    }
+
+    #[test]
+    fn cookie_header() {
+        let mut jar = jar_with(&[
+            ("a=1", "http://example.com/"),
+            ("b=2; Path=/docs", "http://example.com/"),
+            ("c=3", "http://other.com/"),
+            ("d=4; Domain=example.com", "http://www.example.com/"),
+        ]);
+        let target = url("http://example.com/docs/page");
+        assert_eq!(
+            Some("b=2; a=1; d=4".to_string()),
+            jar.cookie_header(&target, &target, Method::Get)
+        );
+
+        let target = url("http://sub.example.com/");
+        assert_eq!(
+            Some("d=4".to_string()),
+            jar.cookie_header(&target, &target, Method::Get)
+        );
+    }
+
+    #[test]
+    fn cookie_header_without_matching_cookies() {
+        let mut jar = jar_with(&[("a=1", "https://example.com/")]);
+        let target = url("http://other.com/");
+        assert_eq!(None, jar.cookie_header(&target, &target, Method::Get));
+    }
+
+    #[test]
+    fn cookie_header_excludes_secure_cookies_for_http() {
+        let mut jar = jar_with(&[("a=1; Secure", "https://example.com/")]);
+        let target = url("http://example.com/");
+        assert_eq!(None, jar.cookie_header(&target, &target, Method::Get));
+    }
+
+    #[test]
+    fn cookie_header_with_same_site_cookies() {
+        let mut jar = jar_with(&[
+            ("strict=1; SameSite=Strict", "https://example.com/"),
+            ("lax=2; SameSite=Lax", "https://example.com/"),
+            ("none=3; SameSite=None; Secure", "https://example.com/"),
+        ]);
+        let target = url("https://example.com/");
+        let initiator = url("https://other.com/");
+        assert_eq!(
+            Some("strict=1; lax=2; none=3".to_string()),
+            jar.cookie_header(&target, &url("https://www.example.com/"), Method::Post)
+        );
+        assert_eq!(
+            Some("lax=2; none=3".to_string()),
+            jar.cookie_header(&target, &initiator, Method::Get)
+        );
+        assert_eq!(
+            Some("none=3".to_string()),
+            jar.cookie_header(&target, &initiator, Method::Post)
+        );
+    }
+
+    #[test]
+    fn insert_replaces_existing_cookie() {
+        let mut jar = jar_with(&[
+            ("a=1", "http://example.com/"),
+            ("b=2", "http://example.com/"),
+            ("a=3", "http://example.com/"),
+        ]);
+        let target = url("http://example.com/");
+        assert_eq!(
+            Some("a=3; b=2".to_string()),
+            jar.cookie_header(&target, &target, Method::Get)
+        );
+    }
+
+    #[test]
+    fn insert_expired_cookie_removes_existing_cookie() {
+        let mut jar = jar_with(&[
+            ("a=1", "http://example.com/"),
+            ("a=; Max-Age=0", "http://example.com/"),
+        ]);
+        let target = url("http://example.com/");
+        assert_eq!(None, jar.cookie_header(&target, &target, Method::Get));
+    }
+
+    #[test]
+    fn insert_reports_changed_persistent_cookies() {
+        let mut jar = jar_with(&[]);
+        let target = url("http://example.com/");
+        let cookie = |set_cookie| Cookie::parse(set_cookie, &target, NOW).unwrap();
+
+        assert!(!jar.insert(cookie("a=1"), NOW));
+        assert!(jar.insert(cookie("a=2; Max-Age=60"), NOW));
+        // a session cookie replacing a persistent one
+        assert!(jar.insert(cookie("a=3"), NOW));
+        assert!(!jar.insert(cookie("a=; Max-Age=0"), NOW));
+        assert!(jar.insert(cookie("b=1; Max-Age=60"), NOW));
+        assert!(jar.insert(cookie("b=; Max-Age=0"), NOW));
+    }
+
+    #[test]
+    fn store_from_response_saves_replaced_persistent_cookie() {
+        let file = std::env::temp_dir().join(format!("rbrowser-cookies-{}", std::process::id()));
+        let mut jar = CookieJar::load(Some(file.clone()));
+        let target = url("http://example.com/");
+        let response = |set_cookie: &str| {
+            Response::new(
+                format!("HTTP/1.1 200 OK\r\nSet-Cookie: {}\r\n\r\n", set_cookie).as_bytes(),
+            )
+            .unwrap()
+        };
+
+        jar.store_from_response(&target, &response("a=1; Max-Age=60"));
+        assert!(fs::read_to_string(&file).unwrap().contains("\ta\t1\t"));
+
+        jar.store_from_response(&target, &response("a=2"));
+        assert_eq!("", fs::read_to_string(&file).unwrap());
+        let _ = fs::remove_file(file);
+    }
+
+    #[test]
+    fn to_line_and_from_line() {
+        let cookie = Cookie::parse(
+            "id=1; Domain=example.com; Max-Age=60; HttpOnly",
+            &url("https://example.com/a/b"),
+            NOW,
+        )
+        .unwrap();
+        let line = cookie.to_line();
+        assert_eq!(
+            "example.com\tFALSE\t/a\tFALSE\t1000060\tid\t1\tTRUE\tLax",
+            line
+        );
+        assert_eq!(Some(cookie), Cookie::from_line(&line));
+    }
+
+    #[test]
+    fn site() {
+        assert_eq!("example.com", CookieJar::site("www.example.com"));
+        assert_eq!("example.com", CookieJar::site("example.com"));
+        assert_eq!("localhost", CookieJar::site("localhost"));
+        assert_eq!("127.0.0.1", CookieJar::site("127.0.0.1"));
+    }
+}
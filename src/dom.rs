@@ -0,0 +1,272 @@
+use crate::selector::{Selector, SelectorError};
+
+// The nodes of a document are stored in a single vector and refer to each other by their index,
+// which keeps the tree builder free of shared ownership
+pub type NodeId = usize;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Element {
+    // lowercase
+    pub name: String,
+    pub attributes: Vec<(String, String)>,
+}
+
+impl Element {
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum NodeData {
+    Document,
+    Element(Element),
+    Text(String),
+    Comment(String),
+}
+
+#[derive(Clone, Debug)]
+pub struct Node {
+    pub data: NodeData,
+    pub parent: Option<NodeId>,
+    pub children: Vec<NodeId>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Document {
+    nodes: Vec<Node>,
+}
+
+impl Document {
+    pub const ROOT: NodeId = 0;
+
+    pub fn new() -> Self {
+        Self {
+            nodes: vec![Node {
+                data: NodeData::Document,
+                parent: None,
+                children: Vec::new(),
+            }],
+        }
+    }
+
+    pub fn node(&self, id: NodeId) -> &Node {
+        &self.nodes[id]
+    }
+
+    pub fn element(&self, id: NodeId) -> Option<&Element> {
+        match &self.nodes[id].data {
+            NodeData::Element(element) => Some(element),
+            _ => None,
+        }
+    }
+
+    pub fn element_mut(&mut self, id: NodeId) -> Option<&mut Element> {
+        match &mut self.nodes[id].data {
+            NodeData::Element(element) => Some(element),
+            _ => None,
+        }
+    }
+
+    // Adds the node as last child of the parent. Text following a text node is added to it
+    // instead, as adjacent text nodes are never created by parsing.
+    pub fn append(&mut self, parent: NodeId, data: NodeData) -> NodeId {
+        if let NodeData::Text(text) = &data {
+            if let Some(&last) = self.nodes[parent].children.last() {
+                if let NodeData::Text(existing) = &mut self.nodes[last].data {
+                    existing.push_str(text);
+                    return last;
+                }
+            }
+        }
+
+        let id = self.nodes.len();
+        self.nodes.push(Node {
+            data,
+            parent: Some(parent),
+            children: Vec::new(),
+        });
+        self.nodes[parent].children.push(id);
+        id
+    }
+
+    // The parent element, the document is no element
+    pub fn parent(&self, id: NodeId) -> Option<NodeId> {
+        self.node(id)
+            .parent
+            .filter(|&parent| self.element(parent).is_some())
+    }
+
+    // The ancestor elements, from the parent up to the root element
+    pub fn ancestors(&self, id: NodeId) -> Vec<NodeId> {
+        let mut ancestors = Vec::new();
+        let mut current = id;
+
+        while let Some(parent) = self.parent(current) {
+            ancestors.push(parent);
+            current = parent;
+        }
+
+        ancestors
+    }
+
+    // The elements before the node with the same parent, in document order
+    pub fn previous_element_siblings(&self, id: NodeId) -> Vec<NodeId> {
+        self.node(id).parent.map_or_else(Vec::new, |parent| {
+            self.node(parent)
+                .children
+                .iter()
+                .take_while(|&&sibling| sibling != id)
+                .copied()
+                .filter(|&sibling| self.element(sibling).is_some())
+                .collect()
+        })
+    }
+
+    // The first element matching the selectors, like querySelector() in the DOM
+    pub fn query_selector(&self, selectors: &str) -> Result<Option<NodeId>, SelectorError> {
+        Ok(self.query_selector_all(selectors)?.first().copied())
+    }
+
+    // The elements matching the selectors in document order, like querySelectorAll() in the DOM
+    pub fn query_selector_all(&self, selectors: &str) -> Result<Vec<NodeId>, SelectorError> {
+        let selector = Selector::parse(selectors)?;
+
+        Ok(self
+            .descendants(Self::ROOT)
+            .into_iter()
+            .filter(|&id| selector.matches(self, id))
+            .collect())
+    }
+
+    // The node and all its descendants, in document order
+    pub fn descendants(&self, id: NodeId) -> Vec<NodeId> {
+        let mut result = Vec::new();
+        let mut stack = vec![id];
+
+        while let Some(id) = stack.pop() {
+            result.push(id);
+            stack.extend(self.node(id).children.iter().rev());
+        }
+
+        result
+    }
+
+    pub fn body(&self) -> Option<NodeId> {
+        self.query_selector("body").unwrap_or_default()
+    }
+
+    // The text of the first <title> element with whitespace collapsed, see
+    // https://html.spec.whatwg.org/multipage/dom.html#document.title
+    pub fn title(&self) -> Option<String> {
+        self.query_selector("title")
+            .unwrap_or_default()
+            .map(|title| {
+                self.text_content(title)
+                    .split_ascii_whitespace()
+                    .collect::<Vec<&str>>()
+                    .join(" ")
+            })
+    }
+
+    // The text of all text nodes in the node, like textContent in the DOM
+    pub fn text_content(&self, id: NodeId) -> String {
+        self.descendants(id)
+            .into_iter()
+            .filter_map(|id| match &self.nodes[id].data {
+                NodeData::Text(text) => Some(text.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn element(name: &str) -> NodeData {
+        NodeData::Element(Element {
+            name: name.to_string(),
+            attributes: Vec::new(),
+        })
+    }
+
+    #[test]
+    fn append_merges_text() {
+        let mut document = Document::new();
+        let p = document.append(Document::ROOT, element("p"));
+        let first = document.append(p, NodeData::Text(String::from("a")));
+        let second = document.append(p, NodeData::Text(String::from("b")));
+
+        assert_eq!(first, second);
+        assert_eq!(
+            NodeData::Text(String::from("ab")),
+            document.node(first).data
+        );
+        assert_eq!(vec![first], document.node(p).children);
+        assert_eq!(Some(p), document.parent(first));
+        assert_eq!(None, document.parent(p));
+    }
+
+    #[test]
+    fn descendants_and_text_content() {
+        let mut document = Document::new();
+        let body = document.append(Document::ROOT, element("body"));
+        let p = document.append(body, element("p"));
+        document.append(p, NodeData::Text(String::from("Hello ")));
+        document.append(body, NodeData::Comment(String::from("comment")));
+        let b = document.append(body, element("b"));
+        document.append(b, NodeData::Text(String::from("World")));
+
+        assert_eq!(7, document.descendants(Document::ROOT).len());
+        assert_eq!(Ok(Some(b)), document.query_selector("b"));
+        assert_eq!(Some(body), document.body());
+        assert_eq!("Hello World", document.text_content(body));
+        assert_eq!(None, document.title());
+    }
+
+    #[test]
+    fn ancestors_and_siblings() {
+        let mut document = Document::new();
+        let html = document.append(Document::ROOT, element("html"));
+        let body = document.append(html, element("body"));
+        let h1 = document.append(body, element("h1"));
+        document.append(body, NodeData::Text(String::from("text")));
+        let p = document.append(body, element("p"));
+        let b = document.append(p, element("b"));
+
+        assert_eq!(vec![p, body, html], document.ancestors(b));
+        assert_eq!(vec![h1], document.previous_element_siblings(p));
+        assert!(document.previous_element_siblings(h1).is_empty());
+    }
+
+    #[test]
+    fn query_selector() {
+        let mut document = Document::new();
+        let ul = document.append(Document::ROOT, element("ul"));
+        let first = document.append(ul, element("li"));
+        let second = document.append(ul, element("li"));
+
+        assert_eq!(Ok(Some(first)), document.query_selector("ul > li"));
+        assert_eq!(Ok(None), document.query_selector("ol"));
+        assert_eq!(Ok(vec![first, second]), document.query_selector_all("li"));
+        assert_eq!(
+            Ok(vec![second]),
+            document.query_selector_all("li:nth-child(2)")
+        );
+        assert!(document.query_selector_all("li >").is_err());
+    }
+
+    #[test]
+    fn title() {
+        let mut document = Document::new();
+        let title = document.append(Document::ROOT, element("title"));
+        document.append(title, NodeData::Text(String::from(" A\n  title ")));
+
+        assert_eq!(Some(String::from("A title")), document.title());
+    }
+}
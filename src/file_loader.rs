@@ -0,0 +1,185 @@
+use std::fs;
+use std::path::Path;
+use tracing::info;
+
+use crate::character_reference::CharacterReference;
+use crate::network_error::NetworkError;
+use crate::response::Response;
+use crate::url::Url;
+
+// Loads file urls from the local filesystem. Files are returned like a HTTP response with a
+// Content-Type guessed from the file extension, directories as a generated HTML listing.
+pub struct FileLoader {}
+
+impl FileLoader {
+    pub fn load(url: &Url) -> Result<Response, NetworkError> {
+        let path = url
+            .file_path()
+            .ok_or(NetworkError::MalformedResponse("not a file url"))?;
+        let file_error = |e| NetworkError::File(path.display().to_string(), e);
+
+        info!(path = %path.display(), "Load file");
+
+        let (content_type, body) = if fs::metadata(&path).map_err(file_error)?.is_dir() {
+            (
+                "text/html; charset=utf-8",
+                Self::directory_listing(&path, url)
+                    .map_err(file_error)?
+                    .into_bytes(),
+            )
+        } else {
+            (
+                Self::content_type(&path),
+                fs::read(&path).map_err(file_error)?,
+            )
+        };
+
+        let mut response = format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n",
+            content_type,
+            body.len()
+        )
+        .into_bytes();
+        response.extend_from_slice(&body);
+
+        Response::new(&response)
+    }
+
+    fn content_type(path: &Path) -> &'static str {
+        let extension = path
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_ascii_lowercase());
+
+        match extension.as_deref() {
+            Some("html" | "htm" | "xhtml") => "text/html",
+            Some("txt" | "text") => "text/plain",
+            Some("md" | "markdown") => "text/markdown",
+            Some("css") => "text/css",
+            Some("js" | "mjs") => "text/javascript",
+            Some("json") => "application/json",
+            Some("xml") => "application/xml",
+            Some("svg") => "image/svg+xml",
+            Some("png") => "image/png",
+            Some("jpg" | "jpeg") => "image/jpeg",
+            Some("gif") => "image/gif",
+            Some("webp") => "image/webp",
+            Some("ico") => "image/x-icon",
+            Some("pdf") => "application/pdf",
+            _ => "application/octet-stream",
+        }
+    }
+
+    // Directories are listed before files, both sorted by name. The links are absolute, so they
+    // also work if the url of the directory lacks the trailing slash.
+    fn directory_listing(path: &Path, url: &Url) -> std::io::Result<String> {
+        let mut entries = fs::read_dir(path)?
+            .filter_map(Result::ok)
+            .map(|entry| {
+                let is_dir = entry.file_type().map_or(false, |t| t.is_dir());
+                (!is_dir, entry.file_name().to_string_lossy().into_owned())
+            })
+            .collect::<Vec<(bool, String)>>();
+        entries.sort();
+
+        let base = if url.path.ends_with('/') {
+            url.path.clone()
+        } else {
+            url.path.clone() + "/"
+        };
+        let title = CharacterReference::escape(&path.display().to_string());
+
+        let mut listing = format!(
+            "<html><head><title>Index of {}</title></head><body><h1>Index of {}</h1><ul>",
+            title, title
+        );
+
+        if path.parent().is_some() {
+            listing += &format!("<li><a href=\"{}../\">../</a></li>", base);
+        }
+
+        for (is_file, name) in entries {
+            let suffix = if is_file { "" } else { "/" };
+            listing += &format!(
+                "<li><a href=\"{}{}{}\">{}{}</a></li>",
+                base,
+                Url::encode_path_segment(&name),
+                suffix,
+                CharacterReference::escape(&name),
+                suffix
+            );
+        }
+
+        listing += "</ul></body></html>";
+        Ok(listing)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn temp_directory(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir()
+            .join(format!("rbrowser-file-{}", std::process::id()))
+            .join(name);
+        fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    fn url(path: &Path) -> Url {
+        Url::new(&format!("file://{}", path.display())).unwrap()
+    }
+
+    #[test]
+    fn load_file() {
+        let directory = temp_directory("file");
+        fs::write(directory.join("a b.html"), "<p>Hello</p>").unwrap();
+
+        let response = FileLoader::load(&url(&directory.join("a%20b.html"))).unwrap();
+        assert_eq!(200, response.status.code);
+        assert_eq!(Some("text/html"), response.header("Content-Type"));
+        assert_eq!("<p>Hello</p>", response.body);
+    }
+
+    #[test]
+    fn load_missing_file() {
+        let directory = temp_directory("missing");
+        let result = FileLoader::load(&url(&directory.join("missing.html")));
+        assert!(matches!(result, Err(NetworkError::File(_, _))));
+    }
+
+    #[test]
+    fn load_directory() {
+        let directory = temp_directory("directory");
+        fs::create_dir_all(directory.join("sub")).unwrap();
+        fs::write(directory.join("b&c.txt"), "").unwrap();
+        fs::write(directory.join("a.txt"), "").unwrap();
+
+        let response = FileLoader::load(&url(&directory)).unwrap();
+        let base = url(&directory).path + "/";
+        assert_eq!(
+            Some("text/html; charset=utf-8"),
+            response.header("Content-Type")
+        );
+        assert!(response.body.contains(&format!(
+            "<ul><li><a href=\"{}../\">../</a></li>\
+             <li><a href=\"{}sub/\">sub/</a></li>\
+             <li><a href=\"{}a.txt\">a.txt</a></li>\
+             <li><a href=\"{}b%26c.txt\">b&amp;c.txt</a></li></ul>",
+            base, base, base, base
+        )));
+    }
+
+    #[test]
+    fn content_type() {
+        assert_eq!(
+            "text/css",
+            FileLoader::content_type(Path::new("a/style.CSS"))
+        );
+        assert_eq!(
+            "application/octet-stream",
+            FileLoader::content_type(Path::new("README"))
+        );
+    }
+}
@@ -0,0 +1,151 @@
+// A list of header fields as they appear in a request or response. The order and the case of the
+// names is preserved, a name can occur multiple times, and lookups are case-insensitive.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Headers {
+    entries: Vec<(String, String)>,
+}
+
+impl Headers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Adds a header field, keeping any existing fields with the same name
+    pub fn append(&mut self, name: &str, value: &str) {
+        self.entries.push((name.to_string(), value.to_string()));
+    }
+
+    // Sets a header field, replacing all existing fields with the same name. The new field takes
+    // the position of the first replaced field.
+    pub fn insert(&mut self, name: &str, value: &str) {
+        match self.position(name) {
+            Some(i) => {
+                self.remove(name);
+                self.entries
+                    .insert(i, (name.to_string(), value.to_string()));
+            }
+            None => self.append(name, value),
+        }
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        self.entries.retain(|(n, _)| !n.eq_ignore_ascii_case(name));
+    }
+
+    // Appends a continuation line (obs-fold) to the value of the last header field
+    pub fn extend_last(&mut self, continuation: &str) {
+        if let Some((_, value)) = self.entries.last_mut() {
+            if !value.is_empty() {
+                value.push(' ');
+            }
+            value.push_str(continuation);
+        }
+    }
+
+    // Returns the value of the first field with the given name
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.position(name).map(|i| self.entries[i].1.as_str())
+    }
+
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> {
+        self.entries
+            .iter()
+            .filter(move |(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.position(name).is_some()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    fn position(&self, name: &str) -> Option<usize> {
+        self.entries
+            .iter()
+            .position(|(n, _)| n.eq_ignore_ascii_case(name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn get_is_case_insensitive() {
+        let mut headers = Headers::new();
+        headers.append("Content-Type", "text/html");
+        assert_eq!(Some("text/html"), headers.get("content-type"));
+        assert_eq!(Some("text/html"), headers.get("CONTENT-TYPE"));
+        assert_eq!(None, headers.get("Content-Length"));
+    }
+
+    #[test]
+    fn get_all() {
+        let mut headers = Headers::new();
+        headers.append("Set-Cookie", "a=1");
+        headers.append("Vary", "Accept");
+        headers.append("set-cookie", "b=2");
+        assert_eq!(Some("a=1"), headers.get("Set-Cookie"));
+        assert_eq!(
+            vec!["a=1", "b=2"],
+            headers.get_all("Set-Cookie").collect::<Vec<&str>>()
+        );
+        assert_eq!(0, headers.get_all("Link").count());
+    }
+
+    #[test]
+    fn iter_preserves_order_and_case() {
+        let mut headers = Headers::new();
+        headers.append("B", "2");
+        headers.append("a", "1");
+        headers.append("C", "3");
+        assert_eq!(
+            vec![("B", "2"), ("a", "1"), ("C", "3")],
+            headers.iter().collect::<Vec<(&str, &str)>>()
+        );
+    }
+
+    #[test]
+    fn insert_replaces_existing_fields() {
+        let mut headers = Headers::new();
+        headers.append("A", "1");
+        headers.append("B", "2");
+        headers.append("a", "3");
+        headers.insert("A", "4");
+        assert_eq!(
+            vec![("A", "4"), ("B", "2")],
+            headers.iter().collect::<Vec<(&str, &str)>>()
+        );
+    }
+
+    #[test]
+    fn insert_new_field() {
+        let mut headers = Headers::new();
+        headers.insert("A", "1");
+        assert_eq!(Some("1"), headers.get("A"));
+    }
+
+    #[test]
+    fn remove() {
+        let mut headers = Headers::new();
+        headers.append("A", "1");
+        headers.append("a", "2");
+        headers.append("B", "3");
+        headers.remove("A");
+        assert!(!headers.contains("A"));
+        assert!(headers.contains("B"));
+    }
+
+    #[test]
+    fn extend_last() {
+        let mut headers = Headers::new();
+        headers.append("Warning", "199 - \"first");
+        headers.extend_last("second\"");
+        assert_eq!(Some("199 - \"first second\""), headers.get("Warning"));
+    }
+}
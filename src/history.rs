@@ -0,0 +1,148 @@
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock};
+use tracing::warn;
+
+use crate::http_date::HttpDate;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Visit {
+    pub url: String,
+    // in seconds since the Unix Epoch
+    pub time: u64,
+}
+
+impl Visit {
+    // Visits are persisted one per line with tab-separated fields: time, url
+    fn to_line(&self) -> String {
+        format!("{}\t{}", self.time, self.url)
+    }
+
+    fn from_line(line: &str) -> Option<Self> {
+        let (time, url) = line.split_once('\t')?;
+
+        Some(Self {
+            url: url.to_string(),
+            time: time.parse().ok()?,
+        })
+    }
+}
+
+// The pages the user visited, oldest first. Only the most recent visits are kept.
+pub struct History {
+    visits: Vec<Visit>,
+    file: Option<PathBuf>,
+}
+
+impl History {
+    const DATA_DIRECTORY_NAME: &'static str = "rbrowser";
+    const FILE_NAME: &'static str = "history.txt";
+    const MAX_VISITS: usize = 1000;
+
+    pub fn global() -> &'static Mutex<Self> {
+        static HISTORY: OnceLock<Mutex<History>> = OnceLock::new();
+
+        HISTORY.get_or_init(|| {
+            let file = dirs::data_dir().map(|mut dir| {
+                dir.push(Self::DATA_DIRECTORY_NAME);
+                dir.push(Self::FILE_NAME);
+                dir
+            });
+
+            Mutex::new(Self::load(file))
+        })
+    }
+
+    fn load(file: Option<PathBuf>) -> Self {
+        let visits = file
+            .as_ref()
+            .and_then(|file| fs::read_to_string(file).ok())
+            .map(|content| content.lines().filter_map(Visit::from_line).collect())
+            .unwrap_or_default();
+
+        Self { visits, file }
+    }
+
+    pub fn add(&mut self, url: &str) {
+        self.visits.push(Visit {
+            url: url.to_string(),
+            time: HttpDate::now(),
+        });
+
+        if self.visits.len() > Self::MAX_VISITS {
+            let excess = self.visits.len() - Self::MAX_VISITS;
+            self.visits.drain(..excess);
+        }
+
+        self.save();
+    }
+
+    pub fn visits(&self) -> &[Visit] {
+        &self.visits
+    }
+
+    fn save(&self) {
+        let file = match &self.file {
+            Some(file) => file,
+            None => return,
+        };
+
+        let content: String = self
+            .visits
+            .iter()
+            .map(|visit| visit.to_line() + "\n")
+            .collect();
+
+        let result = file
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| fs::write(file, content));
+
+        if let Err(e) = result {
+            warn!(file = ?file, error = %e, "Unable to save history");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn visit_line_roundtrip() {
+        let visit = Visit {
+            url: String::from("http://example.com:80/"),
+            time: 1_000_000,
+        };
+        assert_eq!("1000000\thttp://example.com:80/", visit.to_line());
+        assert_eq!(Some(visit.clone()), Visit::from_line(&visit.to_line()));
+        assert_eq!(None, Visit::from_line("yesterday\thttp://example.com:80/"));
+    }
+
+    #[test]
+    fn add_keeps_most_recent_visits() {
+        let mut history = History {
+            visits: Vec::new(),
+            file: None,
+        };
+
+        for i in 0..=History::MAX_VISITS {
+            history.add(&format!("http://example.com/{}", i));
+        }
+
+        assert_eq!(History::MAX_VISITS, history.visits().len());
+        assert_eq!("http://example.com/1", history.visits()[0].url);
+    }
+
+    #[test]
+    fn load_and_save() {
+        let file = std::env::temp_dir().join(format!("rbrowser-history-{}", std::process::id()));
+        let _ = fs::remove_file(&file);
+        let mut history = History::load(Some(file.clone()));
+        history.add("http://example.com/");
+
+        let history = History::load(Some(file.clone()));
+        assert_eq!(1, history.visits().len());
+        let _ = fs::remove_file(file);
+    }
+}
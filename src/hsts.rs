@@ -0,0 +1,374 @@
+use std::collections::HashMap;
+use std::fs;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock};
+use tracing::{info, warn};
+
+use crate::hsts_preload::HSTS_PRELOAD;
+use crate::http_date::HttpDate;
+use crate::response::Response;
+use crate::url::{Scheme, Url};
+
+// A host which must only be accessed over https, see https://www.rfc-editor.org/rfc/rfc6797
+#[derive(Clone, Debug, PartialEq)]
+pub struct HstsPolicy {
+    // in seconds since the Unix Epoch
+    pub expires: u64,
+    pub include_subdomains: bool,
+}
+
+impl HstsPolicy {
+    // Parses the value of a Strict-Transport-Security header. Directives are case-insensitive,
+    // values may be quoted, and headers without max-age or with duplicate directives are invalid.
+    // see https://www.rfc-editor.org/rfc/rfc6797#section-6.1
+    pub fn parse(value: &str, now: u64) -> Option<Self> {
+        let mut max_age = None;
+        let mut include_subdomains = false;
+        let mut seen = Vec::new();
+
+        for directive in value.split(';').map(str::trim).filter(|d| !d.is_empty()) {
+            let (name, value) = directive
+                .split_once('=')
+                .map_or((directive, ""), |(name, value)| (name, value));
+            let name = name.trim().to_ascii_lowercase();
+            let value = value.trim().trim_matches('"');
+
+            if seen.contains(&name) {
+                return None;
+            }
+
+            match name.as_str() {
+                "max-age" => max_age = Some(value.parse::<u64>().ok()?),
+                "includesubdomains" => include_subdomains = true,
+                _ => {}
+            }
+            seen.push(name);
+        }
+
+        Some(Self {
+            expires: now.saturating_add(max_age?),
+            include_subdomains,
+        })
+    }
+
+    pub const fn is_expired(&self, now: u64) -> bool {
+        self.expires <= now
+    }
+
+    // Policies are persisted one per line with tab-separated fields: host, expires,
+    // includeSubDomains
+    fn to_line(&self, host: &str) -> String {
+        format!(
+            "{}\t{}\t{}",
+            host,
+            self.expires,
+            if self.include_subdomains {
+                "TRUE"
+            } else {
+                "FALSE"
+            }
+        )
+    }
+
+    fn from_line(line: &str) -> Option<(String, Self)> {
+        let fields: Vec<&str> = line.split('\t').collect();
+
+        if fields.len() != 3 {
+            return None;
+        }
+
+        Some((
+            fields[0].to_string(),
+            Self {
+                expires: fields[1].parse().ok()?,
+                include_subdomains: fields[2] == "TRUE",
+            },
+        ))
+    }
+}
+
+// The known HSTS hosts, from Strict-Transport-Security headers and the preload list. Requests to
+// these hosts (and their subdomains, if the policy includes them) are upgraded to https before
+// they are sent.
+pub struct HstsStore {
+    policies: HashMap<String, HstsPolicy>,
+    file: Option<PathBuf>,
+}
+
+impl HstsStore {
+    const DATA_DIRECTORY_NAME: &'static str = "rbrowser";
+    const FILE_NAME: &'static str = "hsts.txt";
+
+    pub fn global() -> &'static Mutex<Self> {
+        static STORE: OnceLock<Mutex<HstsStore>> = OnceLock::new();
+
+        STORE.get_or_init(|| {
+            let file = dirs::data_dir().map(|mut dir| {
+                dir.push(Self::DATA_DIRECTORY_NAME);
+                dir.push(Self::FILE_NAME);
+                dir
+            });
+
+            Mutex::new(Self::load(file))
+        })
+    }
+
+    fn load(file: Option<PathBuf>) -> Self {
+        let now = HttpDate::now();
+        let policies = file
+            .as_ref()
+            .and_then(|file| fs::read_to_string(file).ok())
+            .map(|content| {
+                content
+                    .lines()
+                    .filter_map(HstsPolicy::from_line)
+                    .filter(|(_, policy)| !policy.is_expired(now))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Self { policies, file }
+    }
+
+    // Only headers received over https are processed, and IP addresses can't be HSTS hosts
+    // see https://www.rfc-editor.org/rfc/rfc6797#section-8.1
+    pub fn store_from_response(&mut self, url: &Url, response: &Response) {
+        if url.scheme != Scheme::Https || url.host.parse::<IpAddr>().is_ok() {
+            return;
+        }
+
+        let now = HttpDate::now();
+        let policy = match response
+            .header("Strict-Transport-Security")
+            .and_then(|value| HstsPolicy::parse(value, now))
+        {
+            Some(policy) => policy,
+            None => return,
+        };
+
+        let host = url.host.to_ascii_lowercase();
+        let changed = if policy.is_expired(now) {
+            self.policies.remove(&host).is_some()
+        } else if self.policies.get(&host).map_or(true, |p| *p != policy) {
+            info!(host, "Storing HSTS policy");
+            self.policies.insert(host, policy);
+            true
+        } else {
+            false
+        };
+
+        if changed {
+            self.save();
+        }
+    }
+
+    // Returns the https url to use instead of the given http url, if the host is a known HSTS
+    // host. The port is only changed if it's the default port.
+    // see https://www.rfc-editor.org/rfc/rfc6797#section-8.3
+    // Whether the domain is on the preload list shipped with browsers (see https://hstspreload.org),
+    // and if so, whether its subdomains are, too. Preloaded hosts can't be removed by a header with
+    // max-age=0.
+    fn preloaded(domain: &str) -> Option<bool> {
+        HSTS_PRELOAD
+            .binary_search_by(|(name, _)| (*name).cmp(domain))
+            .ok()
+            .map(|index| HSTS_PRELOAD[index].1)
+    }
+
+    pub fn upgrade(&self, url: &Url) -> Option<Url> {
+        if url.scheme != Scheme::Http || !self.is_hsts_host(&url.host, HttpDate::now()) {
+            return None;
+        }
+
+        let mut upgraded = url.clone();
+        upgraded.scheme = Scheme::Https;
+        if url.port == 80 {
+            upgraded.port = 443;
+        }
+
+        Some(upgraded)
+    }
+
+    // The host itself and all its superdomains are checked, the latter only match if their
+    // policy includes subdomains
+    fn is_hsts_host(&self, host: &str, now: u64) -> bool {
+        if host.parse::<IpAddr>().is_ok() {
+            return false;
+        }
+
+        let host = host.to_ascii_lowercase();
+        let mut domain = host.as_str();
+
+        loop {
+            let is_host = domain == host;
+            let preloaded = Self::preloaded(domain)
+                .map_or(false, |include_subdomains| is_host || include_subdomains);
+            let known = self.policies.get(domain).map_or(false, |policy| {
+                !policy.is_expired(now) && (is_host || policy.include_subdomains)
+            });
+
+            if preloaded || known {
+                return true;
+            }
+
+            match domain.split_once('.') {
+                Some((_, superdomain)) => domain = superdomain,
+                None => return false,
+            }
+        }
+    }
+
+    fn save(&self) {
+        let file = match &self.file {
+            Some(file) => file,
+            None => return,
+        };
+
+        let content: String = self
+            .policies
+            .iter()
+            .map(|(host, policy)| policy.to_line(host) + "\n")
+            .collect();
+
+        let result = file
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| fs::write(file, content));
+
+        if let Err(e) = result {
+            warn!(file = ?file, error = %e, "Unable to save HSTS policies");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: u64 = 1_000_000;
+
+    fn url(s: &str) -> Url {
+        Url::new(s).unwrap()
+    }
+
+    fn store() -> HstsStore {
+        HstsStore {
+            policies: HashMap::new(),
+            file: None,
+        }
+    }
+
+    fn response(sts: &str) -> Response {
+        let response = format!(
+            "HTTP/1.1 200 OK\r\nStrict-Transport-Security: {}\r\n\r\n",
+            sts
+        );
+        Response::new(response.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn parse_policy() {
+        let policy = HstsPolicy::parse("max-age=31536000; includeSubDomains", NOW).unwrap();
+        assert_eq!(NOW + 31_536_000, policy.expires);
+        assert!(policy.include_subdomains);
+
+        let policy = HstsPolicy::parse("MAX-AGE=\"100\"; preload", NOW).unwrap();
+        assert_eq!(NOW + 100, policy.expires);
+        assert!(!policy.include_subdomains);
+    }
+
+    #[test]
+    fn parse_invalid_policy() {
+        assert_eq!(None, HstsPolicy::parse("includeSubDomains", NOW));
+        assert_eq!(None, HstsPolicy::parse("max-age=abc", NOW));
+        assert_eq!(None, HstsPolicy::parse("max-age=1; max-age=2", NOW));
+    }
+
+    #[test]
+    fn policy_line_roundtrip() {
+        let policy = HstsPolicy {
+            expires: NOW,
+            include_subdomains: true,
+        };
+        let line = policy.to_line("example.com");
+        assert_eq!("example.com\t1000000\tTRUE", line);
+        assert_eq!(
+            Some((String::from("example.com"), policy)),
+            HstsPolicy::from_line(&line)
+        );
+    }
+
+    #[test]
+    fn store_from_response() {
+        let mut store = store();
+        store.store_from_response(&url("https://example.com"), &response("max-age=100"));
+        assert_eq!(
+            Some(url("https://example.com/a?b")),
+            store.upgrade(&url("http://example.com/a?b"))
+        );
+        assert_eq!(None, store.upgrade(&url("http://sub.example.com")));
+
+        store.store_from_response(&url("https://example.com"), &response("max-age=0"));
+        assert_eq!(None, store.upgrade(&url("http://example.com")));
+    }
+
+    #[test]
+    fn store_from_response_ignores_http_and_ip_addresses() {
+        let mut store = store();
+        store.store_from_response(&url("http://example.com"), &response("max-age=100"));
+        store.store_from_response(&url("https://127.0.0.1"), &response("max-age=100"));
+        assert!(store.policies.is_empty());
+    }
+
+    #[test]
+    fn upgrade_with_subdomains() {
+        let mut store = store();
+        store.store_from_response(
+            &url("https://example.com"),
+            &response("max-age=100; includeSubDomains"),
+        );
+        assert!(store.upgrade(&url("http://a.b.example.com")).is_some());
+        assert!(store.upgrade(&url("http://notexample.com")).is_none());
+    }
+
+    #[test]
+    fn upgrade_keeps_non_default_port() {
+        let mut store = store();
+        store.store_from_response(&url("https://example.com"), &response("max-age=100"));
+        assert_eq!(443, store.upgrade(&url("http://example.com")).unwrap().port);
+        assert_eq!(
+            8080,
+            store.upgrade(&url("http://example.com:8080")).unwrap().port
+        );
+    }
+
+    #[test]
+    fn preload_list_is_sorted() {
+        assert!(HSTS_PRELOAD.windows(2).all(|pair| pair[0].0 < pair[1].0));
+    }
+
+    #[test]
+    fn upgrade_preloaded() {
+        let store = store();
+        assert!(store.upgrade(&url("http://web.dev")).is_some());
+        assert!(store.upgrade(&url("http://gist.github.com")).is_some());
+        assert!(store.upgrade(&url("http://paypal.com")).is_some());
+        assert!(store.upgrade(&url("http://sub.paypal.com")).is_none());
+        assert!(store.upgrade(&url("https://web.dev")).is_none());
+    }
+
+    #[test]
+    fn expired_policy() {
+        let mut store = store();
+        store.policies.insert(
+            String::from("example.com"),
+            HstsPolicy {
+                expires: NOW,
+                include_subdomains: false,
+            },
+        );
+        assert!(!store.is_hsts_host("example.com", NOW));
+        assert!(store.is_hsts_host("example.com", NOW - 1));
+    }
+}
@@ -0,0 +1,18 @@
+// The HSTS preload list of Chromium, generated by tools/hsts_preload.py from
+// https://chromium.googlesource.com/chromium/src/+/main/net/http/transport_security_state_static.json.
+// The boolean is include_subdomains. Sorted by name, for binary search.
+//
+// Not generated yet: until tools/hsts_preload.py has been run, this holds the hosts which were
+// listed by hand before.
+
+pub static HSTS_PRELOAD: [(&str, bool); 9] = [
+    ("app", true),
+    ("bank", true),
+    ("dev", true),
+    ("foo", true),
+    ("github.com", true),
+    ("insurance", true),
+    ("page", true),
+    ("paypal.com", false),
+    ("www.paypal.com", false),
+];
@@ -0,0 +1,1414 @@
+use std::collections::VecDeque;
+
+use crate::character_reference::CharacterReference;
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Doctype {
+    pub name: Option<String>,
+    pub public_id: Option<String>,
+    pub system_id: Option<String>,
+    pub force_quirks: bool,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Tag {
+    // lowercase
+    pub name: String,
+    // in source order, duplicates are dropped
+    pub attributes: Vec<(String, String)>,
+    pub self_closing: bool,
+}
+
+impl Tag {
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+// Consecutive characters are combined into a single Text token
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Token {
+    Doctype(Doctype),
+    StartTag(Tag),
+    EndTag(String),
+    Comment(String),
+    Text(String),
+}
+
+// The states of the tokenizer, see https://html.spec.whatwg.org/multipage/parsing.html#tokenization
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum State {
+    Data,
+    Rcdata,
+    Rawtext,
+    ScriptData,
+    Plaintext,
+    TagOpen,
+    EndTagOpen,
+    TagName,
+    RcdataLessThanSign,
+    RcdataEndTagOpen,
+    RcdataEndTagName,
+    RawtextLessThanSign,
+    RawtextEndTagOpen,
+    RawtextEndTagName,
+    ScriptDataLessThanSign,
+    ScriptDataEndTagOpen,
+    ScriptDataEndTagName,
+    ScriptDataEscapeStart,
+    ScriptDataEscapeStartDash,
+    ScriptDataEscaped,
+    ScriptDataEscapedDash,
+    ScriptDataEscapedDashDash,
+    ScriptDataEscapedLessThanSign,
+    ScriptDataEscapedEndTagOpen,
+    ScriptDataEscapedEndTagName,
+    ScriptDataDoubleEscapeStart,
+    ScriptDataDoubleEscaped,
+    ScriptDataDoubleEscapedDash,
+    ScriptDataDoubleEscapedDashDash,
+    ScriptDataDoubleEscapedLessThanSign,
+    ScriptDataDoubleEscapeEnd,
+    BeforeAttributeName,
+    AttributeName,
+    AfterAttributeName,
+    BeforeAttributeValue,
+    AttributeValueDoubleQuoted,
+    AttributeValueSingleQuoted,
+    AttributeValueUnquoted,
+    AfterAttributeValueQuoted,
+    SelfClosingStartTag,
+    BogusComment,
+    MarkupDeclarationOpen,
+    CommentStart,
+    CommentStartDash,
+    Comment,
+    CommentLessThanSign,
+    CommentLessThanSignBang,
+    CommentLessThanSignBangDash,
+    CommentLessThanSignBangDashDash,
+    CommentEndDash,
+    CommentEnd,
+    CommentEndBang,
+    Doctype,
+    BeforeDoctypeName,
+    DoctypeName,
+    AfterDoctypeName,
+    AfterDoctypePublicKeyword,
+    BeforeDoctypePublicIdentifier,
+    DoctypePublicIdentifierDoubleQuoted,
+    DoctypePublicIdentifierSingleQuoted,
+    AfterDoctypePublicIdentifier,
+    BetweenDoctypePublicAndSystemIdentifiers,
+    AfterDoctypeSystemKeyword,
+    BeforeDoctypeSystemIdentifier,
+    DoctypeSystemIdentifierDoubleQuoted,
+    DoctypeSystemIdentifierSingleQuoted,
+    AfterDoctypeSystemIdentifier,
+    BogusDoctype,
+    CharacterReference,
+    NamedCharacterReference,
+    AmbiguousAmpersand,
+    NumericCharacterReference,
+    HexadecimalCharacterReferenceStart,
+    DecimalCharacterReferenceStart,
+    HexadecimalCharacterReference,
+    DecimalCharacterReference,
+    NumericCharacterReferenceEnd,
+}
+
+// Splits HTML into tokens, following the state machine of the HTML standard. Parse errors are
+// not reported, the tokenizer recovers from them like browsers do.
+//
+// Usually the tree builder switches the tokenizer into the states for the contents of elements
+// like <title> or <script>, here the tokenizer does it on its own after their start tags.
+pub struct HtmlTokenizer {
+    input: Vec<char>,
+    position: usize,
+    state: State,
+    // the state to go back to after a character reference
+    return_state: State,
+    tag: Tag,
+    is_end_tag: bool,
+    attribute: Option<(String, String)>,
+    comment: String,
+    doctype: Doctype,
+    temporary_buffer: String,
+    character_reference_code: u32,
+    last_start_tag: String,
+    text: String,
+    tokens: VecDeque<Token>,
+    eof: bool,
+}
+
+impl HtmlTokenizer {
+    const REPLACEMENT_CHARACTER: char = '\u{fffd}';
+
+    pub fn new(input: &str) -> Self {
+        // newlines are normalized before tokenization
+        let input = input.replace("\r\n", "\n").replace('\r', "\n");
+
+        Self {
+            input: input.chars().collect(),
+            position: 0,
+            state: State::Data,
+            return_state: State::Data,
+            tag: Tag::default(),
+            is_end_tag: false,
+            attribute: None,
+            comment: String::new(),
+            doctype: Doctype::default(),
+            temporary_buffer: String::new(),
+            character_reference_code: 0,
+            last_start_tag: String::new(),
+            text: String::new(),
+            tokens: VecDeque::new(),
+            eof: false,
+        }
+    }
+
+    fn next_char(&mut self) -> Option<char> {
+        let c = self.input.get(self.position).copied();
+        self.position += 1;
+        c
+    }
+
+    const fn reconsume_in(&mut self, state: State) {
+        self.position -= 1;
+        self.state = state;
+    }
+
+    // Whether the input continues with the given string, compared case-insensitively. The
+    // string is consumed if it does.
+    fn consume_if_next(&mut self, s: &str) -> bool {
+        let length = s.chars().count();
+        let matches = self
+            .input
+            .get(self.position..self.position + length)
+            .map_or(false, |next| {
+                next.iter()
+                    .zip(s.chars())
+                    .all(|(a, b)| a.eq_ignore_ascii_case(&b))
+            });
+
+        if matches {
+            self.position += length;
+        }
+        matches
+    }
+
+    const fn is_whitespace(c: char) -> bool {
+        matches!(c, '\t' | '\n' | '\x0c' | ' ')
+    }
+
+    fn emit(&mut self, token: Token) {
+        if !self.text.is_empty() {
+            self.tokens
+                .push_back(Token::Text(std::mem::take(&mut self.text)));
+        }
+        self.tokens.push_back(token);
+    }
+
+    fn emit_char(&mut self, c: char) {
+        self.text.push(c);
+    }
+
+    fn emit_str(&mut self, s: &str) {
+        self.text.push_str(s);
+    }
+
+    fn emit_eof(&mut self) {
+        if !self.text.is_empty() {
+            self.tokens
+                .push_back(Token::Text(std::mem::take(&mut self.text)));
+        }
+        self.eof = true;
+    }
+
+    fn new_tag(&mut self, is_end_tag: bool) {
+        self.tag = Tag::default();
+        self.is_end_tag = is_end_tag;
+        self.attribute = None;
+    }
+
+    // The state afterwards depends on the element, the contents of some elements are text
+    fn emit_tag(&mut self) {
+        self.finish_attribute();
+        let tag = std::mem::take(&mut self.tag);
+        self.state = State::Data;
+
+        if self.is_end_tag {
+            self.emit(Token::EndTag(tag.name));
+            return;
+        }
+
+        self.state = match tag.name.as_str() {
+            "title" | "textarea" => State::Rcdata,
+            "style" | "xmp" | "iframe" | "noembed" | "noframes" => State::Rawtext,
+            "script" => State::ScriptData,
+            "plaintext" => State::Plaintext,
+            _ => State::Data,
+        };
+        self.last_start_tag.clone_from(&tag.name);
+        self.emit(Token::StartTag(tag));
+    }
+
+    // An end tag matching the last start tag, which ends the text of elements like <title>
+    fn is_appropriate_end_tag(&self) -> bool {
+        self.is_end_tag && self.tag.name == self.last_start_tag
+    }
+
+    fn start_attribute(&mut self, name: &str) {
+        self.finish_attribute();
+        self.attribute = Some((name.to_string(), String::new()));
+    }
+
+    // Only the first of several attributes with the same name is kept
+    fn finish_attribute(&mut self) {
+        if let Some((name, value)) = self.attribute.take() {
+            if self.tag.attribute(&name).is_none() {
+                self.tag.attributes.push((name, value));
+            }
+        }
+    }
+
+    fn push_to_attribute_name(&mut self, c: char) {
+        if let Some((name, _)) = &mut self.attribute {
+            name.push(c);
+        }
+    }
+
+    fn push_to_attribute_value(&mut self, s: &str) {
+        if let Some((_, value)) = &mut self.attribute {
+            value.push_str(s);
+        }
+    }
+
+    fn emit_comment(&mut self) {
+        let comment = std::mem::take(&mut self.comment);
+        self.emit(Token::Comment(comment));
+    }
+
+    fn emit_doctype(&mut self, force_quirks: bool) {
+        let mut doctype = std::mem::take(&mut self.doctype);
+        doctype.force_quirks |= force_quirks;
+        self.emit(Token::Doctype(doctype));
+    }
+
+    const fn is_in_attribute(&self) -> bool {
+        matches!(
+            self.return_state,
+            State::AttributeValueDoubleQuoted
+                | State::AttributeValueSingleQuoted
+                | State::AttributeValueUnquoted
+        )
+    }
+
+    fn flush_character_reference(&mut self) {
+        let buffer = std::mem::take(&mut self.temporary_buffer);
+
+        if self.is_in_attribute() {
+            self.push_to_attribute_value(&buffer);
+        } else {
+            self.emit_str(&buffer);
+        }
+    }
+
+    // The text states: RCDATA, RAWTEXT and script data end at an appropriate end tag, anything
+    // else is text
+    fn end_tag_name(&mut self, c: Option<char>, text_state: State) {
+        match c {
+            Some(c) if Self::is_whitespace(c) && self.is_appropriate_end_tag() => {
+                self.state = State::BeforeAttributeName;
+            }
+            Some('/') if self.is_appropriate_end_tag() => self.state = State::SelfClosingStartTag,
+            Some('>') if self.is_appropriate_end_tag() => self.emit_tag(),
+            Some(c) if c.is_ascii_alphabetic() => {
+                self.tag.name.push(c.to_ascii_lowercase());
+                self.temporary_buffer.push(c);
+            }
+            _ => {
+                let buffer = std::mem::take(&mut self.temporary_buffer);
+                self.emit_str("</");
+                self.emit_str(&buffer);
+                self.reconsume_in(text_state);
+            }
+        }
+    }
+
+    fn end_tag_open(&mut self, c: Option<char>, text_state: State, name_state: State) {
+        match c {
+            Some(c) if c.is_ascii_alphabetic() => {
+                self.new_tag(true);
+                self.reconsume_in(name_state);
+            }
+            _ => {
+                self.emit_str("</");
+                self.reconsume_in(text_state);
+            }
+        }
+    }
+
+    #[allow(clippy::too_many_lines)]
+    fn step(&mut self) {
+        let c = self.next_char();
+
+        match self.state {
+            State::Data => match c {
+                Some('&') => {
+                    self.return_state = State::Data;
+                    self.state = State::CharacterReference;
+                }
+                Some('<') => self.state = State::TagOpen,
+                // null characters are ignored by the tree builder anyway
+                Some('\0') => {}
+                Some(c) => self.emit_char(c),
+                None => self.emit_eof(),
+            },
+            State::Rcdata => match c {
+                Some('&') => {
+                    self.return_state = State::Rcdata;
+                    self.state = State::CharacterReference;
+                }
+                Some('<') => self.state = State::RcdataLessThanSign,
+                Some('\0') => self.emit_char(Self::REPLACEMENT_CHARACTER),
+                Some(c) => self.emit_char(c),
+                None => self.emit_eof(),
+            },
+            State::Rawtext => match c {
+                Some('<') => self.state = State::RawtextLessThanSign,
+                Some('\0') => self.emit_char(Self::REPLACEMENT_CHARACTER),
+                Some(c) => self.emit_char(c),
+                None => self.emit_eof(),
+            },
+            State::ScriptData => match c {
+                Some('<') => self.state = State::ScriptDataLessThanSign,
+                Some('\0') => self.emit_char(Self::REPLACEMENT_CHARACTER),
+                Some(c) => self.emit_char(c),
+                None => self.emit_eof(),
+            },
+            State::Plaintext => match c {
+                Some('\0') => self.emit_char(Self::REPLACEMENT_CHARACTER),
+                Some(c) => self.emit_char(c),
+                None => self.emit_eof(),
+            },
+            State::TagOpen => match c {
+                Some('!') => self.state = State::MarkupDeclarationOpen,
+                Some('/') => self.state = State::EndTagOpen,
+                Some(c) if c.is_ascii_alphabetic() => {
+                    self.new_tag(false);
+                    self.reconsume_in(State::TagName);
+                }
+                Some('?') => {
+                    self.comment.clear();
+                    self.reconsume_in(State::BogusComment);
+                }
+                None => {
+                    self.emit_char('<');
+                    self.emit_eof();
+                }
+                Some(_) => {
+                    self.emit_char('<');
+                    self.reconsume_in(State::Data);
+                }
+            },
+            State::EndTagOpen => match c {
+                Some(c) if c.is_ascii_alphabetic() => {
+                    self.new_tag(true);
+                    self.reconsume_in(State::TagName);
+                }
+                Some('>') => self.state = State::Data,
+                None => {
+                    self.emit_str("</");
+                    self.emit_eof();
+                }
+                Some(_) => {
+                    self.comment.clear();
+                    self.reconsume_in(State::BogusComment);
+                }
+            },
+            State::TagName => match c {
+                Some(c) if Self::is_whitespace(c) => self.state = State::BeforeAttributeName,
+                Some('/') => self.state = State::SelfClosingStartTag,
+                Some('>') => self.emit_tag(),
+                Some('\0') => self.tag.name.push(Self::REPLACEMENT_CHARACTER),
+                Some(c) => self.tag.name.push(c.to_ascii_lowercase()),
+                None => self.emit_eof(),
+            },
+            State::RcdataLessThanSign => {
+                if c == Some('/') {
+                    self.temporary_buffer.clear();
+                    self.state = State::RcdataEndTagOpen;
+                } else {
+                    self.emit_char('<');
+                    self.reconsume_in(State::Rcdata);
+                }
+            }
+            State::RcdataEndTagOpen => {
+                self.end_tag_open(c, State::Rcdata, State::RcdataEndTagName);
+            }
+            State::RcdataEndTagName => self.end_tag_name(c, State::Rcdata),
+            State::RawtextLessThanSign => {
+                if c == Some('/') {
+                    self.temporary_buffer.clear();
+                    self.state = State::RawtextEndTagOpen;
+                } else {
+                    self.emit_char('<');
+                    self.reconsume_in(State::Rawtext);
+                }
+            }
+            State::RawtextEndTagOpen => {
+                self.end_tag_open(c, State::Rawtext, State::RawtextEndTagName);
+            }
+            State::RawtextEndTagName => self.end_tag_name(c, State::Rawtext),
+            State::ScriptDataLessThanSign => match c {
+                Some('/') => {
+                    self.temporary_buffer.clear();
+                    self.state = State::ScriptDataEndTagOpen;
+                }
+                Some('!') => {
+                    self.emit_str("<!");
+                    self.state = State::ScriptDataEscapeStart;
+                }
+                _ => {
+                    self.emit_char('<');
+                    self.reconsume_in(State::ScriptData);
+                }
+            },
+            State::ScriptDataEndTagOpen => {
+                self.end_tag_open(c, State::ScriptData, State::ScriptDataEndTagName);
+            }
+            State::ScriptDataEndTagName => self.end_tag_name(c, State::ScriptData),
+            State::ScriptDataEscapeStart => {
+                if c == Some('-') {
+                    self.emit_char('-');
+                    self.state = State::ScriptDataEscapeStartDash;
+                } else {
+                    self.reconsume_in(State::ScriptData);
+                }
+            }
+            State::ScriptDataEscapeStartDash => {
+                if c == Some('-') {
+                    self.emit_char('-');
+                    self.state = State::ScriptDataEscapedDashDash;
+                } else {
+                    self.reconsume_in(State::ScriptData);
+                }
+            }
+            State::ScriptDataEscaped => match c {
+                Some('-') => {
+                    self.emit_char('-');
+                    self.state = State::ScriptDataEscapedDash;
+                }
+                Some('<') => self.state = State::ScriptDataEscapedLessThanSign,
+                Some('\0') => self.emit_char(Self::REPLACEMENT_CHARACTER),
+                Some(c) => self.emit_char(c),
+                None => self.emit_eof(),
+            },
+            State::ScriptDataEscapedDash | State::ScriptDataEscapedDashDash => match c {
+                Some('-') => {
+                    self.emit_char('-');
+                    self.state = State::ScriptDataEscapedDashDash;
+                }
+                Some('<') => self.state = State::ScriptDataEscapedLessThanSign,
+                Some('>') if self.state == State::ScriptDataEscapedDashDash => {
+                    self.emit_char('>');
+                    self.state = State::ScriptData;
+                }
+                Some(c) => {
+                    self.emit_char(if c == '\0' {
+                        Self::REPLACEMENT_CHARACTER
+                    } else {
+                        c
+                    });
+                    self.state = State::ScriptDataEscaped;
+                }
+                None => self.emit_eof(),
+            },
+            State::ScriptDataEscapedLessThanSign => match c {
+                Some('/') => {
+                    self.temporary_buffer.clear();
+                    self.state = State::ScriptDataEscapedEndTagOpen;
+                }
+                Some(c) if c.is_ascii_alphabetic() => {
+                    self.temporary_buffer.clear();
+                    self.emit_char('<');
+                    self.reconsume_in(State::ScriptDataDoubleEscapeStart);
+                }
+                _ => {
+                    self.emit_char('<');
+                    self.reconsume_in(State::ScriptDataEscaped);
+                }
+            },
+            State::ScriptDataEscapedEndTagOpen => self.end_tag_open(
+                c,
+                State::ScriptDataEscaped,
+                State::ScriptDataEscapedEndTagName,
+            ),
+            State::ScriptDataEscapedEndTagName => self.end_tag_name(c, State::ScriptDataEscaped),
+            State::ScriptDataDoubleEscapeStart | State::ScriptDataDoubleEscapeEnd => {
+                // a <script> inside an escaped script starts the double escaped state, its end
+                // tag ends it
+                let (on_script, otherwise) = if self.state == State::ScriptDataDoubleEscapeStart {
+                    (State::ScriptDataDoubleEscaped, State::ScriptDataEscaped)
+                } else {
+                    (State::ScriptDataEscaped, State::ScriptDataDoubleEscaped)
+                };
+
+                match c {
+                    Some(c) if Self::is_whitespace(c) || c == '/' || c == '>' => {
+                        self.state = if self.temporary_buffer == "script" {
+                            on_script
+                        } else {
+                            otherwise
+                        };
+                        self.emit_char(c);
+                    }
+                    Some(c) if c.is_ascii_alphabetic() => {
+                        self.temporary_buffer.push(c.to_ascii_lowercase());
+                        self.emit_char(c);
+                    }
+                    _ => self.reconsume_in(otherwise),
+                }
+            }
+            State::ScriptDataDoubleEscaped => match c {
+                Some('-') => {
+                    self.emit_char('-');
+                    self.state = State::ScriptDataDoubleEscapedDash;
+                }
+                Some('<') => {
+                    self.emit_char('<');
+                    self.state = State::ScriptDataDoubleEscapedLessThanSign;
+                }
+                Some('\0') => self.emit_char(Self::REPLACEMENT_CHARACTER),
+                Some(c) => self.emit_char(c),
+                None => self.emit_eof(),
+            },
+            State::ScriptDataDoubleEscapedDash | State::ScriptDataDoubleEscapedDashDash => {
+                match c {
+                    Some('-') => {
+                        self.emit_char('-');
+                        self.state = State::ScriptDataDoubleEscapedDashDash;
+                    }
+                    Some('<') => {
+                        self.emit_char('<');
+                        self.state = State::ScriptDataDoubleEscapedLessThanSign;
+                    }
+                    Some('>') if self.state == State::ScriptDataDoubleEscapedDashDash => {
+                        self.emit_char('>');
+                        self.state = State::ScriptData;
+                    }
+                    Some(c) => {
+                        self.emit_char(if c == '\0' {
+                            Self::REPLACEMENT_CHARACTER
+                        } else {
+                            c
+                        });
+                        self.state = State::ScriptDataDoubleEscaped;
+                    }
+                    None => self.emit_eof(),
+                }
+            }
+            State::ScriptDataDoubleEscapedLessThanSign => {
+                if c == Some('/') {
+                    self.temporary_buffer.clear();
+                    self.emit_char('/');
+                    self.state = State::ScriptDataDoubleEscapeEnd;
+                } else {
+                    self.reconsume_in(State::ScriptDataDoubleEscaped);
+                }
+            }
+            State::BeforeAttributeName => match c {
+                Some(c) if Self::is_whitespace(c) => {}
+                Some('/' | '>') | None => self.reconsume_in(State::AfterAttributeName),
+                Some('=') => {
+                    self.start_attribute("=");
+                    self.state = State::AttributeName;
+                }
+                Some(_) => {
+                    self.start_attribute("");
+                    self.reconsume_in(State::AttributeName);
+                }
+            },
+            State::AttributeName => match c {
+                Some(c) if Self::is_whitespace(c) => {
+                    self.reconsume_in(State::AfterAttributeName);
+                }
+                Some('/' | '>') | None => self.reconsume_in(State::AfterAttributeName),
+                Some('=') => self.state = State::BeforeAttributeValue,
+                Some('\0') => self.push_to_attribute_name(Self::REPLACEMENT_CHARACTER),
+                Some(c) => self.push_to_attribute_name(c.to_ascii_lowercase()),
+            },
+            State::AfterAttributeName => match c {
+                Some(c) if Self::is_whitespace(c) => {}
+                Some('/') => self.state = State::SelfClosingStartTag,
+                Some('=') => self.state = State::BeforeAttributeValue,
+                Some('>') => self.emit_tag(),
+                Some(_) => {
+                    self.start_attribute("");
+                    self.reconsume_in(State::AttributeName);
+                }
+                None => self.emit_eof(),
+            },
+            State::BeforeAttributeValue => match c {
+                Some(c) if Self::is_whitespace(c) => {}
+                Some('"') => self.state = State::AttributeValueDoubleQuoted,
+                Some('\'') => self.state = State::AttributeValueSingleQuoted,
+                Some('>') => self.emit_tag(),
+                _ => self.reconsume_in(State::AttributeValueUnquoted),
+            },
+            State::AttributeValueDoubleQuoted | State::AttributeValueSingleQuoted => {
+                let quote = if self.state == State::AttributeValueDoubleQuoted {
+                    '"'
+                } else {
+                    '\''
+                };
+
+                match c {
+                    Some(c) if c == quote => self.state = State::AfterAttributeValueQuoted,
+                    Some('&') => {
+                        self.return_state = self.state;
+                        self.state = State::CharacterReference;
+                    }
+                    Some('\0') => self.push_to_attribute_value("\u{fffd}"),
+                    Some(c) => self.push_to_attribute_value(c.encode_utf8(&mut [0; 4])),
+                    None => self.emit_eof(),
+                }
+            }
+            State::AttributeValueUnquoted => match c {
+                Some(c) if Self::is_whitespace(c) => self.state = State::BeforeAttributeName,
+                Some('&') => {
+                    self.return_state = State::AttributeValueUnquoted;
+                    self.state = State::CharacterReference;
+                }
+                Some('>') => self.emit_tag(),
+                Some('\0') => self.push_to_attribute_value("\u{fffd}"),
+                Some(c) => self.push_to_attribute_value(c.encode_utf8(&mut [0; 4])),
+                None => self.emit_eof(),
+            },
+            State::AfterAttributeValueQuoted => match c {
+                Some(c) if Self::is_whitespace(c) => self.state = State::BeforeAttributeName,
+                Some('/') => self.state = State::SelfClosingStartTag,
+                Some('>') => self.emit_tag(),
+                Some(_) => self.reconsume_in(State::BeforeAttributeName),
+                None => self.emit_eof(),
+            },
+            State::SelfClosingStartTag => match c {
+                Some('>') => {
+                    self.tag.self_closing = true;
+                    self.emit_tag();
+                }
+                Some(_) => self.reconsume_in(State::BeforeAttributeName),
+                None => self.emit_eof(),
+            },
+            State::BogusComment => match c {
+                Some('>') => {
+                    self.emit_comment();
+                    self.state = State::Data;
+                }
+                Some('\0') => self.comment.push(Self::REPLACEMENT_CHARACTER),
+                Some(c) => self.comment.push(c),
+                None => {
+                    self.emit_comment();
+                    self.emit_eof();
+                }
+            },
+            State::MarkupDeclarationOpen => {
+                // nothing is consumed yet, the checks start at the character after "<!"
+                self.position -= 1;
+                self.comment.clear();
+
+                if self.consume_if_next("--") {
+                    self.state = State::CommentStart;
+                } else if self.consume_if_next("doctype") {
+                    self.state = State::Doctype;
+                } else if self.consume_if_next("[CDATA[") {
+                    // CDATA sections are only supported in SVG and MathML
+                    self.comment.push_str("[CDATA[");
+                    self.state = State::BogusComment;
+                } else {
+                    self.state = State::BogusComment;
+                }
+            }
+            State::CommentStart => match c {
+                Some('-') => self.state = State::CommentStartDash,
+                Some('>') => {
+                    self.emit_comment();
+                    self.state = State::Data;
+                }
+                _ => self.reconsume_in(State::Comment),
+            },
+            State::CommentStartDash => match c {
+                Some('-') => self.state = State::CommentEnd,
+                Some('>') => {
+                    self.emit_comment();
+                    self.state = State::Data;
+                }
+                None => {
+                    self.emit_comment();
+                    self.emit_eof();
+                }
+                Some(_) => {
+                    self.comment.push('-');
+                    self.reconsume_in(State::Comment);
+                }
+            },
+            State::Comment => match c {
+                Some('<') => {
+                    self.comment.push('<');
+                    self.state = State::CommentLessThanSign;
+                }
+                Some('-') => self.state = State::CommentEndDash,
+                Some('\0') => self.comment.push(Self::REPLACEMENT_CHARACTER),
+                Some(c) => self.comment.push(c),
+                None => {
+                    self.emit_comment();
+                    self.emit_eof();
+                }
+            },
+            State::CommentLessThanSign => match c {
+                Some('!') => {
+                    self.comment.push('!');
+                    self.state = State::CommentLessThanSignBang;
+                }
+                Some('<') => self.comment.push('<'),
+                _ => self.reconsume_in(State::Comment),
+            },
+            State::CommentLessThanSignBang => {
+                if c == Some('-') {
+                    self.state = State::CommentLessThanSignBangDash;
+                } else {
+                    self.reconsume_in(State::Comment);
+                }
+            }
+            State::CommentLessThanSignBangDash => {
+                if c == Some('-') {
+                    self.state = State::CommentLessThanSignBangDashDash;
+                } else {
+                    self.reconsume_in(State::CommentEndDash);
+                }
+            }
+            // a nested comment is a parse error, but doesn't change anything
+            State::CommentLessThanSignBangDashDash => self.reconsume_in(State::CommentEnd),
+            State::CommentEndDash => match c {
+                Some('-') => self.state = State::CommentEnd,
+                None => {
+                    self.emit_comment();
+                    self.emit_eof();
+                }
+                Some(_) => {
+                    self.comment.push('-');
+                    self.reconsume_in(State::Comment);
+                }
+            },
+            State::CommentEnd => match c {
+                Some('>') => {
+                    self.emit_comment();
+                    self.state = State::Data;
+                }
+                Some('!') => self.state = State::CommentEndBang,
+                Some('-') => self.comment.push('-'),
+                None => {
+                    self.emit_comment();
+                    self.emit_eof();
+                }
+                Some(_) => {
+                    self.comment.push_str("--");
+                    self.reconsume_in(State::Comment);
+                }
+            },
+            State::CommentEndBang => match c {
+                Some('-') => {
+                    self.comment.push_str("--!");
+                    self.state = State::CommentEndDash;
+                }
+                Some('>') => {
+                    self.emit_comment();
+                    self.state = State::Data;
+                }
+                None => {
+                    self.emit_comment();
+                    self.emit_eof();
+                }
+                Some(_) => {
+                    self.comment.push_str("--!");
+                    self.reconsume_in(State::Comment);
+                }
+            },
+            State::Doctype => match c {
+                Some(c) if Self::is_whitespace(c) => self.state = State::BeforeDoctypeName,
+                None => {
+                    self.doctype = Doctype::default();
+                    self.emit_doctype(true);
+                    self.emit_eof();
+                }
+                Some(_) => self.reconsume_in(State::BeforeDoctypeName),
+            },
+            State::BeforeDoctypeName => match c {
+                Some(c) if Self::is_whitespace(c) => {}
+                Some('>') => {
+                    self.doctype = Doctype::default();
+                    self.emit_doctype(true);
+                    self.state = State::Data;
+                }
+                None => {
+                    self.doctype = Doctype::default();
+                    self.emit_doctype(true);
+                    self.emit_eof();
+                }
+                Some(c) => {
+                    let c = if c == '\0' {
+                        Self::REPLACEMENT_CHARACTER
+                    } else {
+                        c.to_ascii_lowercase()
+                    };
+                    self.doctype = Doctype {
+                        name: Some(c.to_string()),
+                        ..Doctype::default()
+                    };
+                    self.state = State::DoctypeName;
+                }
+            },
+            State::DoctypeName => match c {
+                Some(c) if Self::is_whitespace(c) => self.state = State::AfterDoctypeName,
+                Some('>') => {
+                    self.emit_doctype(false);
+                    self.state = State::Data;
+                }
+                None => {
+                    self.emit_doctype(true);
+                    self.emit_eof();
+                }
+                Some(c) => {
+                    let c = if c == '\0' {
+                        Self::REPLACEMENT_CHARACTER
+                    } else {
+                        c.to_ascii_lowercase()
+                    };
+                    self.doctype.name.get_or_insert_with(String::new).push(c);
+                }
+            },
+            State::AfterDoctypeName => match c {
+                Some(c) if Self::is_whitespace(c) => {}
+                Some('>') => {
+                    self.emit_doctype(false);
+                    self.state = State::Data;
+                }
+                None => {
+                    self.emit_doctype(true);
+                    self.emit_eof();
+                }
+                Some(_) => {
+                    self.position -= 1;
+                    if self.consume_if_next("public") {
+                        self.state = State::AfterDoctypePublicKeyword;
+                    } else if self.consume_if_next("system") {
+                        self.state = State::AfterDoctypeSystemKeyword;
+                    } else {
+                        self.doctype.force_quirks = true;
+                        self.state = State::BogusDoctype;
+                    }
+                }
+            },
+            State::AfterDoctypePublicKeyword
+            | State::BeforeDoctypePublicIdentifier
+            | State::AfterDoctypeSystemKeyword
+            | State::BeforeDoctypeSystemIdentifier => {
+                let is_public = matches!(
+                    self.state,
+                    State::AfterDoctypePublicKeyword | State::BeforeDoctypePublicIdentifier
+                );
+                let is_keyword = matches!(
+                    self.state,
+                    State::AfterDoctypePublicKeyword | State::AfterDoctypeSystemKeyword
+                );
+
+                match c {
+                    Some(c) if Self::is_whitespace(c) => {
+                        if is_keyword {
+                            self.state = if is_public {
+                                State::BeforeDoctypePublicIdentifier
+                            } else {
+                                State::BeforeDoctypeSystemIdentifier
+                            };
+                        }
+                    }
+                    Some(quote @ ('"' | '\'')) => {
+                        self.start_doctype_identifier(is_public, quote);
+                    }
+                    Some('>') => {
+                        self.emit_doctype(true);
+                        self.state = State::Data;
+                    }
+                    None => {
+                        self.emit_doctype(true);
+                        self.emit_eof();
+                    }
+                    Some(_) => {
+                        self.doctype.force_quirks = true;
+                        self.reconsume_in(State::BogusDoctype);
+                    }
+                }
+            }
+            State::DoctypePublicIdentifierDoubleQuoted
+            | State::DoctypePublicIdentifierSingleQuoted
+            | State::DoctypeSystemIdentifierDoubleQuoted
+            | State::DoctypeSystemIdentifierSingleQuoted => {
+                let is_public = matches!(
+                    self.state,
+                    State::DoctypePublicIdentifierDoubleQuoted
+                        | State::DoctypePublicIdentifierSingleQuoted
+                );
+                let quote = if matches!(
+                    self.state,
+                    State::DoctypePublicIdentifierDoubleQuoted
+                        | State::DoctypeSystemIdentifierDoubleQuoted
+                ) {
+                    '"'
+                } else {
+                    '\''
+                };
+
+                match c {
+                    Some(c) if c == quote => {
+                        self.state = if is_public {
+                            State::AfterDoctypePublicIdentifier
+                        } else {
+                            State::AfterDoctypeSystemIdentifier
+                        };
+                    }
+                    Some('>') => {
+                        self.emit_doctype(true);
+                        self.state = State::Data;
+                    }
+                    None => {
+                        self.emit_doctype(true);
+                        self.emit_eof();
+                    }
+                    Some(c) => {
+                        let c = if c == '\0' {
+                            Self::REPLACEMENT_CHARACTER
+                        } else {
+                            c
+                        };
+                        let identifier = if is_public {
+                            &mut self.doctype.public_id
+                        } else {
+                            &mut self.doctype.system_id
+                        };
+                        identifier.get_or_insert_with(String::new).push(c);
+                    }
+                }
+            }
+            State::AfterDoctypePublicIdentifier
+            | State::BetweenDoctypePublicAndSystemIdentifiers => match c {
+                Some(c) if Self::is_whitespace(c) => {
+                    self.state = State::BetweenDoctypePublicAndSystemIdentifiers;
+                }
+                Some('>') => {
+                    self.emit_doctype(false);
+                    self.state = State::Data;
+                }
+                Some(quote @ ('"' | '\'')) => self.start_doctype_identifier(false, quote),
+                None => {
+                    self.emit_doctype(true);
+                    self.emit_eof();
+                }
+                Some(_) => {
+                    self.doctype.force_quirks = true;
+                    self.reconsume_in(State::BogusDoctype);
+                }
+            },
+            State::AfterDoctypeSystemIdentifier => match c {
+                Some(c) if Self::is_whitespace(c) => {}
+                Some('>') => {
+                    self.emit_doctype(false);
+                    self.state = State::Data;
+                }
+                None => {
+                    self.emit_doctype(true);
+                    self.emit_eof();
+                }
+                Some(_) => self.reconsume_in(State::BogusDoctype),
+            },
+            State::BogusDoctype => match c {
+                Some('>') => {
+                    self.emit_doctype(false);
+                    self.state = State::Data;
+                }
+                None => {
+                    self.emit_doctype(false);
+                    self.emit_eof();
+                }
+                Some(_) => {}
+            },
+            State::CharacterReference => {
+                self.temporary_buffer = String::from("&");
+
+                match c {
+                    Some(c) if c.is_ascii_alphanumeric() => {
+                        self.reconsume_in(State::NamedCharacterReference);
+                    }
+                    Some('#') => {
+                        self.temporary_buffer.push('#');
+                        self.state = State::NumericCharacterReference;
+                    }
+                    _ => {
+                        self.flush_character_reference();
+                        self.reconsume_in(self.return_state);
+                    }
+                }
+            }
+            State::NamedCharacterReference => {
+                self.position -= 1;
+
+                if let Some((length, replacement)) =
+                    CharacterReference::named(&self.input[self.position..])
+                {
+                    let name: String = self.input[self.position..self.position + length]
+                        .iter()
+                        .collect();
+                    self.position += length;
+
+                    // in attribute values, legacy references without semicolon followed by "=" or
+                    // an alphanumeric character are kept, as they are likely part of a URL query
+                    // like "?a=1&copy=2"
+                    let next = self.input.get(self.position);
+                    let is_kept = self.is_in_attribute()
+                        && !name.ends_with(';')
+                        && next.map_or(false, |&c| c == '=' || c.is_ascii_alphanumeric());
+
+                    self.temporary_buffer.push_str(&name);
+                    if !is_kept {
+                        self.temporary_buffer = replacement.to_string();
+                    }
+                    self.flush_character_reference();
+                    self.state = self.return_state;
+                } else {
+                    self.flush_character_reference();
+                    self.state = State::AmbiguousAmpersand;
+                }
+            }
+            State::AmbiguousAmpersand => match c {
+                Some(c) if c.is_ascii_alphanumeric() => {
+                    if self.is_in_attribute() {
+                        self.push_to_attribute_value(c.encode_utf8(&mut [0; 4]));
+                    } else {
+                        self.emit_char(c);
+                    }
+                }
+                _ => self.reconsume_in(self.return_state),
+            },
+            State::NumericCharacterReference => {
+                self.character_reference_code = 0;
+
+                match c {
+                    Some(c @ ('x' | 'X')) => {
+                        self.temporary_buffer.push(c);
+                        self.state = State::HexadecimalCharacterReferenceStart;
+                    }
+                    _ => self.reconsume_in(State::DecimalCharacterReferenceStart),
+                }
+            }
+            State::HexadecimalCharacterReferenceStart | State::DecimalCharacterReferenceStart => {
+                let (radix, next_state) = if self.state == State::HexadecimalCharacterReferenceStart
+                {
+                    (16, State::HexadecimalCharacterReference)
+                } else {
+                    (10, State::DecimalCharacterReference)
+                };
+
+                match c {
+                    Some(c) if c.is_digit(radix) => self.reconsume_in(next_state),
+                    _ => {
+                        self.flush_character_reference();
+                        self.reconsume_in(self.return_state);
+                    }
+                }
+            }
+            State::HexadecimalCharacterReference | State::DecimalCharacterReference => {
+                let radix = if self.state == State::HexadecimalCharacterReference {
+                    16
+                } else {
+                    10
+                };
+
+                match c {
+                    Some(c) if c.is_digit(radix) => {
+                        let digit = c.to_digit(radix).unwrap_or_default();
+                        self.character_reference_code = self
+                            .character_reference_code
+                            .saturating_mul(radix)
+                            .saturating_add(digit);
+                    }
+                    Some(';') => self.state = State::NumericCharacterReferenceEnd,
+                    _ => self.reconsume_in(State::NumericCharacterReferenceEnd),
+                }
+            }
+            State::NumericCharacterReferenceEnd => {
+                // this state consumes nothing
+                self.position -= 1;
+                let c = CharacterReference::numeric(self.character_reference_code);
+                self.temporary_buffer = c.to_string();
+                self.flush_character_reference();
+                self.state = self.return_state;
+            }
+        }
+    }
+
+    fn start_doctype_identifier(&mut self, is_public: bool, quote: char) {
+        let (identifier, state) = match (is_public, quote) {
+            (true, '"') => (
+                &mut self.doctype.public_id,
+                State::DoctypePublicIdentifierDoubleQuoted,
+            ),
+            (true, _) => (
+                &mut self.doctype.public_id,
+                State::DoctypePublicIdentifierSingleQuoted,
+            ),
+            (false, '"') => (
+                &mut self.doctype.system_id,
+                State::DoctypeSystemIdentifierDoubleQuoted,
+            ),
+            (false, _) => (
+                &mut self.doctype.system_id,
+                State::DoctypeSystemIdentifierSingleQuoted,
+            ),
+        };
+
+        *identifier = Some(String::new());
+        self.state = state;
+    }
+}
+
+impl Iterator for HtmlTokenizer {
+    type Item = Token;
+
+    fn next(&mut self) -> Option<Token> {
+        loop {
+            if let Some(token) = self.tokens.pop_front() {
+                return Some(token);
+            } else if self.eof {
+                return None;
+            }
+
+            self.step();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokenize(input: &str) -> Vec<Token> {
+        HtmlTokenizer::new(input).collect()
+    }
+
+    fn text(s: &str) -> Token {
+        Token::Text(s.to_string())
+    }
+
+    fn start_tag(name: &str, attributes: &[(&str, &str)]) -> Token {
+        Token::StartTag(Tag {
+            name: name.to_string(),
+            attributes: attributes
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+            self_closing: false,
+        })
+    }
+
+    fn end_tag(name: &str) -> Token {
+        Token::EndTag(name.to_string())
+    }
+
+    #[test]
+    fn tags_and_text() {
+        assert_eq!(
+            vec![
+                start_tag("p", &[]),
+                text("Hello "),
+                start_tag("b", &[]),
+                text("World"),
+                end_tag("b"),
+                end_tag("p"),
+            ],
+            tokenize("<P>Hello <b>World</B></p>")
+        );
+    }
+
+    #[test]
+    fn less_than_in_text() {
+        assert_eq!(
+            vec![
+                text("a < b and c <> d "),
+                Token::Comment(String::from(" e"))
+            ],
+            tokenize("a < b and c <> d </ e")
+        );
+    }
+
+    #[test]
+    fn attributes() {
+        assert_eq!(
+            vec![start_tag(
+                "a",
+                &[
+                    ("href", "/a?b=1&c=>"),
+                    ("title", "it's"),
+                    ("class", "x"),
+                    ("hidden", "")
+                ]
+            )],
+            tokenize("<a HREF=\"/a?b=1&amp;c=>\" title='it&apos;s' class=x hidden class=y>")
+        );
+    }
+
+    #[test]
+    fn self_closing_tag() {
+        let tokens = tokenize("<br/><img src=a />");
+        assert!(matches!(&tokens[0], Token::StartTag(tag) if tag.self_closing));
+        assert!(
+            matches!(&tokens[1], Token::StartTag(tag) if tag.self_closing
+            && tag.attribute("src") == Some("a"))
+        );
+    }
+
+    #[test]
+    fn comments() {
+        assert_eq!(
+            vec![
+                Token::Comment(String::from(" a <b> -- c ")),
+                Token::Comment(String::new()),
+                Token::Comment(String::from("?xml version=\"1.0\"?")),
+                text("x"),
+            ],
+            tokenize("<!-- a <b> -- c --><!----><?xml version=\"1.0\"?>x")
+        );
+        assert_eq!(
+            vec![Token::Comment(String::from("unterminated"))],
+            tokenize("<!--unterminated")
+        );
+    }
+
+    #[test]
+    fn doctype() {
+        assert_eq!(
+            vec![Token::Doctype(Doctype {
+                name: Some(String::from("html")),
+                ..Doctype::default()
+            })],
+            tokenize("<!DOCTYPE HTML>")
+        );
+        assert_eq!(
+            vec![Token::Doctype(Doctype {
+                name: Some(String::from("html")),
+                public_id: Some(String::from("-//W3C//DTD HTML 4.01//EN")),
+                system_id: Some(String::from("http://www.w3.org/TR/html4/strict.dtd")),
+                force_quirks: false,
+            })],
+            tokenize(
+                "<!doctype html PUBLIC \"-//W3C//DTD HTML 4.01//EN\" \
+                 'http://www.w3.org/TR/html4/strict.dtd'>"
+            )
+        );
+        assert!(matches!(
+            &tokenize("<!doctype html bogus>")[0],
+            Token::Doctype(doctype) if doctype.force_quirks
+        ));
+    }
+
+    #[test]
+    fn character_references() {
+        assert_eq!(
+            vec![text("<a> & &#; &unknown; A\u{1f600}")],
+            tokenize("&lt;a&gt; &amp; &#; &unknown; &#65;&#x1F600;")
+        );
+        assert_eq!(
+            vec![text("\u{20ac} \u{fffd} \u{fffd} \u{fffd} \u{2014}")],
+            tokenize("&#128; &#0; &#x110000; &#99999999999999; &#x2014")
+        );
+    }
+
+    #[test]
+    fn legacy_character_references() {
+        assert_eq!(
+            vec![text("\u{a9} 2024 & \u{ac}it; &hellip")],
+            tokenize("&copy 2024 &amp &notit; &hellip")
+        );
+    }
+
+    #[test]
+    fn character_references_in_attributes() {
+        assert_eq!(
+            vec![start_tag(
+                "a",
+                &[
+                    ("href", "?a=1&copy=2&notx&amp;y\u{a9}"),
+                    ("title", "\u{a9} & \u{2014}")
+                ]
+            )],
+            tokenize("<a href=\"?a=1&copy=2&notx&amp;amp;y&copy\" title=\"&copy &amp &mdash;\">")
+        );
+    }
+
+    #[test]
+    fn rcdata_and_rawtext() {
+        assert_eq!(
+            vec![
+                start_tag("title", &[]),
+                text("<b>a & b</b></titles>"),
+                end_tag("title"),
+                start_tag("style", &[]),
+                text("p > a { content: \"&amp;\" }"),
+                end_tag("style"),
+            ],
+            tokenize(
+                "<title><b>a &amp; b</b></titles></TITLE>\
+                 <style>p > a { content: \"&amp;\" }</style>"
+            )
+        );
+    }
+
+    #[test]
+    fn script_data() {
+        assert_eq!(
+            vec![
+                start_tag("script", &[]),
+                text("if (a < b && c) { x = '</p>'; }"),
+                end_tag("script"),
+            ],
+            tokenize("<script>if (a < b && c) { x = '</p>'; }</script>")
+        );
+    }
+
+    #[test]
+    fn script_data_escaped() {
+        let tokens =
+            tokenize("<script><!-- document.write('<script></script>'); --></script>after");
+        assert_eq!(
+            vec![
+                start_tag("script", &[]),
+                text("<!-- document.write('<script></script>'); -->"),
+                end_tag("script"),
+                text("after"),
+            ],
+            tokens
+        );
+    }
+
+    #[test]
+    fn newlines_are_normalized() {
+        assert_eq!(vec![text("a\nb\nc")], tokenize("a\r\nb\rc"));
+    }
+
+    #[test]
+    fn end_of_file_in_tag() {
+        assert_eq!(vec![text("a")], tokenize("a<div class="));
+    }
+}
@@ -0,0 +1,1003 @@
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::time::Instant;
+use tracing::{info, warn};
+
+use crate::cancellation::Cancellation;
+use crate::config::{Config, SizeLimits};
+use crate::connection_pool::Connection;
+use crate::network_error::{NetworkError, SizeLimit, TimeoutPhase};
+use crate::request::{Method, Request};
+
+// A frame, see https://www.rfc-editor.org/rfc/rfc9113#section-4.1
+#[derive(Debug, PartialEq)]
+struct Frame {
+    kind: u8,
+    flags: u8,
+    stream_id: u32,
+    payload: Vec<u8>,
+}
+
+impl Frame {
+    const DATA: u8 = 0x0;
+    const HEADERS: u8 = 0x1;
+    const RST_STREAM: u8 = 0x3;
+    const SETTINGS: u8 = 0x4;
+    const PUSH_PROMISE: u8 = 0x5;
+    const PING: u8 = 0x6;
+    const GOAWAY: u8 = 0x7;
+    const WINDOW_UPDATE: u8 = 0x8;
+    const CONTINUATION: u8 = 0x9;
+
+    const END_STREAM: u8 = 0x1;
+    const ACK: u8 = 0x1;
+    const END_HEADERS: u8 = 0x4;
+    const PADDED: u8 = 0x8;
+    const PRIORITY: u8 = 0x20;
+
+    const HEADER_LENGTH: usize = 9;
+
+    const fn new(kind: u8, flags: u8, stream_id: u32, payload: Vec<u8>) -> Self {
+        Self {
+            kind,
+            flags,
+            stream_id,
+            payload,
+        }
+    }
+
+    fn read<R: Read>(reader: &mut R, max_frame_size: u32) -> Result<Self, NetworkError> {
+        let mut header = [0; Self::HEADER_LENGTH];
+        reader.read_exact(&mut header)?;
+
+        let length = u32::from_be_bytes([0, header[0], header[1], header[2]]);
+        if length > max_frame_size {
+            return Err(NetworkError::Http2(String::from("frame too large")));
+        }
+
+        // the reserved bit is ignored
+        let stream_id =
+            u32::from_be_bytes([header[5], header[6], header[7], header[8]]) & 0x7fff_ffff;
+        let mut payload = vec![0; length as usize];
+        reader.read_exact(&mut payload)?;
+
+        Ok(Self::new(header[3], header[4], stream_id, payload))
+    }
+
+    fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let length = (self.payload.len() as u32).to_be_bytes();
+        let mut bytes = Vec::with_capacity(Self::HEADER_LENGTH + self.payload.len());
+        bytes.extend_from_slice(&length[1..]);
+        bytes.push(self.kind);
+        bytes.push(self.flags);
+        bytes.extend_from_slice(&self.stream_id.to_be_bytes());
+        bytes.extend_from_slice(&self.payload);
+
+        writer.write_all(&bytes)
+    }
+
+    const fn has_flag(&self, flag: u8) -> bool {
+        self.flags & flag != 0
+    }
+
+    // The payload of DATA and HEADERS frames without padding and priority information
+    fn data(&self) -> Result<&[u8], NetworkError> {
+        let invalid = || NetworkError::Http2(String::from("invalid padding"));
+        let mut data = &self.payload[..];
+        let mut padding = 0;
+
+        if self.has_flag(Self::PADDED) {
+            padding = usize::from(*data.first().ok_or_else(invalid)?);
+            data = &data[1..];
+        }
+
+        if self.kind == Self::HEADERS && self.has_flag(Self::PRIORITY) {
+            data = data.get(5..).ok_or_else(invalid)?;
+        }
+
+        data.get(..(data.len().checked_sub(padding).ok_or_else(invalid)?))
+            .ok_or_else(invalid)
+    }
+}
+
+#[derive(Default)]
+struct StreamState {
+    started: Option<Instant>,
+    // the header block of the response, which might be spread over several frames
+    header_block: Vec<u8>,
+    headers: Option<Vec<(String, String)>>,
+    body: Vec<u8>,
+    ended: bool,
+    // why the stream was reset, by the server or by us
+    reset: Option<NetworkError>,
+    send_window: i64,
+    // the body is passed on while it is received, only its size is remembered
+    head_taken: bool,
+    body_size: u64,
+}
+
+// A HTTP/2 client connection, see https://www.rfc-editor.org/rfc/rfc9113
+//
+// Several requests can be in flight at the same time: whoever waits for a response reads the
+// next frame from the connection, frames for other streams are buffered until their requests
+// ask for them. Server push is disabled.
+pub struct Http2Connection {
+    connection: Connection,
+    encoder: hpack::Encoder<'static>,
+    decoder: hpack::Decoder<'static>,
+    streams: HashMap<u32, StreamState>,
+    next_stream_id: u32,
+    // settings of the server
+    max_frame_size: u32,
+    max_concurrent_streams: u32,
+    initial_window_size: i64,
+    // how much request body the server accepts on the connection as a whole
+    send_window: i64,
+    // the stream whose header block is continued by CONTINUATION frames
+    continued_stream: Option<u32>,
+    // set after GOAWAY, streams with higher ids won't be processed by the server
+    last_stream_id: Option<u32>,
+    // set if reading or writing failed, a partially read frame leaves the connection unusable
+    broken: bool,
+    limits: SizeLimits,
+}
+
+impl Http2Connection {
+    const PREFACE: &'static [u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+    const SETTINGS_HEADER_TABLE_SIZE: u16 = 0x1;
+    const SETTINGS_ENABLE_PUSH: u16 = 0x2;
+    const SETTINGS_MAX_CONCURRENT_STREAMS: u16 = 0x3;
+    const SETTINGS_INITIAL_WINDOW_SIZE: u16 = 0x4;
+    const SETTINGS_MAX_FRAME_SIZE: u16 = 0x5;
+
+    const DEFAULT_WINDOW_SIZE: i64 = 65_535;
+    const DEFAULT_MAX_FRAME_SIZE: u32 = 16_384;
+    // the largest frame size a peer may announce, 2^24 - 1
+    const MAX_FRAME_SIZE_LIMIT: u32 = 16_777_215;
+    // the window we grant the server, per stream and for the connection
+    const RECEIVE_WINDOW_SIZE: u32 = 1 << 24;
+    const MAX_STREAM_ID: u32 = 0x7fff_ffff;
+    // error code of RST_STREAM frames
+    const CANCEL: u32 = 0x8;
+
+    // Headers which only make sense for HTTP/1.1 connections
+    const CONNECTION_HEADERS: [&'static str; 6] = [
+        "connection",
+        "host",
+        "keep-alive",
+        "proxy-connection",
+        "transfer-encoding",
+        "upgrade",
+    ];
+
+    pub fn handshake(mut connection: Connection) -> Result<Self, NetworkError> {
+        let mut settings = Vec::new();
+        for (id, value) in [
+            (Self::SETTINGS_ENABLE_PUSH, 0),
+            (
+                Self::SETTINGS_INITIAL_WINDOW_SIZE,
+                Self::RECEIVE_WINDOW_SIZE,
+            ),
+        ] {
+            settings.extend_from_slice(&id.to_be_bytes());
+            settings.extend_from_slice(&value.to_be_bytes());
+        }
+
+        let window_increment = Self::RECEIVE_WINDOW_SIZE - Self::DEFAULT_WINDOW_SIZE as u32;
+
+        connection.write_all(Self::PREFACE)?;
+        Frame::new(Frame::SETTINGS, 0, 0, settings).write(&mut connection)?;
+        Frame::new(
+            Frame::WINDOW_UPDATE,
+            0,
+            0,
+            window_increment.to_be_bytes().to_vec(),
+        )
+        .write(&mut connection)?;
+        connection.flush()?;
+
+        info!("Started HTTP/2 connection");
+
+        Ok(Self {
+            connection,
+            encoder: hpack::Encoder::new(),
+            decoder: hpack::Decoder::new(),
+            streams: HashMap::new(),
+            next_stream_id: 1,
+            max_frame_size: Self::DEFAULT_MAX_FRAME_SIZE,
+            max_concurrent_streams: u32::MAX,
+            initial_window_size: Self::DEFAULT_WINDOW_SIZE,
+            send_window: Self::DEFAULT_WINDOW_SIZE,
+            continued_stream: None,
+            last_stream_id: None,
+            broken: false,
+            limits: Config::global().size_limits,
+        })
+    }
+
+    // Whether a new request can be sent on this connection
+    pub fn is_usable(&self) -> bool {
+        !self.broken
+            && self.last_stream_id.is_none()
+            && self.next_stream_id < Self::MAX_STREAM_ID
+            && (self.streams.len() as u32) < self.max_concurrent_streams
+    }
+
+    // Sends the request and returns the id of its stream
+    pub fn send_request(
+        &mut self,
+        request: &Request,
+        cancellation: &Cancellation,
+    ) -> Result<u32, NetworkError> {
+        if !self.is_usable() {
+            return Err(NetworkError::Http2(String::from(
+                "connection can't be used for new requests",
+            )));
+        }
+
+        let stream_id = self.next_stream_id;
+        self.next_stream_id += 2;
+        self.streams.insert(
+            stream_id,
+            StreamState {
+                started: Some(Instant::now()),
+                send_window: self.initial_window_size,
+                ..StreamState::default()
+            },
+        );
+        self.set_deadlines(stream_id, cancellation);
+
+        let result = self.write_request(stream_id, request);
+        self.broken |= result.is_err();
+        result.map(|()| stream_id)
+    }
+
+    // Returns whether the response was complete once its stream has ended. The response itself
+    // is passed on by take_received, which should be called before.
+    pub fn take_response(&mut self, stream_id: u32) -> Option<Result<(), NetworkError>> {
+        let stream = self.streams.get(&stream_id)?;
+
+        if stream.reset.is_some() {
+            return self.streams.remove(&stream_id)?.reset.map(Err);
+        }
+
+        if !stream.ended || stream.headers.is_none() || self.continued_stream == Some(stream_id) {
+            return None;
+        }
+
+        let stream = self.streams.remove(&stream_id)?;
+        Some(Self::head(&stream.headers.unwrap_or_default()).map(|_| ()))
+    }
+
+    // Returns what was received on the stream since the last call, in the form of a HTTP/1.1
+    // response, so the response can be processed before it is complete
+    pub fn take_received(&mut self, stream_id: u32) -> Vec<u8> {
+        let stream = match self.streams.get_mut(&stream_id) {
+            Some(stream) if stream.reset.is_none() => stream,
+            _ => return Vec::new(),
+        };
+        let headers = match &stream.headers {
+            Some(headers) => headers,
+            None => return Vec::new(),
+        };
+
+        let mut received = Vec::new();
+        if !stream.head_taken {
+            // an invalid head is reported by take_response
+            match Self::head(headers) {
+                Ok(head) => received = head.into_bytes(),
+                Err(_) => return Vec::new(),
+            }
+            stream.head_taken = true;
+        }
+
+        received.append(&mut stream.body);
+        received
+    }
+
+    // Reads and handles the next frame, using the timeouts of the given stream
+    pub fn read_frame(
+        &mut self,
+        stream_id: u32,
+        cancellation: &Cancellation,
+    ) -> Result<(), NetworkError> {
+        if self.broken {
+            return Err(NetworkError::Http2(String::from("connection failed")));
+        }
+
+        self.set_deadlines(stream_id, cancellation);
+
+        let result = Frame::read(&mut self.connection, Self::DEFAULT_MAX_FRAME_SIZE)
+            .and_then(|frame| self.handle_frame(frame));
+        self.broken |= result.is_err();
+        result
+    }
+
+    fn set_deadlines(&mut self, stream_id: u32, cancellation: &Cancellation) {
+        let config = Config::global();
+        let mut deadlines = Vec::new();
+
+        if let Some(stream) = self.streams.get(&stream_id) {
+            if let Some(started) = stream.started {
+                if stream.headers.is_none() {
+                    deadlines.extend(Connection::deadline(
+                        started,
+                        config.first_byte_timeout,
+                        TimeoutPhase::FirstByte,
+                    ));
+                }
+                deadlines.extend(Connection::deadline(
+                    started,
+                    config.transfer_timeout,
+                    TimeoutPhase::Transfer,
+                ));
+            }
+        }
+
+        self.connection.set_deadlines(cancellation, deadlines);
+    }
+
+    fn write_request(&mut self, stream_id: u32, request: &Request) -> Result<(), NetworkError> {
+        let url = &request.url;
+        let mut headers = vec![
+            (b":method".to_vec(), request.method.to_string().into_bytes()),
+            (b":scheme".to_vec(), url.scheme.to_string().into_bytes()),
+            (b":authority".to_vec(), url.host_header().into_bytes()),
+            (b":path".to_vec(), url.request_target().into_bytes()),
+            (
+                b"user-agent".to_vec(),
+                Request::USER_AGENT.as_bytes().to_vec(),
+            ),
+        ];
+
+        for (name, value) in request.headers().iter() {
+            let name = name.to_ascii_lowercase();
+
+            if !Self::CONNECTION_HEADERS.contains(&name.as_str()) {
+                headers.push((name.into_bytes(), value.as_bytes().to_vec()));
+            }
+        }
+
+        let body = request.body.as_deref().unwrap_or_default();
+        if !body.is_empty() || matches!(request.method, Method::Post | Method::Put) {
+            headers.push((
+                b"content-length".to_vec(),
+                body.len().to_string().into_bytes(),
+            ));
+        }
+
+        let header_block = self.encoder.encode(&headers);
+        let mut chunks = header_block.chunks(self.max_frame_size as usize).peekable();
+        let mut kind = Frame::HEADERS;
+
+        // an empty header block is impossible, there are always pseudo headers
+        while let Some(chunk) = chunks.next() {
+            let mut flags = 0;
+            if chunks.peek().is_none() {
+                flags |= Frame::END_HEADERS;
+            }
+            if kind == Frame::HEADERS && body.is_empty() {
+                flags |= Frame::END_STREAM;
+            }
+
+            Frame::new(kind, flags, stream_id, chunk.to_vec()).write(&mut self.connection)?;
+            kind = Frame::CONTINUATION;
+        }
+
+        self.write_body(stream_id, body)?;
+        Ok(self.connection.flush()?)
+    }
+
+    // The body is sent in DATA frames as far as the flow control windows allow, frames from the
+    // server are read while waiting for the windows to be enlarged
+    fn write_body(&mut self, stream_id: u32, body: &[u8]) -> Result<(), NetworkError> {
+        let mut remaining = body;
+
+        while !remaining.is_empty() {
+            let stream_window = self.streams.get(&stream_id).map_or(0, |s| s.send_window);
+            let available = self
+                .send_window
+                .min(stream_window)
+                .min(i64::from(self.max_frame_size));
+
+            if available <= 0 {
+                self.connection.flush()?;
+                let frame = Frame::read(&mut self.connection, Self::DEFAULT_MAX_FRAME_SIZE)?;
+                self.handle_frame(frame)?;
+
+                if self
+                    .streams
+                    .get(&stream_id)
+                    .map_or(true, |s| s.reset.is_some() || s.ended)
+                {
+                    // the server doesn't want the rest of the body, either it already responded
+                    // or the reason is reported when the response is taken
+                    return Ok(());
+                }
+                continue;
+            }
+
+            let (chunk, rest) = remaining.split_at(remaining.len().min(available as usize));
+            let flags = if rest.is_empty() {
+                Frame::END_STREAM
+            } else {
+                0
+            };
+            Frame::new(Frame::DATA, flags, stream_id, chunk.to_vec())
+                .write(&mut self.connection)?;
+
+            self.send_window -= chunk.len() as i64;
+            if let Some(stream) = self.streams.get_mut(&stream_id) {
+                stream.send_window -= chunk.len() as i64;
+            }
+            remaining = rest;
+        }
+
+        Ok(())
+    }
+
+    fn handle_frame(&mut self, frame: Frame) -> Result<(), NetworkError> {
+        if let Some(stream_id) = self.continued_stream {
+            if frame.kind != Frame::CONTINUATION || frame.stream_id != stream_id {
+                return Err(NetworkError::Http2(String::from("expected CONTINUATION")));
+            }
+        }
+
+        match frame.kind {
+            Frame::DATA => self.handle_data(&frame),
+            Frame::HEADERS | Frame::CONTINUATION => self.handle_headers(&frame),
+            Frame::RST_STREAM => {
+                let code = frame.payload.get(..4).map_or(0, |code| {
+                    u32::from_be_bytes([code[0], code[1], code[2], code[3]])
+                });
+                if let Some(stream) = self.streams.get_mut(&frame.stream_id) {
+                    stream.reset = Some(NetworkError::Http2(format!(
+                        "stream reset by server (error code {})",
+                        code
+                    )));
+                }
+                Ok(())
+            }
+            Frame::SETTINGS => self.handle_settings(&frame),
+            Frame::PING if !frame.has_flag(Frame::ACK) => {
+                Frame::new(Frame::PING, Frame::ACK, 0, frame.payload)
+                    .write(&mut self.connection)?;
+                Ok(self.connection.flush()?)
+            }
+            Frame::GOAWAY => {
+                let last_stream_id = frame.payload.get(..4).map_or(0, |id| {
+                    u32::from_be_bytes([id[0], id[1], id[2], id[3]]) & 0x7fff_ffff
+                });
+                info!(last_stream_id, "Server is closing the HTTP/2 connection");
+
+                // streams above the last stream id have not been processed and can be retried
+                for (_, stream) in self
+                    .streams
+                    .iter_mut()
+                    .filter(|(id, stream)| **id > last_stream_id && !stream.ended)
+                {
+                    stream.reset = Some(NetworkError::Http2(String::from(
+                        "connection closed by server",
+                    )));
+                }
+                self.last_stream_id = Some(last_stream_id);
+                Ok(())
+            }
+            Frame::WINDOW_UPDATE => {
+                let increment = frame.payload.get(..4).map_or(0, |increment| {
+                    u32::from_be_bytes([increment[0], increment[1], increment[2], increment[3]])
+                        & 0x7fff_ffff
+                });
+
+                if frame.stream_id == 0 {
+                    self.send_window += i64::from(increment);
+                } else if let Some(stream) = self.streams.get_mut(&frame.stream_id) {
+                    stream.send_window += i64::from(increment);
+                }
+                Ok(())
+            }
+            Frame::PUSH_PROMISE => Err(NetworkError::Http2(String::from(
+                "server push although it's disabled",
+            ))),
+            // PRIORITY, acknowledgements and unknown frames are ignored
+            _ => Ok(()),
+        }
+    }
+
+    // Data of reset streams is discarded, it may still arrive after the reset was sent
+    fn handle_data(&mut self, frame: &Frame) -> Result<(), NetworkError> {
+        let data = frame.data()?;
+        let max_size = self.limits.body_size;
+        let mut too_large = false;
+        let mut open = false;
+
+        if let Some(stream) = self.streams.get_mut(&frame.stream_id) {
+            if stream.reset.is_none() {
+                too_large = stream.body_size + data.len() as u64 > max_size;
+                open = !too_large && !frame.has_flag(Frame::END_STREAM);
+
+                if !too_large {
+                    stream.body.extend_from_slice(data);
+                    stream.body_size += data.len() as u64;
+                    stream.ended |= frame.has_flag(Frame::END_STREAM);
+                }
+            }
+        }
+
+        if too_large {
+            self.cancel_stream(
+                frame.stream_id,
+                NetworkError::TooLarge(SizeLimit::BodySize(max_size)),
+            )?;
+        }
+
+        // the received data is consumed immediately, hence the windows are restored right away
+        if !frame.payload.is_empty() {
+            let increment = (frame.payload.len() as u32).to_be_bytes().to_vec();
+            Frame::new(Frame::WINDOW_UPDATE, 0, 0, increment.clone())
+                .write(&mut self.connection)?;
+
+            if open {
+                Frame::new(Frame::WINDOW_UPDATE, 0, frame.stream_id, increment)
+                    .write(&mut self.connection)?;
+            }
+            self.connection.flush()?;
+        }
+
+        Ok(())
+    }
+
+    // Header blocks have to be decoded even for unknown streams to keep the HPACK state in sync
+    fn handle_headers(&mut self, frame: &Frame) -> Result<(), NetworkError> {
+        let block = if frame.kind == Frame::HEADERS {
+            frame.data()?
+        } else {
+            &frame.payload[..]
+        };
+
+        let stream = self.streams.entry(frame.stream_id).or_default();
+        stream.header_block.extend_from_slice(block);
+
+        // the block can't be skipped without breaking the HPACK state, so the connection fails
+        if stream.header_block.len() as u64 > self.limits.header_size {
+            return Err(NetworkError::TooLarge(SizeLimit::HeaderSize(
+                self.limits.header_size,
+            )));
+        }
+        stream.ended |= frame.has_flag(Frame::END_STREAM);
+
+        if !frame.has_flag(Frame::END_HEADERS) {
+            self.continued_stream = Some(frame.stream_id);
+            return Ok(());
+        }
+        self.continued_stream = None;
+
+        let header_block = std::mem::take(&mut stream.header_block);
+        let headers: Vec<(String, String)> = self
+            .decoder
+            .decode(&header_block)
+            .map_err(|_| NetworkError::Http2(String::from("invalid header block")))?
+            .into_iter()
+            .map(|(name, value)| {
+                (
+                    String::from_utf8_lossy(&name).into_owned(),
+                    String::from_utf8_lossy(&value).into_owned(),
+                )
+            })
+            .collect();
+
+        let stream = self.streams.entry(frame.stream_id).or_default();
+        if stream.started.is_none() {
+            // not a stream we opened, e.g. one which was already removed
+            self.streams.remove(&frame.stream_id);
+            return Ok(());
+        }
+
+        // a small block can decode into large headers by referencing the dynamic table
+        let existing = stream.headers.as_deref().unwrap_or_default();
+        let count = (existing.len() + headers.len()) as u64;
+        let size = existing
+            .iter()
+            .chain(headers.iter())
+            .map(|(name, value)| (name.len() + value.len()) as u64)
+            .sum::<u64>();
+
+        if count > self.limits.header_count {
+            let limit = SizeLimit::HeaderCount(self.limits.header_count);
+            return self.cancel_stream(frame.stream_id, NetworkError::TooLarge(limit));
+        } else if size > self.limits.header_size {
+            let limit = SizeLimit::HeaderSize(self.limits.header_size);
+            return self.cancel_stream(frame.stream_id, NetworkError::TooLarge(limit));
+        }
+
+        let is_informational = headers
+            .iter()
+            .any(|(name, value)| name == ":status" && value.starts_with('1'));
+
+        // informational responses are skipped, a second block after the response are trailers
+        match &mut stream.headers {
+            None if is_informational => {}
+            None => stream.headers = Some(headers),
+            Some(existing) => existing.extend(headers),
+        }
+
+        Ok(())
+    }
+
+    // Resets the stream because of the given error, which its request receives
+    fn cancel_stream(&mut self, stream_id: u32, error: NetworkError) -> Result<(), NetworkError> {
+        warn!(stream_id, error = %error, "Resetting HTTP/2 stream");
+
+        if let Some(stream) = self.streams.get_mut(&stream_id) {
+            stream.reset = Some(error);
+        }
+
+        Frame::new(
+            Frame::RST_STREAM,
+            0,
+            stream_id,
+            Self::CANCEL.to_be_bytes().to_vec(),
+        )
+        .write(&mut self.connection)?;
+        Ok(self.connection.flush()?)
+    }
+
+    fn handle_settings(&mut self, frame: &Frame) -> Result<(), NetworkError> {
+        if frame.has_flag(Frame::ACK) {
+            return Ok(());
+        }
+
+        for setting in frame.payload.chunks_exact(6) {
+            let id = u16::from_be_bytes([setting[0], setting[1]]);
+            let value = u32::from_be_bytes([setting[2], setting[3], setting[4], setting[5]]);
+
+            match id {
+                Self::SETTINGS_HEADER_TABLE_SIZE if value < 4096 => {
+                    // the encoder always assumes the default table size
+                    return Err(NetworkError::Http2(String::from(
+                        "unsupported header table size",
+                    )));
+                }
+                Self::SETTINGS_MAX_CONCURRENT_STREAMS => self.max_concurrent_streams = value,
+                Self::SETTINGS_INITIAL_WINDOW_SIZE => {
+                    let delta = i64::from(value) - self.initial_window_size;
+                    for stream in self.streams.values_mut() {
+                        stream.send_window += delta;
+                    }
+                    self.initial_window_size = i64::from(value);
+                }
+                Self::SETTINGS_MAX_FRAME_SIZE
+                    if !(Self::DEFAULT_MAX_FRAME_SIZE..=Self::MAX_FRAME_SIZE_LIMIT)
+                        .contains(&value) =>
+                {
+                    // a PROTOCOL_ERROR, see https://www.rfc-editor.org/rfc/rfc9113#section-6.5.2
+                    return Err(NetworkError::Http2(String::from("invalid max frame size")));
+                }
+                Self::SETTINGS_MAX_FRAME_SIZE => self.max_frame_size = value,
+                _ => {}
+            }
+        }
+
+        Frame::new(Frame::SETTINGS, Frame::ACK, 0, Vec::new()).write(&mut self.connection)?;
+        Ok(self.connection.flush()?)
+    }
+
+    // The status line and headers in the HTTP/1.1 form, including the empty line at the end.
+    // Connection specific headers are dropped, and line breaks would inject headers, see
+    // https://www.rfc-editor.org/rfc/rfc9113#section-8.2.1
+    fn head(headers: &[(String, String)]) -> Result<String, NetworkError> {
+        let status = headers
+            .iter()
+            .find(|(name, _)| name == ":status")
+            .map(|(_, value)| value.as_str())
+            .ok_or(NetworkError::MalformedResponse("missing :status"))?;
+
+        let mut head = format!("HTTP/2 {}\r\n", status);
+        for (name, value) in headers.iter().filter(|(name, _)| !name.starts_with(':')) {
+            if name.contains(['\r', '\n', '\0']) || value.contains(['\r', '\n', '\0']) {
+                return Err(NetworkError::Http2(String::from("invalid header field")));
+            }
+
+            if !Self::CONNECTION_HEADERS.contains(&name.as_str()) {
+                head += &format!("{}: {}\r\n", name, value);
+            }
+        }
+
+        Ok(head + "\r\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+
+    use crate::url::Url;
+
+    fn frame_bytes(frame: &Frame) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        frame.write(&mut bytes).unwrap();
+        bytes
+    }
+
+    fn headers(headers: &[(&str, &str)]) -> Vec<u8> {
+        let headers = headers
+            .iter()
+            .map(|(name, value)| (name.as_bytes().to_vec(), value.as_bytes().to_vec()))
+            .collect();
+        hpack::Encoder::new().encode(&headers)
+    }
+
+    // Starts a server which sends the given frames and then waits until the client disconnects
+    fn server(frames: Vec<Frame>) -> Url {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            for frame in frames {
+                frame.write(&mut stream).unwrap();
+            }
+            let _ = stream.read_to_end(&mut Vec::new());
+        });
+
+        Url::new(&format!("http://127.0.0.1:{}/", port)).unwrap()
+    }
+
+    fn receive(
+        connection: &mut Http2Connection,
+        stream_id: u32,
+        cancellation: &Cancellation,
+    ) -> Result<Vec<u8>, NetworkError> {
+        let mut response = Vec::new();
+        loop {
+            response.extend(connection.take_received(stream_id));
+            if let Some(result) = connection.take_response(stream_id) {
+                return result.map(|()| response);
+            }
+
+            connection.read_frame(stream_id, cancellation)?;
+        }
+    }
+
+    fn request(frames: Vec<Frame>) -> Result<Vec<u8>, NetworkError> {
+        request_with_limits(frames, SizeLimits::default())
+    }
+
+    fn request_with_limits(
+        frames: Vec<Frame>,
+        limits: SizeLimits,
+    ) -> Result<Vec<u8>, NetworkError> {
+        let url = server(frames);
+        let cancellation = Cancellation::new();
+        let connection = Connection::open(&url, &cancellation).unwrap();
+        let mut connection = Http2Connection::handshake(connection).unwrap();
+        connection.limits = limits;
+
+        let stream_id = connection
+            .send_request(&Request::new(url), &cancellation)
+            .unwrap();
+        receive(&mut connection, stream_id, &cancellation)
+    }
+
+    #[test]
+    fn frame_write_and_read() {
+        let frame = Frame::new(Frame::HEADERS, Frame::END_HEADERS, 3, b"abc".to_vec());
+        let bytes = frame_bytes(&frame);
+        assert_eq!(b"\x00\x00\x03\x01\x04\x00\x00\x00\x03abc".to_vec(), bytes);
+        assert_eq!(frame, Frame::read(&mut &bytes[..], 16_384).unwrap());
+    }
+
+    #[test]
+    fn frame_read_too_large() {
+        let bytes = frame_bytes(&Frame::new(Frame::DATA, 0, 1, vec![0; 20]));
+        assert!(matches!(
+            Frame::read(&mut &bytes[..], 16),
+            Err(NetworkError::Http2(_))
+        ));
+    }
+
+    #[test]
+    fn frame_data_with_padding_and_priority() {
+        let frame = Frame::new(
+            Frame::HEADERS,
+            Frame::PADDED | Frame::PRIORITY,
+            1,
+            b"\x02\x00\x00\x00\x00\x10abc\x00\x00".to_vec(),
+        );
+        assert_eq!(b"abc", frame.data().unwrap());
+
+        let frame = Frame::new(Frame::DATA, Frame::PADDED, 1, b"\x05abc".to_vec());
+        assert!(frame.data().is_err());
+    }
+
+    #[test]
+    fn receive_response() {
+        let response = request(vec![
+            Frame::new(Frame::SETTINGS, 0, 0, Vec::new()),
+            Frame::new(
+                Frame::HEADERS,
+                Frame::END_HEADERS,
+                1,
+                headers(&[(":status", "200"), ("content-type", "text/plain")]),
+            ),
+            Frame::new(Frame::DATA, 0, 1, b"Hello ".to_vec()),
+            Frame::new(Frame::DATA, Frame::END_STREAM, 1, b"World".to_vec()),
+        ])
+        .unwrap();
+
+        assert_eq!(
+            "HTTP/2 200\r\ncontent-type: text/plain\r\n\r\nHello World",
+            String::from_utf8(response).unwrap()
+        );
+    }
+
+    #[test]
+    fn take_received() {
+        let url = server(vec![
+            Frame::new(
+                Frame::HEADERS,
+                Frame::END_HEADERS,
+                1,
+                headers(&[(":status", "200")]),
+            ),
+            Frame::new(Frame::DATA, 0, 1, b"Hello ".to_vec()),
+            Frame::new(Frame::DATA, Frame::END_STREAM, 1, b"World".to_vec()),
+        ]);
+        let cancellation = Cancellation::new();
+        let connection = Connection::open(&url, &cancellation).unwrap();
+        let mut connection = Http2Connection::handshake(connection).unwrap();
+        let stream_id = connection
+            .send_request(&Request::new(url), &cancellation)
+            .unwrap();
+
+        let mut received = Vec::new();
+        while connection.streams[&stream_id].body_size < 11 {
+            connection.read_frame(stream_id, &cancellation).unwrap();
+            received.extend(connection.take_received(stream_id));
+        }
+
+        assert_eq!(b"HTTP/2 200\r\n\r\nHello World".to_vec(), received);
+        assert!(connection.take_received(stream_id).is_empty());
+    }
+
+    #[test]
+    fn receive_response_with_continuation_and_informational_response() {
+        let block = headers(&[(":status", "404"), ("server", "test")]);
+        let (first, second) = block.split_at(2);
+
+        let response = request(vec![
+            Frame::new(
+                Frame::HEADERS,
+                Frame::END_HEADERS,
+                1,
+                headers(&[(":status", "103")]),
+            ),
+            Frame::new(Frame::HEADERS, Frame::END_STREAM, 1, first.to_vec()),
+            Frame::new(Frame::CONTINUATION, Frame::END_HEADERS, 1, second.to_vec()),
+        ])
+        .unwrap();
+
+        assert_eq!(
+            "HTTP/2 404\r\nserver: test\r\n\r\n",
+            String::from_utf8(response).unwrap()
+        );
+    }
+
+    #[test]
+    fn receive_reset_stream() {
+        let result = request(vec![Frame::new(
+            Frame::RST_STREAM,
+            0,
+            1,
+            2_u32.to_be_bytes().to_vec(),
+        )]);
+        assert!(matches!(result, Err(NetworkError::Http2(_))));
+    }
+
+    #[test]
+    fn receive_too_large_response() {
+        let limits = SizeLimits {
+            header_size: 1024,
+            header_count: 2,
+            body_size: 8,
+            decoded_body_size: 8,
+        };
+        let response_headers = Frame::new(
+            Frame::HEADERS,
+            Frame::END_HEADERS,
+            1,
+            headers(&[(":status", "200")]),
+        );
+
+        let result = request_with_limits(
+            vec![
+                response_headers,
+                Frame::new(Frame::DATA, 0, 1, b"Hello ".to_vec()),
+                Frame::new(Frame::DATA, Frame::END_STREAM, 1, b"World".to_vec()),
+            ],
+            limits,
+        );
+        assert!(matches!(
+            result,
+            Err(NetworkError::TooLarge(SizeLimit::BodySize(8)))
+        ));
+
+        let result = request_with_limits(
+            vec![Frame::new(
+                Frame::HEADERS,
+                Frame::END_HEADERS | Frame::END_STREAM,
+                1,
+                headers(&[(":status", "200"), ("a", "1"), ("b", "2")]),
+            )],
+            limits,
+        );
+        assert!(matches!(
+            result,
+            Err(NetworkError::TooLarge(SizeLimit::HeaderCount(2)))
+        ));
+    }
+
+    #[test]
+    fn head() {
+        let headers = |headers: &[(&str, &str)]| {
+            Http2Connection::head(
+                &headers
+                    .iter()
+                    .map(|(name, value)| (name.to_string(), value.to_string()))
+                    .collect::<Vec<(String, String)>>(),
+            )
+        };
+
+        assert_eq!(
+            "HTTP/2 200\r\nserver: test\r\n\r\n",
+            headers(&[
+                (":status", "200"),
+                ("transfer-encoding", "chunked"),
+                ("server", "test"),
+                ("connection", "close"),
+            ])
+            .unwrap()
+        );
+        assert!(matches!(
+            headers(&[(":status", "200"), ("server", "a\r\nset-cookie: b=c")]),
+            Err(NetworkError::Http2(_))
+        ));
+        assert!(matches!(
+            headers(&[(":status", "200"), ("a\nb", "c")]),
+            Err(NetworkError::Http2(_))
+        ));
+    }
+
+    #[test]
+    fn reject_invalid_max_frame_size() {
+        for value in [0, 16_383, 16_777_216] {
+            let mut setting = Http2Connection::SETTINGS_MAX_FRAME_SIZE
+                .to_be_bytes()
+                .to_vec();
+            setting.extend_from_slice(&u32::to_be_bytes(value));
+
+            let result = request(vec![Frame::new(Frame::SETTINGS, 0, 0, setting)]);
+            assert!(matches!(result, Err(NetworkError::Http2(_))));
+        }
+    }
+
+    #[test]
+    fn goaway_makes_connection_unusable() {
+        let url = server(vec![Frame::new(Frame::GOAWAY, 0, 0, vec![0; 8])]);
+        let cancellation = Cancellation::new();
+        let connection = Connection::open(&url, &cancellation).unwrap();
+        let mut connection = Http2Connection::handshake(connection).unwrap();
+
+        let stream_id = connection
+            .send_request(&Request::new(url.clone()), &cancellation)
+            .unwrap();
+        assert!(receive(&mut connection, stream_id, &cancellation).is_err());
+        assert!(!connection.is_usable());
+        assert!(connection
+            .send_request(&Request::new(url), &cancellation)
+            .is_err());
+    }
+}
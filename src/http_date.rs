@@ -0,0 +1,227 @@
+use std::time::SystemTime;
+
+pub struct HttpDate {}
+
+impl HttpDate {
+    const MONTHS: [&'static str; 12] = [
+        "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+    ];
+    const WEEKDAYS: [&'static str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+
+    // Parses a date in any of the formats used by HTTP (IMF-fixdate, RFC 850, asctime) and the
+    // sloppier variants found in cookies, and returns it in seconds since the Unix Epoch. Dates
+    // before the Epoch are returned as 0.
+    // see also https://datatracker.ietf.org/doc/html/rfc6265#section-5.1.1
+    pub fn parse(s: &str) -> Option<u64> {
+        let mut time = None;
+        let mut day = None;
+        let mut month = None;
+        let mut year = None;
+
+        let is_delimiter = |c: char| {
+            c == '\t'
+                || (' '..='/').contains(&c)
+                || (';'..='@').contains(&c)
+                || ('['..='`').contains(&c)
+                || ('{'..='~').contains(&c)
+        };
+
+        for token in s.split(is_delimiter).filter(|token| !token.is_empty()) {
+            if time.is_none() {
+                if let Some(t) = Self::parse_time(token) {
+                    time = Some(t);
+                    continue;
+                }
+            }
+            if day.is_none() {
+                if let Some(d) = Self::parse_number(token, 1, 2) {
+                    day = Some(d);
+                    continue;
+                }
+            }
+            if month.is_none() {
+                if let Some(m) = Self::parse_month(token) {
+                    month = Some(m);
+                    continue;
+                }
+            }
+            if year.is_none() {
+                if let Some(y) = Self::parse_number(token, 2, 4) {
+                    year = Some(y);
+                }
+            }
+        }
+
+        let (hour, minute, second) = time?;
+        let (day, month, year) = (day?, month?, year?);
+
+        let year = match year {
+            70..=99 => year + 1900,
+            0..=69 => year + 2000,
+            _ => year,
+        };
+
+        if !(1..=31).contains(&day) || year < 1601 || hour > 23 || minute > 59 || second > 59 {
+            return None;
+        }
+
+        let days = Self::days_from_civil(year, month, day);
+        let seconds = days * 86400 + hour * 3600 + minute * 60 + second;
+
+        Some(u64::try_from(seconds).unwrap_or(0))
+    }
+
+    // Formats seconds since the Unix Epoch as IMF-fixdate, e.g. "Sun, 06 Nov 1994 08:49:37 GMT"
+    pub fn format(timestamp: u64) -> String {
+        let days = i64::try_from(timestamp / 86400).unwrap_or(i64::MAX / 2);
+        let seconds = timestamp % 86400;
+        let (year, month, day) = Self::civil_from_days(days);
+        let month_name = Self::MONTHS[usize::try_from(month - 1).unwrap_or(0)];
+
+        format!(
+            "{}, {:02} {}{} {} {:02}:{:02}:{:02} GMT",
+            // 1970-01-01 was a Thursday
+            Self::WEEKDAYS[usize::try_from((days + 4) % 7).unwrap_or(0)],
+            day,
+            month_name[..1].to_ascii_uppercase(),
+            &month_name[1..],
+            year,
+            seconds / 3600,
+            seconds % 3600 / 60,
+            seconds % 60
+        )
+    }
+
+    pub fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_secs())
+    }
+
+    // Expects a token in the form hh:mm:ss, each part with one or two digits
+    fn parse_time(token: &str) -> Option<(i64, i64, i64)> {
+        let mut parts = token.splitn(3, ':');
+        let hour = Self::parse_number(parts.next()?, 1, 2)?;
+        let minute = Self::parse_number(parts.next()?, 1, 2)?;
+        let second = Self::parse_number(parts.next()?, 1, 2)?;
+
+        Some((hour, minute, second))
+    }
+
+    // Parses the leading digits of the token, which must be followed by a non-digit, if anything
+    fn parse_number(token: &str, min_digits: usize, max_digits: usize) -> Option<i64> {
+        let digits: String = token.chars().take_while(char::is_ascii_digit).collect();
+
+        if (min_digits..=max_digits).contains(&digits.len()) {
+            digits.parse().ok()
+        } else {
+            None
+        }
+    }
+
+    fn parse_month(token: &str) -> Option<i64> {
+        let prefix = token.get(..3)?.to_ascii_lowercase();
+
+        Self::MONTHS
+            .iter()
+            .position(|&month| month == prefix)
+            .and_then(|i| i64::try_from(i + 1).ok())
+    }
+
+    // Number of days since 1970-01-01 for the given date in the proleptic Gregorian calendar
+    // from http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+    const fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+        let year = if month <= 2 { year - 1 } else { year };
+        let era = if year >= 0 { year } else { year - 399 } / 400;
+        let year_of_era = year - era * 400;
+        let month_index = if month > 2 { month - 3 } else { month + 9 };
+        let day_of_year = (153 * month_index + 2) / 5 + day - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+        era * 146_097 + day_of_era - 719_468
+    }
+
+    // The inverse of days_from_civil, returns year, month and day
+    // from http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    const fn civil_from_days(days: i64) -> (i64, i64, i64) {
+        let days = days + 719_468;
+        let era = if days >= 0 { days } else { days - 146_096 } / 146_097;
+        let day_of_era = days - era * 146_097;
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let month_index = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+        let month = if month_index < 10 {
+            month_index + 3
+        } else {
+            month_index - 9
+        };
+        let year = year_of_era + era * 400;
+
+        (if month <= 2 { year + 1 } else { year }, month, day)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_imf_fixdate() {
+        assert_eq!(
+            Some(784_111_777),
+            HttpDate::parse("Sun, 06 Nov 1994 08:49:37 GMT")
+        );
+    }
+
+    #[test]
+    fn format() {
+        assert_eq!(
+            "Sun, 06 Nov 1994 08:49:37 GMT",
+            HttpDate::format(784_111_777)
+        );
+        assert_eq!("Thu, 01 Jan 1970 00:00:00 GMT", HttpDate::format(0));
+        assert_eq!(
+            "Thu, 29 Feb 2024 12:00:00 GMT",
+            HttpDate::format(1_709_208_000)
+        );
+    }
+
+    #[test]
+    fn parse_rfc850_date() {
+        assert_eq!(
+            Some(784_111_777),
+            HttpDate::parse("Sunday, 06-Nov-94 08:49:37 GMT")
+        );
+    }
+
+    #[test]
+    fn parse_asctime_date() {
+        assert_eq!(
+            Some(784_111_777),
+            HttpDate::parse("Sun Nov  6 08:49:37 1994")
+        );
+    }
+
+    #[test]
+    fn parse_date_with_two_digit_year_after_2000() {
+        assert_eq!(
+            Some(1_609_459_200),
+            HttpDate::parse("Fri, 01-Jan-21 00:00:00 GMT")
+        );
+    }
+
+    #[test]
+    fn parse_date_before_epoch() {
+        assert_eq!(Some(0), HttpDate::parse("Thu, 01 Jan 1950 00:00:00 GMT"));
+    }
+
+    #[test]
+    fn parse_invalid_dates() {
+        assert_eq!(None, HttpDate::parse(""));
+        assert_eq!(None, HttpDate::parse("Sun, 06 Nov 1994"));
+        assert_eq!(None, HttpDate::parse("Sun, 32 Nov 1994 08:49:37 GMT"));
+        assert_eq!(None, HttpDate::parse("Sun, 06 Nov 1994 25:49:37 GMT"));
+    }
+}
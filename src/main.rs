@@ -1,15 +1,45 @@
 #![warn(clippy::all, clippy::nursery, clippy::pedantic)]
 
+mod about;
+mod body_decoder;
 mod browser;
+mod cache;
+mod cancellation;
+mod character_reference;
+mod charset;
+mod config;
+mod connection_pool;
+mod content_encoding;
+mod cookie_jar;
+mod dom;
+mod file_loader;
+mod headers;
+mod history;
+mod hsts;
+mod hsts_preload;
+mod html_tokenizer;
+mod http2;
+mod http_date;
+mod named_character_references;
+mod network_error;
+mod proxy;
 mod request;
 mod request_handler;
 mod response;
+mod response_stream;
+mod selector;
+mod tls;
+mod tree_builder;
 mod url;
 mod url_parser;
 
 use std::env;
+use std::io;
 
 use crate::browser::Browser;
+use crate::cache::Cache;
+use crate::request::Method;
+use crate::selector::Selector;
 use crate::url::Url;
 use crate::url_parser::UrlParser;
 
@@ -17,22 +47,86 @@ fn main() {
     setup();
 
     let mut args = env::args().skip(1);
+    let mut method = Method::Get;
+    let mut body = None;
+    let mut url = None;
+    let mut selectors = None;
 
-    let url = if let Some(arg) = args.next() {
-        UrlParser::parse(&arg)
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-X" | "--request" => match args.next().as_deref().and_then(Method::from_name) {
+                Some(m) => method = m,
+                None => {
+                    eprintln!("Unsupported method, must be GET, HEAD, POST, PUT or DELETE");
+                    std::process::exit(1);
+                }
+            },
+            "-d" | "--data" => body = args.next().map(String::into_bytes),
+            "-s" | "--select" => match args.next() {
+                Some(s) => match Selector::parse(&s) {
+                    Ok(_) => selectors = Some(s),
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        std::process::exit(1);
+                    }
+                },
+                None => {
+                    eprintln!("Missing selector");
+                    std::process::exit(1);
+                }
+            },
+            "--clear-cache" => match Cache::clear() {
+                Ok(()) => {
+                    println!("Cache cleared");
+                    return;
+                }
+                Err(e) => {
+                    eprintln!("Unable to clear cache: {}", e);
+                    std::process::exit(1);
+                }
+            },
+            _ => url = Some(UrlParser::parse(&arg)),
+        }
+    }
+
+    let url = if let Some(url) = url {
+        url
     } else {
-        println!("Usage: rbrowser <URL>");
+        println!(
+            "Usage: rbrowser [--clear-cache] [-X <method>] [-d <data>] [-s <selectors>] <URL>"
+        );
         return;
     };
 
-    match url {
-        Ok(url) => {
-            if let Err(e) = Browser::load(&url) {
-                eprintln!("{}", e);
-                std::process::exit(1);
+    // like curl, sending data without an explicit method means POST
+    if body.is_some() && method == Method::Get {
+        method = Method::Post;
+    }
+
+    match (url, selectors) {
+        // the text of the matching elements is printed instead of showing the page
+        (Ok(url), Some(selectors)) => {
+            let result = Browser::select(&url, method, body, &selectors);
+            Cache::global().lock().unwrap().flush();
+
+            match result {
+                Ok(matches) => {
+                    for text in matches {
+                        println!("{}", text);
+                    }
+                }
+                Err(e) => {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                }
             }
         }
-        Err(e) => {
+        (Ok(url), None) => {
+            Browser::load(url, method, body);
+            // the access times of cached responses are saved when the browser exits
+            Cache::global().lock().unwrap().flush();
+        }
+        (Err(e), _) => {
             eprintln!("{}", e);
             std::process::exit(1);
         }
@@ -44,5 +138,6 @@ fn setup() {
         env::set_var("RUST_LOG", "info");
     }
 
-    tracing_subscriber::fmt::init();
+    // logging goes to stderr, which keeps stdout clean for the output of --select
+    tracing_subscriber::fmt().with_writer(io::stderr).init();
 }
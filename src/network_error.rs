@@ -0,0 +1,244 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TimeoutPhase {
+    Connect,
+    TlsHandshake,
+    FirstByte,
+    Transfer,
+}
+
+impl fmt::Display for TimeoutPhase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Connect => write!(f, "connecting to the server"),
+            Self::TlsHandshake => write!(f, "establishing a secure connection"),
+            Self::FirstByte => write!(f, "waiting for the response"),
+            Self::Transfer => write!(f, "receiving the response"),
+        }
+    }
+}
+
+// The limit a response exceeded, with the configured maximum
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SizeLimit {
+    HeaderSize(u64),
+    HeaderCount(u64),
+    BodySize(u64),
+    DecodedBodySize(u64),
+}
+
+impl fmt::Display for SizeLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::HeaderSize(max) => write!(f, "the headers exceed {} bytes", max),
+            Self::HeaderCount(max) => write!(f, "there are more than {} headers", max),
+            Self::BodySize(max) => write!(f, "the body exceeds {} bytes", max),
+            Self::DecodedBodySize(max) => write!(f, "the decoded body exceeds {} bytes", max),
+        }
+    }
+}
+
+// Why the certificate of a server was rejected
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum CertificateProblem {
+    Expired,
+    NotValidYet,
+    UnknownIssuer,
+    NameMismatch,
+    Revoked,
+    Invalid(String),
+}
+
+impl From<&rustls::CertificateError> for CertificateProblem {
+    fn from(e: &rustls::CertificateError) -> Self {
+        match e {
+            rustls::CertificateError::Expired => Self::Expired,
+            rustls::CertificateError::NotValidYet => Self::NotValidYet,
+            rustls::CertificateError::UnknownIssuer => Self::UnknownIssuer,
+            rustls::CertificateError::NotValidForName => Self::NameMismatch,
+            rustls::CertificateError::Revoked => Self::Revoked,
+            e => Self::Invalid(format!("{:?}", e)),
+        }
+    }
+}
+
+impl fmt::Display for CertificateProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Expired => write!(f, "has expired"),
+            Self::NotValidYet => write!(f, "is not valid yet"),
+            Self::UnknownIssuer => write!(f, "is not issued by a trusted certificate authority"),
+            Self::NameMismatch => write!(f, "is not valid for this host name"),
+            Self::Revoked => write!(f, "has been revoked"),
+            Self::Invalid(reason) => write!(f, "is invalid ({})", reason),
+        }
+    }
+}
+
+// Everything that can go wrong while loading a resource over the network
+#[derive(Debug)]
+pub enum NetworkError {
+    // the host could not be resolved
+    Dns(String, io::Error),
+    Connect(io::Error),
+    Tls(String),
+    // the server's certificate was rejected, the host is the one we tried to connect to
+    Certificate(String, CertificateProblem),
+    Proxy(String),
+    // the connection failed after it was established, e.g. it was closed by the server
+    Io(io::Error),
+    // a HTTP/2 protocol error or a stream reset by the server
+    Http2(String),
+    MalformedResponse(&'static str),
+    TooManyRedirects,
+    Decoding(&'static str),
+    UnsupportedContentEncoding(String),
+    Timeout(TimeoutPhase),
+    Cancelled,
+    TooLarge(SizeLimit),
+    // a file url could not be read, with the path of the file
+    File(String, io::Error),
+}
+
+impl Error for NetworkError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Dns(_, e) | Self::Connect(e) | Self::Io(e) | Self::File(_, e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for NetworkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Dns(host, e) => write!(f, "Unable to resolve host {}: {}", host, e),
+            Self::Connect(e) => write!(f, "Unable to connect: {}", e),
+            Self::Tls(message) => write!(f, "Secure connection failed: {}", message),
+            Self::Certificate(host, problem) => {
+                write!(f, "The certificate of {} {}", host, problem)
+            }
+            Self::Proxy(message) => write!(f, "Proxy error: {}", message),
+            Self::Io(e) => write!(f, "Connection failed: {}", e),
+            Self::Http2(message) => write!(f, "HTTP/2 error: {}", message),
+            Self::MalformedResponse(reason) => write!(f, "Malformed response: {}", reason),
+            Self::TooManyRedirects => write!(f, "Too many redirects"),
+            Self::Decoding(reason) => write!(f, "Unable to decode response: {}", reason),
+            Self::UnsupportedContentEncoding(name) => {
+                write!(f, "Unsupported content encoding: {}", name)
+            }
+            Self::Timeout(phase) => write!(f, "Timed out while {}", phase),
+            Self::Cancelled => write!(f, "Request cancelled"),
+            Self::TooLarge(limit) => write!(f, "Response too large: {}", limit),
+            Self::File(path, e) => write!(f, "Unable to read {}: {}", path, e),
+        }
+    }
+}
+
+impl NetworkError {
+    // Like From<io::Error>, but rejected certificates are reported with the host they belong to
+    pub fn from_handshake_error(e: io::Error, host: &str) -> Self {
+        let certificate_error = e
+            .get_ref()
+            .and_then(|inner| inner.downcast_ref::<rustls::Error>())
+            .and_then(|tls_error| match tls_error {
+                rustls::Error::InvalidCertificate(e) => Some(CertificateProblem::from(e)),
+                _ => None,
+            });
+
+        match certificate_error {
+            Some(problem) => Self::Certificate(host.to_string(), problem),
+            None => Self::from(e),
+        }
+    }
+}
+
+// rustls reports handshake and certificate failures as io::Errors wrapping a rustls::Error.
+// Timeouts and cancellations are detected while reading, hence they are wrapped in io::Errors,
+// too.
+impl From<io::Error> for NetworkError {
+    fn from(e: io::Error) -> Self {
+        let inner = e.get_ref();
+
+        if let Some(tls_error) = inner.and_then(|inner| inner.downcast_ref::<rustls::Error>()) {
+            return Self::Tls(tls_error.to_string());
+        }
+
+        match inner.and_then(|inner| inner.downcast_ref::<Self>()) {
+            Some(Self::Timeout(phase)) => Self::Timeout(*phase),
+            Some(Self::Cancelled) => Self::Cancelled,
+            Some(Self::TooLarge(limit)) => Self::TooLarge(*limit),
+            _ => Self::Io(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_io_error() {
+        let e = io::Error::new(io::ErrorKind::UnexpectedEof, "closed");
+        assert!(matches!(NetworkError::from(e), NetworkError::Io(_)));
+    }
+
+    #[test]
+    fn from_io_error_with_tls_error() {
+        let e = io::Error::new(io::ErrorKind::InvalidData, rustls::Error::DecryptError);
+        assert!(matches!(NetworkError::from(e), NetworkError::Tls(_)));
+    }
+
+    #[test]
+    fn from_handshake_error() {
+        let tls_error = rustls::Error::InvalidCertificate(rustls::CertificateError::Expired);
+        let e = io::Error::new(io::ErrorKind::InvalidData, tls_error);
+        assert!(matches!(
+            NetworkError::from_handshake_error(e, "example.com"),
+            NetworkError::Certificate(host, CertificateProblem::Expired) if host == "example.com"
+        ));
+
+        let e = io::Error::new(io::ErrorKind::InvalidData, rustls::Error::DecryptError);
+        assert!(matches!(
+            NetworkError::from_handshake_error(e, "example.com"),
+            NetworkError::Tls(_)
+        ));
+    }
+
+    #[test]
+    fn from_io_error_with_timeout() {
+        let timeout = NetworkError::Timeout(TimeoutPhase::FirstByte);
+        let e = io::Error::new(io::ErrorKind::TimedOut, timeout);
+        assert!(matches!(
+            NetworkError::from(e),
+            NetworkError::Timeout(TimeoutPhase::FirstByte)
+        ));
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(
+            "Timed out while connecting to the server",
+            NetworkError::Timeout(TimeoutPhase::Connect).to_string()
+        );
+        assert_eq!(
+            "Malformed response: truncated chunked body",
+            NetworkError::MalformedResponse("truncated chunked body").to_string()
+        );
+        assert_eq!(
+            "Response too large: the decoded body exceeds 1024 bytes",
+            NetworkError::TooLarge(SizeLimit::DecodedBodySize(1024)).to_string()
+        );
+        assert_eq!(
+            "The certificate of example.com is not valid for this host name",
+            NetworkError::Certificate(
+                String::from("example.com"),
+                CertificateProblem::NameMismatch
+            )
+            .to_string()
+        );
+    }
+}
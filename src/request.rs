@@ -1,35 +1,122 @@
-use std::collections::HashMap;
+use std::fmt;
 
+use crate::headers::Headers;
 use crate::Url;
 
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Method {
+    Get,
+    Head,
+    Post,
+    Put,
+    Delete,
+}
+
+impl Method {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_uppercase().as_str() {
+            "GET" => Some(Self::Get),
+            "HEAD" => Some(Self::Head),
+            "POST" => Some(Self::Post),
+            "PUT" => Some(Self::Put),
+            "DELETE" => Some(Self::Delete),
+            _ => None,
+        }
+    }
+
+    // Safe requests only retrieve data, all other requests may change data on the server
+    pub const fn is_safe(self) -> bool {
+        matches!(self, Self::Get | Self::Head)
+    }
+
+    // Idempotent requests can safely be sent a second time if the first attempt failed
+    pub const fn is_idempotent(self) -> bool {
+        !matches!(self, Self::Post)
+    }
+}
+
+impl fmt::Display for Method {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Get => write!(f, "GET"),
+            Self::Head => write!(f, "HEAD"),
+            Self::Post => write!(f, "POST"),
+            Self::Put => write!(f, "PUT"),
+            Self::Delete => write!(f, "DELETE"),
+        }
+    }
+}
+
 pub struct Request {
+    pub method: Method,
     pub url: Url,
-    headers: HashMap<String, String>,
+    headers: Headers,
+    pub body: Option<Vec<u8>>,
 }
 
 impl Request {
+    pub const USER_AGENT: &'static str = "rbrowser";
+
     pub fn new(url: Url) -> Self {
         Self {
+            method: Method::Get,
             url,
-            headers: HashMap::new(),
+            headers: Headers::new(),
+            body: None,
         }
     }
 
-    pub fn build(&self) -> String {
-        let mut headers = String::from("User-Agent: rbrowser\r\n");
+    pub fn build(&self) -> Vec<u8> {
+        self.build_with_target(&self.url.request_target(), None)
+    }
 
-        for (name, value) in &self.headers {
-            headers += &format!("{}: {}\r\n", &name, &value);
+    // HTTP proxies expect the absolute url as request target, see
+    // https://www.rfc-editor.org/rfc/rfc9112#section-3.2.2
+    pub fn build_for_proxy(&self, proxy_authorization: Option<&str>) -> Vec<u8> {
+        self.build_with_target(&self.url.absolute_form(), proxy_authorization)
+    }
+
+    fn build_with_target(&self, target: &str, proxy_authorization: Option<&str>) -> Vec<u8> {
+        let mut headers = format!("User-Agent: {}\r\n", Self::USER_AGENT);
+
+        for (name, value) in self.headers.iter() {
+            headers += &format!("{}: {}\r\n", name, value);
+        }
+
+        if let Some(proxy_authorization) = proxy_authorization {
+            headers += &format!("Proxy-Authorization: {}\r\n", proxy_authorization);
+        }
+
+        // servers expect a length for methods with a body, even if the body is empty
+        match (&self.body, self.method) {
+            (Some(body), _) => headers += &format!("Content-Length: {}\r\n", body.len()),
+            (None, Method::Post | Method::Put) => headers += "Content-Length: 0\r\n",
+            _ => {}
         }
 
-        format!(
-            "GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n{}\r\n",
-            self.url.path, self.url.host, headers
+        let mut request = format!(
+            "{} {} HTTP/1.1\r\nHost: {}\r\nConnection: keep-alive\r\n{}\r\n",
+            self.method,
+            target,
+            self.url.host_header(),
+            headers
         )
+        .into_bytes();
+
+        if let Some(body) = &self.body {
+            request.extend_from_slice(body);
+        }
+
+        request
+    }
+
+    pub const fn headers(&self) -> &Headers {
+        &self.headers
     }
 
+    // Sets the header, replacing an existing header with the same name
     pub fn header(&mut self, name: &str, value: &str) {
-        self.headers.insert(name.to_string(), value.to_string());
+        self.headers.insert(name, value);
     }
 }
 
@@ -42,9 +129,9 @@ mod tests {
         let request = Request::new(Url::new("http://example.com").unwrap());
         let expected = "GET / HTTP/1.1\r\n\
                         Host: example.com\r\n\
-                        Connection: close\r\n\
+                        Connection: keep-alive\r\n\
                         User-Agent: rbrowser\r\n\r\n";
-        assert_eq!(expected, request.build());
+        assert_eq!(expected.as_bytes(), request.build());
     }
 
     #[test]
@@ -53,9 +140,90 @@ mod tests {
         request.header("Header-A", "A");
         let expected = "GET / HTTP/1.1\r\n\
                         Host: example.com\r\n\
-                        Connection: close\r\n\
+                        Connection: keep-alive\r\n\
                         User-Agent: rbrowser\r\n\
                         Header-A: A\r\n\r\n";
-        assert_eq!(expected, request.build());
+        assert_eq!(expected.as_bytes(), request.build());
+    }
+
+    #[test]
+    fn build_with_custom_headers_in_insertion_order() {
+        let mut request = Request::new(Url::new("http://example.com").unwrap());
+        request.header("Header-C", "C");
+        request.header("Header-A", "A");
+        request.header("Header-B", "B");
+        request.header("header-c", "D");
+        let expected = "GET / HTTP/1.1\r\n\
+                        Host: example.com\r\n\
+                        Connection: keep-alive\r\n\
+                        User-Agent: rbrowser\r\n\
+                        header-c: D\r\n\
+                        Header-A: A\r\n\
+                        Header-B: B\r\n\r\n";
+        assert_eq!(expected.as_bytes(), request.build());
+    }
+
+    #[test]
+    fn build_with_query_and_port() {
+        let request = Request::new(Url::new("http://example.com:8080/search?q=a#top").unwrap());
+        let expected = "GET /search?q=a HTTP/1.1\r\n\
+                        Host: example.com:8080\r\n\
+                        Connection: keep-alive\r\n\
+                        User-Agent: rbrowser\r\n\r\n";
+        assert_eq!(expected.as_bytes(), request.build());
+    }
+
+    #[test]
+    fn build_with_body() {
+        let mut request = Request::new(Url::new("http://example.com/form").unwrap());
+        request.method = Method::Post;
+        request.body = Some(b"a=1&b=2".to_vec());
+        let expected = "POST /form HTTP/1.1\r\n\
+                        Host: example.com\r\n\
+                        Connection: keep-alive\r\n\
+                        User-Agent: rbrowser\r\n\
+                        Content-Length: 7\r\n\r\n\
+                        a=1&b=2";
+        assert_eq!(expected.as_bytes(), request.build());
+    }
+
+    #[test]
+    fn build_post_without_body() {
+        let mut request = Request::new(Url::new("http://example.com").unwrap());
+        request.method = Method::Post;
+        let expected = "POST / HTTP/1.1\r\n\
+                        Host: example.com\r\n\
+                        Connection: keep-alive\r\n\
+                        User-Agent: rbrowser\r\n\
+                        Content-Length: 0\r\n\r\n";
+        assert_eq!(expected.as_bytes(), request.build());
+    }
+
+    #[test]
+    fn build_for_proxy() {
+        let request = Request::new(Url::new("http://example.com:8080/search?q=a#top").unwrap());
+        let expected = "GET http://example.com:8080/search?q=a HTTP/1.1\r\n\
+                        Host: example.com:8080\r\n\
+                        Connection: keep-alive\r\n\
+                        User-Agent: rbrowser\r\n\
+                        Proxy-Authorization: Basic dTpw\r\n\r\n";
+        assert_eq!(
+            expected.as_bytes(),
+            request.build_for_proxy(Some("Basic dTpw"))
+        );
+    }
+
+    #[test]
+    fn build_head() {
+        let mut request = Request::new(Url::new("http://example.com").unwrap());
+        request.method = Method::Head;
+        assert!(request.build().starts_with(b"HEAD / HTTP/1.1\r\n"));
+    }
+
+    #[test]
+    fn method_from_name() {
+        assert_eq!(Some(Method::Delete), Method::from_name("delete"));
+        assert_eq!(Some(Method::Put), Method::from_name("PUT"));
+        assert_eq!(None, Method::from_name("PATCH"));
     }
 }
@@ -1,32 +1,40 @@
-use std::collections::hash_map::DefaultHasher;
-use std::convert::TryInto;
-use std::fs;
-use std::hash::{Hash, Hasher};
-use std::io::{self, Read, Write};
-use std::net::TcpStream;
-use std::sync::Arc;
-use std::time::SystemTime;
+use std::sync::{Arc, Mutex};
 use tracing::info;
 
-use crate::request::Request;
+use crate::cache::{Cache, Lookup};
+use crate::cancellation::Cancellation;
+use crate::connection_pool::{Connection, ConnectionPool};
+use crate::content_encoding::ContentEncoding;
+use crate::cookie_jar::CookieJar;
+use crate::file_loader::FileLoader;
+use crate::hsts::HstsStore;
+use crate::http2::Http2Connection;
+use crate::network_error::NetworkError;
+use crate::request::{Method, Request};
 use crate::response::Response;
+use crate::response_stream::ResponseStream;
 use crate::url::{Scheme, Url};
 
 pub struct RequestHandler {}
 
 impl RequestHandler {
-    pub fn request(url: &Url) -> io::Result<String> {
-        if let Some(cached_response) = Cache::get(url) {
-            Ok(cached_response)
-        } else {
-            let response = RequestHandler2::request(url)?;
-
-            if response.cache_max_age() > 0 {
-                Cache::save(url, &response);
-            }
-
-            Ok(response.body)
+    // Sends a request with the given method and optional body, only GET requests are cached. The
+    // request fails with NetworkError::Cancelled once the cancellation is triggered. Responses
+    // from the network are also passed to progress while they are received, each time their
+    // decoded body has grown. This includes responses which are followed by a redirect.
+    pub fn request(
+        method: Method,
+        url: &Url,
+        body: Option<Vec<u8>>,
+        cancellation: &Cancellation,
+        progress: &mut dyn FnMut(&ResponseStream),
+    ) -> Result<Response, NetworkError> {
+        // local files are read directly, without cache, cookies and redirects
+        if url.scheme == Scheme::File {
+            return FileLoader::load(url);
         }
+
+        RequestHandler2::request(method, url, body, cancellation, progress)
     }
 }
 
@@ -35,138 +43,309 @@ struct RequestHandler2 {}
 impl RequestHandler2 {
     const MAX_REDIRECTS: u8 = 5;
 
-    pub fn request(url: &Url) -> io::Result<Response> {
+    pub fn request(
+        method: Method,
+        url: &Url,
+        body: Option<Vec<u8>>,
+        cancellation: &Cancellation,
+        progress: &mut dyn FnMut(&ResponseStream),
+    ) -> Result<Response, NetworkError> {
         let mut redirect_count = 0;
-        let mut url = url;
-        let mut temp_url;
+        let mut request = Request::new(url.clone());
+        request.method = method;
+        request.body = body;
 
         loop {
-            info!(%url, "Make request");
-            let mut request = Request::new(url.clone());
-            request.header("Accept-Encoding", "gzip");
-
-            let response = Self::do_request(&request)?;
-
-            if response.is_redirect() && redirect_count < Self::MAX_REDIRECTS {
-                let location = response.header("Location").unwrap();
-                temp_url = if location.starts_with('/') {
-                    Url::new(&format!(
-                        "{}://{}:{}{}",
-                        url.scheme, url.host, url.port, location
-                    ))
+            // HSTS hosts are only accessed over https, which also applies to redirect targets
+            if let Some(url) = HstsStore::global().lock().unwrap().upgrade(&request.url) {
+                info!(from = %request.url, to = %url, "Upgrading request to https");
+                request.url = url;
+            }
+
+            info!(method = %request.method, url = %request.url, "Make request");
+            request.header("Accept-Encoding", &ContentEncoding::accept_encoding());
+
+            if let Some(cookies) =
+                CookieJar::global()
+                    .lock()
                     .unwrap()
-                } else {
-                    Url::new(location).unwrap()
-                };
-                url = &temp_url;
-                redirect_count += 1;
-            } else {
-                break Ok(response);
+                    .cookie_header(&request.url, url, request.method)
+            {
+                request.header("Cookie", &cookies);
+            }
+
+            let response = Self::fetch(&mut request, cancellation, progress)?;
+
+            // a redirect without Location can't be followed, its body is shown instead
+            let location = match response.header("Location") {
+                Some(location) if response.is_redirect() => location,
+                _ => break Ok(response),
+            };
+
+            if redirect_count == Self::MAX_REDIRECTS {
+                break Err(NetworkError::TooManyRedirects);
+            }
+
+            let redirect_url = request
+                .url
+                .join(location)
+                .map_err(|_| NetworkError::MalformedResponse("invalid Location header"))?;
+
+            // web pages must not be able to read local files
+            if redirect_url.scheme == Scheme::File {
+                break Err(NetworkError::MalformedResponse("redirect to a local file"));
             }
+            request = Self::redirect(request, redirect_url, response.status.code);
+            redirect_count += 1;
         }
     }
 
-    fn do_request(request: &Request) -> io::Result<Response> {
-        let url = &request.url;
-        let url_to_connect = format!("{}:{}", url.host, url.port);
-        let mut stream = TcpStream::connect(url_to_connect)?;
-        let mut response = Vec::new();
-
-        if url.scheme == Scheme::Https {
-            let mut root_store = rustls::RootCertStore::empty();
-            root_store.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|ta| {
-                rustls::OwnedTrustAnchor::from_subject_spki_name_constraints(
-                    ta.subject,
-                    ta.spki,
-                    ta.name_constraints,
-                )
-            }));
+    // Answers GET requests from the cache if possible, otherwise from the network, revalidating a
+    // stale cached response with a conditional request
+    fn fetch(
+        request: &mut Request,
+        cancellation: &Cancellation,
+        progress: &mut dyn FnMut(&ResponseStream),
+    ) -> Result<Response, NetworkError> {
+        if request.method != Method::Get {
+            let response = Self::do_request(request, cancellation, progress)?;
+
+            if !request.method.is_safe() && !response.status.is_error() {
+                Cache::global().lock().unwrap().invalidate(&request.url);
+            }
 
-            let config = rustls::ClientConfig::builder()
-                .with_safe_defaults()
-                .with_root_certificates(root_store)
-                .with_no_client_auth();
+            return Ok(response);
+        }
 
-            let rc_config = Arc::new(config);
-            let hostname = url.host.as_str().try_into().unwrap();
-            let mut client = rustls::ClientConnection::new(rc_config, hostname).unwrap();
+        let cached_response = match Cache::global().lock().unwrap().lookup(request) {
+            Lookup::Fresh(response) => return Ok(response),
+            Lookup::Stale(cached_response) => {
+                cached_response.add_validators(request);
+                Some(cached_response)
+            }
+            Lookup::Miss => None,
+        };
+
+        let response = Self::do_request(request, cancellation, progress)?;
+
+        match cached_response {
+            Some(cached_response) if response.status.code == 304 => Cache::global()
+                .lock()
+                .unwrap()
+                .refresh(request, cached_response, &response),
+            _ => {
+                Cache::global().lock().unwrap().store(request, &response);
+                Ok(response)
+            }
+        }
+    }
+
+    // 303 always means "fetch the result with GET", and like other browsers we treat a POST
+    // redirected with 301 or 302 the same way. 307 and 308 require the method and body to be
+    // kept.
+    fn redirect(previous: Request, url: Url, status_code: u16) -> Request {
+        let mut request = Request::new(url);
+
+        match (status_code, previous.method) {
+            (303, Method::Head) => request.method = Method::Head,
+            (303, _) | (301 | 302, Method::Post) => {}
+            (_, method) => {
+                request.method = method;
+                request.body = previous.body;
+            }
+        }
+
+        request
+    }
+
+    fn do_request(
+        request: &Request,
+        cancellation: &Cancellation,
+        progress: &mut dyn FnMut(&ResponseStream),
+    ) -> Result<Response, NetworkError> {
+        let url = &request.url;
 
-            let mut tls = rustls::Stream::new(&mut client, &mut stream);
-            tls.write_all(request.build().as_bytes())?;
-            tls.read_to_end(&mut response)?;
+        // requests to an origin with a HTTP/2 connection share that connection. The pool is
+        // unlocked first, locking the HTTP/2 connection waits for the frame it is reading.
+        let http2_connection = ConnectionPool::global()
+            .lock()
+            .unwrap()
+            .http2_connection(url);
+        let http2_connection =
+            http2_connection.filter(|connection| connection.lock().unwrap().is_usable());
+
+        if let Some(connection) = http2_connection {
+            match Self::send_http2(&connection, request, cancellation, progress) {
+                Ok(response) => return Ok(response),
+                Err(e @ (NetworkError::Cancelled | NetworkError::Timeout(_))) => return Err(e),
+                Err(e) if !request.method.is_idempotent() => return Err(e),
+                Err(e) => {
+                    info!(%url, error = %e, "HTTP/2 connection failed, reconnecting");
+                    ConnectionPool::global()
+                        .lock()
+                        .unwrap()
+                        .remove_http2(url, &connection);
+                }
+            }
         } else {
-            write!(stream, "{}", request.build())?;
+            // a pooled connection might have been closed by the server in the meantime, in which
+            // case the request is retried on a new connection, unless it timed out or was
+            // cancelled. Non-idempotent requests must not be sent twice, so they always use a new
+            // connection.
+            let pooled_connection = if request.method.is_idempotent() {
+                ConnectionPool::global().lock().unwrap().checkout(url)
+            } else {
+                None
+            };
+
+            if let Some(connection) = pooled_connection {
+                match Self::send(connection, request, cancellation, progress) {
+                    Ok(response) => return Ok(response),
+                    Err(e @ (NetworkError::Cancelled | NetworkError::Timeout(_))) => return Err(e),
+                    Err(e) => info!(%url, error = %e, "Pooled connection failed, reconnecting"),
+                }
+            }
+        }
+
+        let connection = Connection::open(url, cancellation)?;
 
-            stream.read_to_end(&mut response)?;
+        if connection.is_http2() {
+            let connection = Arc::new(Mutex::new(Http2Connection::handshake(connection)?));
+            ConnectionPool::global()
+                .lock()
+                .unwrap()
+                .add_http2(url, Arc::clone(&connection));
+
+            return Self::send_http2(&connection, request, cancellation, progress);
         }
 
-        Ok(Response::new(&response))
+        Self::send(connection, request, cancellation, progress)
     }
-}
 
-struct Cache {}
-
-impl Cache {
-    const CACHE_DIRECTORY_NAME: &'static str = "rbrowser";
+    fn send(
+        mut connection: Connection,
+        request: &Request,
+        cancellation: &Cancellation,
+        progress: &mut dyn FnMut(&ResponseStream),
+    ) -> Result<Response, NetworkError> {
+        let bytes = match connection.forward_proxy() {
+            Some(proxy) => request.build_for_proxy(proxy.authorization().as_deref()),
+            None => request.build(),
+        };
+        connection.send(&bytes, cancellation)?;
+
+        let mut stream = ResponseStream::new();
+        let reusable = connection.receive(request.method, &mut |bytes| {
+            if stream.receive(bytes) {
+                progress(&stream);
+            }
+        })?;
 
-    pub fn get(url: &Url) -> Option<String> {
-        let hashed_url = Self::calculate_hash(&url);
+        if reusable {
+            ConnectionPool::global().lock().unwrap().checkin(connection);
+        }
 
-        let mut dir = dirs::cache_dir().unwrap();
-        dir.push(Self::CACHE_DIRECTORY_NAME);
-        dir.push(hashed_url.to_string());
-        dir.set_extension("txt");
+        Ok(Self::process_response(stream.finish()?, request))
+    }
 
-        info!(%url, file = ?&dir, "Trying to load from cache");
+    // The connection is only locked while sending or reading a single frame, so other requests
+    // can use it in the meantime
+    fn send_http2(
+        connection: &Mutex<Http2Connection>,
+        request: &Request,
+        cancellation: &Cancellation,
+        progress: &mut dyn FnMut(&ResponseStream),
+    ) -> Result<Response, NetworkError> {
+        let stream_id = connection
+            .lock()
+            .unwrap()
+            .send_request(request, cancellation)?;
+        let mut stream = ResponseStream::new();
 
-        if let Ok(file_content) = fs::read_to_string(&dir) {
-            if let Some((first_line, rest)) = file_content.split_once("\r\n") {
-                let valid_until: u64 = first_line.parse().unwrap();
+        loop {
+            let (received, ended) = {
+                let mut connection = connection.lock().unwrap();
+                (
+                    connection.take_received(stream_id),
+                    connection.take_response(stream_id),
+                )
+            };
 
-                if valid_until > Self::now() {
-                    info!(%url, file = ?&dir, "Loaded from cache");
+            // progress is called without holding the lock, other requests can continue meanwhile
+            if stream.receive(&received) {
+                progress(&stream);
+            }
 
-                    return Some(rest.to_string());
-                }
+            match ended {
+                Some(result) => break result?,
+                None => connection
+                    .lock()
+                    .unwrap()
+                    .read_frame(stream_id, cancellation)?,
             }
         }
 
-        info!(%url, "Not cached");
-
-        None
+        Ok(Self::process_response(stream.finish()?, request))
     }
 
-    // Saves the response body in the user's cache folder, using the url's hash as the filename
-    // (plus ".txt" as extension). The first line of the file describes how long the file is
-    // active, in seconds since the start of the Unix Epoch (1970-01-01 00:00:00).
-    pub fn save(url: &Url, response: &Response) {
-        let hashed_url = Self::calculate_hash(&url);
-        let valid_until = Self::now() + u64::from(response.cache_max_age());
+    fn process_response(response: Response, request: &Request) -> Response {
+        CookieJar::global()
+            .lock()
+            .unwrap()
+            .store_from_response(&request.url, &response);
+        HstsStore::global()
+            .lock()
+            .unwrap()
+            .store_from_response(&request.url, &response);
 
-        let file_content = valid_until.to_string() + "\r\n" + &response.body;
+        response
+    }
+}
 
-        let mut dir = dirs::cache_dir().unwrap();
-        dir.push(Self::CACHE_DIRECTORY_NAME);
-        fs::create_dir_all(&dir).expect("Unable to write cache directory");
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        dir.push(hashed_url.to_string());
-        dir.set_extension("txt");
-        fs::write(&dir, &file_content).expect("Unable to write file");
+    fn post_request() -> Request {
+        let mut request = Request::new(Url::new("http://example.com/form").unwrap());
+        request.method = Method::Post;
+        request.body = Some(b"a=1".to_vec());
+        request
+    }
 
-        info!(file = ?&dir, "Wrote response to cache");
+    #[test]
+    fn redirect_with_see_other() {
+        let url = Url::new("http://example.com/result").unwrap();
+        let request = RequestHandler2::redirect(post_request(), url.clone(), 303);
+        assert_eq!(Method::Get, request.method);
+        assert_eq!(url, request.url);
+        assert_eq!(None, request.body);
     }
 
-    // from https://doc.rust-lang.org/std/hash/index.html
-    fn calculate_hash<T: Hash>(t: &T) -> u64 {
-        let mut s = DefaultHasher::new();
-        t.hash(&mut s);
-        s.finish()
+    #[test]
+    fn redirect_post_with_found() {
+        let url = Url::new("http://example.com/result").unwrap();
+        let request = RequestHandler2::redirect(post_request(), url, 302);
+        assert_eq!(Method::Get, request.method);
+        assert_eq!(None, request.body);
     }
 
-    fn now() -> u64 {
-        SystemTime::now()
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .unwrap()
-            .as_secs()
+    #[test]
+    fn redirect_with_temporary_and_permanent_redirect() {
+        for status_code in [307, 308] {
+            let url = Url::new("http://example.com/other").unwrap();
+            let request = RequestHandler2::redirect(post_request(), url, status_code);
+            assert_eq!(Method::Post, request.method);
+            assert_eq!(Some(b"a=1".to_vec()), request.body);
+        }
+    }
+
+    #[test]
+    fn redirect_head_with_see_other() {
+        let mut previous = Request::new(Url::new("http://example.com").unwrap());
+        previous.method = Method::Head;
+        let url = Url::new("http://example.com/other").unwrap();
+        let request = RequestHandler2::redirect(previous, url, 303);
+        assert_eq!(Method::Head, request.method);
     }
 }
@@ -1,191 +1,221 @@
-use encoding::all::ISO_8859_1;
-use encoding::{DecoderTrap, Encoding};
-use flate2::read::GzDecoder;
-use std::collections::HashMap;
-use std::io::Read;
+use std::fmt;
 use std::str;
 
-#[derive(Debug, PartialEq)]
-pub enum HttpStatus {
-    Ok = 200,
-    MovedPermanently = 301,
-    Found = 302,
-    TemporaryRedirect = 307,
-    PermanentRedirect = 308,
-    NotFound = 404,
-    Unsupported = 0, // catch all for states not supported by this browser
+use crate::headers::Headers;
+use crate::network_error::NetworkError;
+use crate::response_stream::ResponseStream;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum StatusClass {
+    Informational,
+    Success,
+    Redirection,
+    ClientError,
+    ServerError,
+    Unknown, // status codes outside of 100-599, e.g. from an invalid status line
 }
 
-type HeaderMap = HashMap<String, String>;
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct HttpStatus {
+    pub code: u16,
+    pub reason: String,
+}
+
+impl HttpStatus {
+    // Creates a status with the given reason phrase, or with the standard reason phrase of the
+    // status code if the server didn't send one.
+    pub fn new(code: u16, reason: &str) -> Self {
+        let reason = if reason.is_empty() {
+            Self::standard_reason(code).to_string()
+        } else {
+            reason.to_string()
+        };
+
+        Self { code, reason }
+    }
+
+    pub const fn class(&self) -> StatusClass {
+        match self.code {
+            100..=199 => StatusClass::Informational,
+            200..=299 => StatusClass::Success,
+            300..=399 => StatusClass::Redirection,
+            400..=499 => StatusClass::ClientError,
+            500..=599 => StatusClass::ServerError,
+            _ => StatusClass::Unknown,
+        }
+    }
+
+    // Only the 3xx codes with a Location to follow, 300 and 304 are not redirects in this sense
+    pub const fn is_redirect(&self) -> bool {
+        matches!(self.code, 301 | 302 | 303 | 307 | 308)
+    }
+
+    pub fn is_error(&self) -> bool {
+        matches!(
+            self.class(),
+            StatusClass::ClientError | StatusClass::ServerError
+        )
+    }
+
+    // see https://www.iana.org/assignments/http-status-codes/http-status-codes.xhtml
+    const fn standard_reason(code: u16) -> &'static str {
+        match code {
+            100 => "Continue",
+            101 => "Switching Protocols",
+            103 => "Early Hints",
+            200 => "OK",
+            201 => "Created",
+            202 => "Accepted",
+            203 => "Non-Authoritative Information",
+            204 => "No Content",
+            205 => "Reset Content",
+            206 => "Partial Content",
+            300 => "Multiple Choices",
+            301 => "Moved Permanently",
+            302 => "Found",
+            303 => "See Other",
+            304 => "Not Modified",
+            307 => "Temporary Redirect",
+            308 => "Permanent Redirect",
+            400 => "Bad Request",
+            401 => "Unauthorized",
+            402 => "Payment Required",
+            403 => "Forbidden",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            406 => "Not Acceptable",
+            407 => "Proxy Authentication Required",
+            408 => "Request Timeout",
+            409 => "Conflict",
+            410 => "Gone",
+            411 => "Length Required",
+            412 => "Precondition Failed",
+            413 => "Content Too Large",
+            414 => "URI Too Long",
+            415 => "Unsupported Media Type",
+            416 => "Range Not Satisfiable",
+            417 => "Expectation Failed",
+            421 => "Misdirected Request",
+            422 => "Unprocessable Content",
+            425 => "Too Early",
+            426 => "Upgrade Required",
+            428 => "Precondition Required",
+            429 => "Too Many Requests",
+            431 => "Request Header Fields Too Large",
+            451 => "Unavailable For Legal Reasons",
+            500 => "Internal Server Error",
+            501 => "Not Implemented",
+            502 => "Bad Gateway",
+            503 => "Service Unavailable",
+            504 => "Gateway Timeout",
+            505 => "HTTP Version Not Supported",
+            511 => "Network Authentication Required",
+            _ => "",
+        }
+    }
+}
+
+impl fmt::Display for HttpStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.code, self.reason)
+    }
+}
 
 #[derive(Debug)]
 pub struct Response {
     pub status: HttpStatus,
-    headers: HeaderMap,
+    headers: Headers,
+    raw_body: Vec<u8>,
     pub body: String,
 }
 
 impl Response {
-    pub fn new(bytes: &[u8]) -> Self {
-        const SEPARATOR: &[u8] = b"\r\n\r\n";
-
-        let (header_bytes, body_bytes) = match Self::find_subsequence(bytes, SEPARATOR) {
-            Some(pos) => (&bytes[0..pos], &bytes[(pos + SEPARATOR.len())..]),
-            _ => (bytes, &bytes[bytes.len()..]),
-        };
-
-        let (status, headers) = HeaderParser::parse(header_bytes);
-        let body = BodyParser::parse(body_bytes, &headers);
+    // Parses a complete response in the HTTP/1.1 form
+    pub fn new(bytes: &[u8]) -> Result<Self, NetworkError> {
+        let mut stream = ResponseStream::new();
+        stream.receive(bytes);
+        stream.finish()
+    }
 
+    // The raw body is the body without transfer coding, the body is also content decoded
+    pub const fn from_parts(
+        status: HttpStatus,
+        headers: Headers,
+        raw_body: Vec<u8>,
+        body: String,
+    ) -> Self {
         Self {
             status,
             headers,
+            raw_body,
             body,
         }
     }
 
-    pub fn cache_max_age(&self) -> u32 {
-        if let Some(cache_control) = self.header("Cache-Control") {
-            if let Some((_, s)) = cache_control.split_once("max-age=") {
-                let max_age: String = s.chars().take_while(|c| c.is_digit(10)).collect();
-
-                return max_age.parse().unwrap();
-            }
-        }
+    // The body before content decoding, e.g. still gzip compressed
+    pub fn raw_body(&self) -> &[u8] {
+        &self.raw_body
+    }
 
-        0
+    pub fn header(&self, k: &str) -> Option<&str> {
+        self.headers.get(k)
     }
 
-    pub fn header(&self, k: &str) -> Option<&String> {
-        self.headers.get(&k.to_ascii_lowercase())
+    pub const fn headers(&self) -> &Headers {
+        &self.headers
     }
 
-    pub fn is_redirect(&self) -> bool {
-        [
-            HttpStatus::MovedPermanently,
-            HttpStatus::Found,
-            HttpStatus::TemporaryRedirect,
-            HttpStatus::PermanentRedirect,
-        ]
-        .contains(&self.status)
+    pub const fn is_redirect(&self) -> bool {
+        self.status.is_redirect()
     }
 
-    fn find_subsequence(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    pub fn find_subsequence(haystack: &[u8], needle: &[u8]) -> Option<usize> {
         haystack
             .windows(needle.len())
             .position(|window| window == needle)
     }
 }
 
-struct HeaderParser {}
+pub struct HeaderParser {}
 
 impl HeaderParser {
-    pub fn parse(headers: &[u8]) -> (HttpStatus, HeaderMap) {
-        // headers are ASCII, hence there should be no problem to turn them to UTF-8
-        let header_content = String::from_utf8(headers.to_vec()).unwrap();
+    pub fn parse(headers: &[u8]) -> Result<(HttpStatus, Headers), NetworkError> {
+        // headers are ASCII, anything else is not a valid response
+        let header_content = str::from_utf8(headers)
+            .map_err(|_| NetworkError::MalformedResponse("headers are not valid UTF-8"))?;
         let mut lines = header_content.lines();
 
         let status = match lines.next() {
             Some(line) => Self::parse_status(line),
-            _ => HttpStatus::Unsupported,
+            _ => HttpStatus::new(0, "Missing Status Line"),
         };
 
-        let mut headers = HashMap::new();
+        let mut headers = Headers::new();
         for line in lines {
-            if let Some((k, v)) = Self::split_into_key_value(line) {
-                headers.insert(k, v);
+            // lines starting with whitespace continue the previous header (obsolete line folding)
+            if line.starts_with([' ', '\t']) {
+                headers.extend_last(line.trim());
+            } else if let Some((k, v)) = Self::split_into_key_value(line) {
+                headers.append(&k, &v);
             }
         }
 
-        (status, headers)
+        Ok((status, headers))
     }
 
+    // The status line has the format: <version> <status code> [<reason phrase>]
     fn parse_status(s: &str) -> HttpStatus {
-        s.split(' ')
-            .nth(1)
-            .map_or(HttpStatus::Unsupported, |status_code| match status_code {
-                "200" => HttpStatus::Ok,
-                "301" => HttpStatus::MovedPermanently,
-                "302" => HttpStatus::Found,
-                "307" => HttpStatus::TemporaryRedirect,
-                "308" => HttpStatus::PermanentRedirect,
-                "404" => HttpStatus::NotFound,
-                _ => HttpStatus::Unsupported,
-            })
-    }
-
-    fn split_into_key_value(s: &str) -> Option<(String, String)> {
-        s.split_once(':')
-            .map(|(k, v)| (k.to_ascii_lowercase(), v.trim().to_string()))
-    }
-}
-
-struct BodyParser {}
-
-impl BodyParser {
-    pub fn parse(body: &[u8], headers: &HeaderMap) -> String {
-        let body = if headers.contains_key("transfer-encoding") {
-            Self::dechunk(body)
-        } else {
-            body.to_vec()
-        };
-
-        let body = if headers.contains_key("content-encoding") {
-            Self::unzip_and_decode(&body)
-        } else if let Ok(s) = String::from_utf8(body.clone()) {
-            s
-        } else {
-            ISO_8859_1.decode(&body, DecoderTrap::Strict).unwrap()
-        };
-
-        body
-    }
-
-    fn dechunk(body: &[u8]) -> Vec<u8> {
-        const TERMINATING_CHUNK_SIZE: usize = 0;
-        let mut dechunked = Vec::new();
-        let mut i = 0;
-
-        // Each chunk has the format: <chunk size in hex>\r\n<chunk data>\r\n
-        loop {
-            let chunk_size = {
-                let mut chars = Vec::new();
-
-                while body[i] != b'\r' {
-                    chars.push(body[i] as char);
-                    i += 1;
-                }
-
-                let s: String = chars.into_iter().collect();
-                usize::from_str_radix(&s, 16).unwrap()
-            };
-
-            if chunk_size == TERMINATING_CHUNK_SIZE {
-                break;
-            }
-
-            let data_position = i + b"\r\n".len();
+        let mut parts = s.splitn(3, ' ');
 
-            dechunked.extend_from_slice(&body[data_position..(data_position + chunk_size)]);
-
-            i = data_position + chunk_size + b"\r\n".len();
+        match parts.nth(1).map(str::parse::<u16>) {
+            Some(Ok(code)) => HttpStatus::new(code, parts.next().unwrap_or_default().trim()),
+            _ => HttpStatus::new(0, "Invalid Status Line"),
         }
-
-        dechunked
     }
 
-    // XXX supports UTF-8 and ISO-8859-1, everything else crashes
-    fn unzip_and_decode(body: &[u8]) -> String {
-        let mut decoder = GzDecoder::new(body);
-        let mut s = String::new();
-        if decoder.read_to_string(&mut s).is_ok() {
-            s
-        } else {
-            let mut decoder = GzDecoder::new(body);
-            let mut v = Vec::new();
-            decoder.read_to_end(&mut v).unwrap();
-            ISO_8859_1.decode(&v, DecoderTrap::Strict).unwrap()
-        }
+    fn split_into_key_value(s: &str) -> Option<(String, String)> {
+        s.split_once(':')
+            .map(|(k, v)| (k.trim().to_string(), v.trim().to_string()))
     }
 }
 
@@ -204,13 +234,11 @@ Server: Apache\r\n\
 Content-Type: text/html\r\n\
 \r\n\
 Some Content",
-        );
-        assert_eq!(HttpStatus::Ok, response.status);
-        assert_eq!("Apache".to_string(), *response.header("Server").unwrap());
-        assert_eq!(
-            "text/html".to_string(),
-            *response.header("Content-Type").unwrap()
-        );
+        )
+        .unwrap();
+        assert_eq!(HttpStatus::new(200, "OK"), response.status);
+        assert_eq!(Some("Apache"), response.header("Server"));
+        assert_eq!(Some("text/html"), response.header("Content-Type"));
         assert_eq!("Some Content".to_string(), response.body);
     }
 
@@ -225,11 +253,26 @@ Some Content",
             .to_vec();
         response.append(&mut content);
 
-        let response = Response::new(&response);
-        assert_eq!(HttpStatus::Ok, response.status);
+        let response = Response::new(&response).unwrap();
+        assert_eq!(HttpStatus::new(200, "OK"), response.status);
         assert_eq!("Hello World".to_string(), response.body);
     }
 
+    #[test]
+    fn new_without_body_with_content_encoding() {
+        let heads: [&[u8]; 3] = [
+            // the response to a HEAD request
+            b"HTTP/1.1 200 OK\r\nContent-Encoding: gzip\r\nContent-Length: 20\r\n\r\n",
+            b"HTTP/1.1 204 No Content\r\nContent-Encoding: br\r\n\r\n",
+            b"HTTP/1.1 304 Not Modified\r\nContent-Encoding: deflate\r\n\r\n",
+        ];
+
+        for head in heads {
+            let response = Response::new(head).unwrap();
+            assert_eq!("", response.body);
+        }
+    }
+
     #[test]
     fn new_chunk_encoded_response() {
         let response = Response::new(
@@ -246,42 +289,111 @@ Some Content",
             chunks.\r\n\
             0\r\n\
             \r\n",
-        );
-        assert_eq!(HttpStatus::Ok, response.status);
+        )
+        .unwrap();
+        assert_eq!(HttpStatus::new(200, "OK"), response.status);
         assert_eq!("Wikipedia in \r\n\r\nchunks.", response.body);
     }
 
     #[test]
-    fn cache_max_age() {
+    fn new_with_repeated_header() {
         let response = Response::new(
             b"HTTP/1.1 200 OK\r\n\
-                                       Cache-Control: max-age=600\r\n\r\n",
+            Set-Cookie: a=1\r\n\
+            Set-Cookie: b=2\r\n\r\n",
+        )
+        .unwrap();
+        assert_eq!(Some("a=1"), response.header("Set-Cookie"));
+        assert_eq!(
+            vec!["a=1", "b=2"],
+            response
+                .headers()
+                .get_all("Set-Cookie")
+                .collect::<Vec<&str>>()
+        );
+    }
+
+    #[test]
+    fn new_with_truncated_chunked_body() {
+        let response = Response::new(
+            b"HTTP/1.1 200 OK\r\n\
+            Transfer-Encoding: chunked\r\n\
+            \r\n\
+            A\r\n\
+            Wiki",
         );
-        assert_eq!(600, response.cache_max_age());
+        assert!(matches!(
+            response,
+            Err(NetworkError::MalformedResponse("truncated chunked body"))
+        ));
     }
 
     #[test]
-    fn cache_max_age_with_other_directive() {
+    fn new_with_huge_chunk_size() {
         let response = Response::new(
             b"HTTP/1.1 200 OK\r\n\
-                                       Cache-Control: private, max-age=600\r\n\r\n",
+            Transfer-Encoding: chunked\r\n\
+            \r\n\
+            FFFFFFFFFFFFFFFF\r\n\
+            Wiki\r\n",
         );
-        assert_eq!(600, response.cache_max_age());
+        assert!(matches!(
+            response,
+            Err(NetworkError::MalformedResponse("truncated chunked body"))
+        ));
     }
 
     #[test]
-    fn cache_max_age_with_missing_cache_control_header() {
-        let response = Response::new(b"HTTP/1.1 200 OK\r\n\r\n");
-        assert_eq!(0, response.cache_max_age());
+    fn new_with_invalid_chunk_size() {
+        let response = Response::new(
+            b"HTTP/1.1 200 OK\r\n\
+            Transfer-Encoding: chunked\r\n\
+            \r\n\
+            XYZ\r\n\
+            Wiki\r\n",
+        );
+        assert!(matches!(
+            response,
+            Err(NetworkError::MalformedResponse("invalid chunk size"))
+        ));
     }
 
     #[test]
-    fn cache_max_age_with_missing_max_age() {
+    fn new_with_invalid_gzip_body() {
         let response = Response::new(
             b"HTTP/1.1 200 OK\r\n\
-                                       Cache-Control: no-store\r\n\r\n",
+            Content-Encoding: gzip\r\n\
+            \r\n\
+            not gzip",
         );
-        assert_eq!(0, response.cache_max_age());
+        assert!(matches!(response, Err(NetworkError::Decoding(_))));
+    }
+
+    #[test]
+    fn new_with_iso_8859_1_body() {
+        let response = Response::new(b"HTTP/1.1 200 OK\r\n\r\nCaf\xe9").unwrap();
+        assert_eq!("Caf\u{e9}", response.body);
+    }
+
+    #[test]
+    fn new_with_charset() {
+        let response = Response::new(
+            b"HTTP/1.1 200 OK\r\n\
+            Content-Type: text/plain; charset=Shift_JIS\r\n\
+            \r\n\
+            \x93\xfa\x96\x7b",
+        )
+        .unwrap();
+        assert_eq!("\u{65e5}\u{672c}", response.body);
+    }
+
+    #[test]
+    fn parse_headers_with_invalid_utf8() {
+        let header_bytes = b"HTTP/1.1 200 OK\r\nHeader-A: \xff";
+        assert!(matches!(
+            HeaderParser::parse(header_bytes),
+            Err(NetworkError::MalformedResponse(_))
+        ));
     }
 
     #[test]
@@ -290,57 +402,88 @@ Some Content",
                              Header-A: Value A\r\n\
                              Header-B: Value B";
 
-        let (status, headers) = HeaderParser::parse(header_bytes);
-        assert_eq!(HttpStatus::Ok, status);
-        assert_eq!("Value A".to_string(), *headers.get("header-a").unwrap());
-        assert_eq!("Value B".to_string(), *headers.get("header-b").unwrap());
+        let (status, headers) = HeaderParser::parse(header_bytes).unwrap();
+        assert_eq!(HttpStatus::new(200, "OK"), status);
+        assert_eq!(Some("Value A"), headers.get("header-a"));
+        assert_eq!(Some("Value B"), headers.get("header-b"));
     }
 
     #[test]
-    fn parse_supported_states() {
-        assert_eq!(
-            HttpStatus::Ok,
-            HeaderParser::parse_status("HTTP/1.1 200 OK")
-        );
-        assert_eq!(
-            HttpStatus::MovedPermanently,
-            HeaderParser::parse_status("HTTP/1.1 301 Moved Permanently")
-        );
-        assert_eq!(
-            HttpStatus::Found,
-            HeaderParser::parse_status("HTTP/1.1 302 Found")
-        );
-        assert_eq!(
-            HttpStatus::TemporaryRedirect,
-            HeaderParser::parse_status("HTTP/1.1 307 Temporary Redirect")
-        );
-        assert_eq!(
-            HttpStatus::PermanentRedirect,
-            HeaderParser::parse_status("HTTP/1.1 308 Permanent Redirect")
-        );
-        assert_eq!(
-            HttpStatus::NotFound,
-            HeaderParser::parse_status("HTTP/1.1 404 Not Found")
-        );
+    fn parse_folded_headers() {
+        let header_bytes = b"HTTP/1.1 200 OK\r\n\
+                             Header-A: first\r\n \
+                             \tsecond\r\n\
+                             Header-B: Value B";
+
+        let (_, headers) = HeaderParser::parse(header_bytes).unwrap();
+        assert_eq!(Some("first second"), headers.get("Header-A"));
+        assert_eq!(Some("Value B"), headers.get("Header-B"));
     }
 
     #[test]
-    fn parse_unsupported_status() {
-        assert_eq!(
-            HttpStatus::Unsupported,
-            HeaderParser::parse_status("HTTP/1.1 208 Already Reported")
-        );
+    fn parse_status() {
+        let status = HeaderParser::parse_status("HTTP/1.1 503 Service Unavailable");
+        assert_eq!(503, status.code);
+        assert_eq!("Service Unavailable", status.reason);
+        assert_eq!(StatusClass::ServerError, status.class());
+    }
+
+    #[test]
+    fn parse_status_without_reason() {
+        let status = HeaderParser::parse_status("HTTP/1.1 429");
+        assert_eq!(HttpStatus::new(429, "Too Many Requests"), status);
+    }
+
+    #[test]
+    fn parse_status_with_unknown_code() {
+        let status = HeaderParser::parse_status("HTTP/1.1 299 Custom Reason");
+        assert_eq!(HttpStatus::new(299, "Custom Reason"), status);
+        assert_eq!(StatusClass::Success, status.class());
     }
 
     #[test]
     fn parse_invalid_status() {
-        assert_eq!(HttpStatus::Unsupported, HeaderParser::parse_status("200"));
+        let status = HeaderParser::parse_status("200");
+        assert_eq!(0, status.code);
+        assert_eq!(StatusClass::Unknown, status.class());
+    }
+
+    #[test]
+    fn status_classes() {
+        assert_eq!(StatusClass::Informational, HttpStatus::new(100, "").class());
+        assert_eq!(StatusClass::Success, HttpStatus::new(204, "").class());
+        assert_eq!(StatusClass::Redirection, HttpStatus::new(304, "").class());
+        assert_eq!(StatusClass::ClientError, HttpStatus::new(401, "").class());
+        assert_eq!(StatusClass::ServerError, HttpStatus::new(500, "").class());
+        assert_eq!(StatusClass::Unknown, HttpStatus::new(600, "").class());
+    }
+
+    #[test]
+    fn redirect_states() {
+        for code in [301, 302, 303, 307, 308] {
+            assert!(HttpStatus::new(code, "").is_redirect());
+        }
+        assert!(!HttpStatus::new(300, "").is_redirect());
+        assert!(!HttpStatus::new(304, "").is_redirect());
+    }
+
+    #[test]
+    fn error_states() {
+        assert!(HttpStatus::new(404, "").is_error());
+        assert!(HttpStatus::new(503, "").is_error());
+        assert!(!HttpStatus::new(200, "").is_error());
+        assert!(!HttpStatus::new(302, "").is_error());
+    }
+
+    #[test]
+    fn display_status() {
+        assert_eq!("404 Not Found", HttpStatus::new(404, "").to_string());
     }
 
     #[test]
     fn split_into_key_value() {
         let result = HeaderParser::split_into_key_value("Header: value").unwrap();
-        assert_eq!(("header".to_string(), "value".to_string()), result);
+        assert_eq!(("Header".to_string(), "value".to_string()), result);
     }
 
     #[test]
@@ -0,0 +1,204 @@
+use tracing::info;
+
+use crate::body_decoder::BodyDecoder;
+use crate::charset::Charset;
+use crate::config::Config;
+use crate::headers::Headers;
+use crate::network_error::NetworkError;
+use crate::response::{HeaderParser, HttpStatus, Response};
+
+enum State {
+    // the status line and headers received so far
+    Head(Vec<u8>),
+    Body(HttpStatus, Headers, BodyDecoder),
+    // invalid headers or a body which can't be decoded, the error is reported by finish
+    Failed(NetworkError),
+}
+
+// A response while it is being received, in the HTTP/1.1 form. Once the headers are complete, the
+// body is decoded piece by piece, so it can be shown before the download is finished. When
+// everything is received, the stream is finished into the response.
+pub struct ResponseStream {
+    state: State,
+}
+
+impl ResponseStream {
+    const SEPARATOR: &'static [u8] = b"\r\n\r\n";
+
+    pub const fn new() -> Self {
+        Self {
+            state: State::Head(Vec::new()),
+        }
+    }
+
+    // Returns whether the decoded body has grown
+    pub fn receive(&mut self, bytes: &[u8]) -> bool {
+        let State::Head(head) = &mut self.state else {
+            return self.decode(bytes);
+        };
+
+        // the separator might be split between two pieces
+        let start = head.len().saturating_sub(Self::SEPARATOR.len() - 1);
+        head.extend_from_slice(bytes);
+
+        let Some(position) = Response::find_subsequence(&head[start..], Self::SEPARATOR) else {
+            return false;
+        };
+        let body = head.split_off(start + position + Self::SEPARATOR.len());
+        head.truncate(start + position);
+
+        self.state = match Self::parse_head(head) {
+            Ok((status, headers, decoder)) => State::Body(status, headers, decoder),
+            Err(e) => State::Failed(e),
+        };
+        self.decode(&body)
+    }
+
+    pub const fn status(&self) -> Option<&HttpStatus> {
+        match &self.state {
+            State::Body(status, _, _) => Some(status),
+            _ => None,
+        }
+    }
+
+    // The body received so far, decoded to text
+    pub fn body(&self) -> String {
+        match &self.state {
+            State::Body(_, headers, decoder) => Charset::decode_partial(&decoder.body(), headers),
+            _ => String::new(),
+        }
+    }
+
+    // The complete response, once everything has been received
+    pub fn finish(self) -> Result<Response, NetworkError> {
+        let (status, headers, decoder) = match self.state {
+            // without the empty line after the headers, everything received is taken as headers
+            State::Head(head) => Self::parse_head(&head)?,
+            State::Body(status, headers, decoder) => (status, headers, decoder),
+            State::Failed(e) => return Err(e),
+        };
+
+        let (raw_body, body) = decoder.finish()?;
+        let body = Charset::decode(&body, &headers);
+        Ok(Response::from_parts(status, headers, raw_body, body))
+    }
+
+    fn parse_head(head: &[u8]) -> Result<(HttpStatus, Headers, BodyDecoder), NetworkError> {
+        let (status, headers) = HeaderParser::parse(head)?;
+        let max_size = Config::global().size_limits.decoded_body_size;
+        let decoder = BodyDecoder::new(&headers, max_size)?;
+
+        Ok((status, headers, decoder))
+    }
+
+    // Returns whether the decoded body has grown
+    fn decode(&mut self, bytes: &[u8]) -> bool {
+        let State::Body(_, _, decoder) = &mut self.state else {
+            return false;
+        };
+        let size = decoder.body().len();
+
+        match decoder.feed(bytes) {
+            Ok(()) => decoder.body().len() > size,
+            Err(e) => {
+                info!(error = %e, "Unable to decode the body while receiving it");
+                self.state = State::Failed(e);
+                false
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn receive_in_pieces() {
+        let response = b"HTTP/1.1 200 OK\r\n\
+                         Transfer-Encoding: chunked\r\n\
+                         \r\n\
+                         4\r\nWiki\r\n5\r\npedia\r\n0\r\n\r\n";
+
+        for piece_size in [1, 7, response.len()] {
+            let mut stream = ResponseStream::new();
+            for piece in response.chunks(piece_size) {
+                stream.receive(piece);
+            }
+
+            assert_eq!(Some(&HttpStatus::new(200, "OK")), stream.status());
+            assert_eq!("Wikipedia", stream.body());
+        }
+    }
+
+    #[test]
+    fn receive_partial_body() {
+        let mut stream = ResponseStream::new();
+        assert!(!stream.receive(b"HTTP/1.1 404 Not Found\r\n"));
+        assert_eq!(None, stream.status());
+
+        assert!(stream.receive(b"Content-Length: 10\r\n\r\nHello"));
+        assert_eq!(404, stream.status().unwrap().code);
+        assert_eq!("Hello", stream.body());
+        assert!(!stream.receive(b""));
+    }
+
+    #[test]
+    fn receive_utf_8_in_pieces_splitting_characters() {
+        let text = "<p>日本語のページ</p>";
+        let mut response = b"HTTP/1.1 200 OK\r\nContent-Type: text/html\r\n\r\n".to_vec();
+        response.extend_from_slice(text.as_bytes());
+
+        let mut stream = ResponseStream::new();
+        for piece in response.chunks(2) {
+            stream.receive(piece);
+            assert!(text.starts_with(&stream.body()), "{}", stream.body());
+        }
+        assert_eq!(text, stream.body());
+    }
+
+    #[test]
+    fn receive_unsupported_encoding() {
+        let mut stream = ResponseStream::new();
+        assert!(!stream.receive(b"HTTP/1.1 200 OK\r\nContent-Encoding: compress\r\n\r\nabc"));
+        assert_eq!("", stream.body());
+        assert!(matches!(
+            stream.finish(),
+            Err(NetworkError::UnsupportedContentEncoding(_))
+        ));
+    }
+
+    #[test]
+    fn finish() {
+        let mut stream = ResponseStream::new();
+        stream.receive(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n4\r\nWi");
+        stream.receive(b"ki\r\n0\r\n\r\n");
+
+        let response = stream.finish().unwrap();
+        assert_eq!(HttpStatus::new(200, "OK"), response.status);
+        assert_eq!(Some("chunked"), response.header("Transfer-Encoding"));
+        assert_eq!(b"Wiki", response.raw_body());
+        assert_eq!("Wiki", response.body);
+    }
+
+    #[test]
+    fn finish_without_body() {
+        let mut stream = ResponseStream::new();
+        stream.receive(b"HTTP/1.1 204 No Content\r\n");
+
+        let response = stream.finish().unwrap();
+        assert_eq!(204, response.status.code);
+        assert_eq!("", response.body);
+    }
+
+    #[test]
+    fn finish_with_invalid_headers() {
+        let mut stream = ResponseStream::new();
+        assert!(!stream.receive(b"HTTP/1.1 200 OK\r\nA: \xff\r\n\r\nabc"));
+        assert_eq!(None, stream.status());
+        assert!(matches!(
+            stream.finish(),
+            Err(NetworkError::MalformedResponse(_))
+        ));
+    }
+}
@@ -0,0 +1,570 @@
+use std::error::Error;
+use std::fmt;
+
+use crate::dom::{Document, Element, NodeId};
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum SelectorError {
+    Empty,
+    InvalidNthChild(String),
+    UnexpectedCharacter(char),
+    UnexpectedEnd,
+    UnsupportedPseudoClass(String),
+}
+
+impl Error for SelectorError {}
+
+impl fmt::Display for SelectorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty => write!(f, "Invalid selector: empty"),
+            Self::InvalidNthChild(argument) => {
+                write!(f, "Invalid selector: invalid :nth-child({})", argument)
+            }
+            Self::UnexpectedCharacter(c) => {
+                write!(f, "Invalid selector: unexpected character '{}'", c)
+            }
+            Self::UnexpectedEnd => write!(f, "Invalid selector: unexpected end"),
+            Self::UnsupportedPseudoClass(name) => {
+                write!(f, "Invalid selector: unsupported pseudo-class :{}", name)
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum AttributeOperator {
+    Exists,
+    // [a=b]
+    Equals(String),
+    // [a~=b], b is one of the whitespace-separated words
+    Includes(String),
+    // [a|=b], b or b followed by "-"
+    DashMatch(String),
+    // [a^=b]
+    Prefix(String),
+    // [a$=b]
+    Suffix(String),
+    // [a*=b]
+    Substring(String),
+}
+
+impl AttributeOperator {
+    fn matches(&self, value: &str) -> bool {
+        match self {
+            Self::Exists => true,
+            Self::Equals(expected) => value == expected,
+            Self::Includes(word) => value.split_ascii_whitespace().any(|w| w == word),
+            Self::DashMatch(expected) => {
+                value == expected
+                    || value
+                        .strip_prefix(expected.as_str())
+                        .map_or(false, |rest| rest.starts_with('-'))
+            }
+            Self::Prefix(s) => !s.is_empty() && value.starts_with(s.as_str()),
+            Self::Suffix(s) => !s.is_empty() && value.ends_with(s.as_str()),
+            Self::Substring(s) => !s.is_empty() && value.contains(s.as_str()),
+        }
+    }
+}
+
+// A sequence of simple selectors without combinators, like div.a#b[c]:not(.d)
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+struct Compound {
+    // None for the universal selector
+    name: Option<String>,
+    ids: Vec<String>,
+    classes: Vec<String>,
+    attributes: Vec<(String, AttributeOperator)>,
+    // (a, b) of :nth-child(an+b)
+    nth_child: Vec<(i32, i32)>,
+    not: Vec<Selector>,
+}
+
+impl Compound {
+    fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    fn matches(&self, document: &Document, id: NodeId, element: &Element) -> bool {
+        let classes = || element.attribute("class").unwrap_or_default();
+
+        self.name
+            .as_ref()
+            .map_or(true, |name| *name == element.name)
+            && self
+                .ids
+                .iter()
+                .all(|id| element.attribute("id") == Some(id.as_str()))
+            && self
+                .classes
+                .iter()
+                .all(|class| classes().split_ascii_whitespace().any(|c| c == class))
+            && self.attributes.iter().all(|(name, operator)| {
+                element
+                    .attribute(name)
+                    .map_or(false, |value| operator.matches(value))
+            })
+            && self
+                .nth_child
+                .iter()
+                .all(|&(a, b)| Self::is_nth(a, b, Self::position(document, id)))
+            && !self.not.iter().any(|not| not.matches(document, id))
+    }
+
+    // The position among the element siblings, starting at 1
+    fn position(document: &Document, id: NodeId) -> i64 {
+        document.previous_element_siblings(id).len() as i64 + 1
+    }
+
+    // Whether there is an n >= 0 with a * n + b = position
+    const fn is_nth(a: i32, b: i32, position: i64) -> bool {
+        // a and b are limited to i32, so the calculation can't overflow
+        let difference = position - b as i64;
+
+        if a == 0 {
+            difference == 0
+        } else {
+            difference % a as i64 == 0 && difference / a as i64 >= 0
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Combinator {
+    // "a b"
+    Descendant,
+    // "a > b"
+    Child,
+    // "a + b"
+    NextSibling,
+    // "a ~ b"
+    SubsequentSibling,
+}
+
+// Compounds joined by combinators, like "ul > li.a + li"
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Complex {
+    compounds: Vec<Compound>,
+    // combinators[i] is between compounds[i] and compounds[i + 1]
+    combinators: Vec<Combinator>,
+}
+
+impl Complex {
+    // Matched from right to left, as the rightmost compound is the one the element has to match
+    fn matches(&self, document: &Document, id: NodeId, index: usize) -> bool {
+        let Some(element) = document.element(id) else {
+            return false;
+        };
+        if !self.compounds[index].matches(document, id, element) {
+            return false;
+        } else if index == 0 {
+            return true;
+        }
+
+        let previous = index - 1;
+        match self.combinators[previous] {
+            Combinator::Descendant => document
+                .ancestors(id)
+                .into_iter()
+                .any(|ancestor| self.matches(document, ancestor, previous)),
+            Combinator::Child => document
+                .parent(id)
+                .map_or(false, |parent| self.matches(document, parent, previous)),
+            Combinator::NextSibling => document
+                .previous_element_siblings(id)
+                .last()
+                .map_or(false, |&sibling| self.matches(document, sibling, previous)),
+            Combinator::SubsequentSibling => document
+                .previous_element_siblings(id)
+                .into_iter()
+                .any(|sibling| self.matches(document, sibling, previous)),
+        }
+    }
+}
+
+// A list of comma-separated CSS selectors, see https://www.w3.org/TR/selectors-4/
+//
+// Supported are type, universal, class, id and attribute selectors, the descendant, child, next
+// and subsequent sibling combinators, and the pseudo-classes :nth-child() and :not(). Escapes and
+// namespaces are not supported.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Selector {
+    complexes: Vec<Complex>,
+}
+
+impl Selector {
+    pub fn parse(s: &str) -> Result<Self, SelectorError> {
+        let mut parser = Parser {
+            input: s.chars().collect(),
+            position: 0,
+        };
+        let selector = parser.selector_list()?;
+
+        match parser.peek() {
+            None => Ok(selector),
+            Some(c) => Err(SelectorError::UnexpectedCharacter(c)),
+        }
+    }
+
+    pub fn matches(&self, document: &Document, id: NodeId) -> bool {
+        self.complexes
+            .iter()
+            .any(|complex| complex.matches(document, id, complex.compounds.len() - 1))
+    }
+}
+
+struct Parser {
+    input: Vec<char>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.input.get(self.position).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek();
+        self.position += 1;
+        c
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), SelectorError> {
+        match self.next() {
+            Some(c) if c == expected => Ok(()),
+            Some(c) => Err(SelectorError::UnexpectedCharacter(c)),
+            None => Err(SelectorError::UnexpectedEnd),
+        }
+    }
+
+    // Returns whether there was whitespace
+    fn skip_whitespace(&mut self) -> bool {
+        let start = self.position;
+        while self.peek().map_or(false, char::is_whitespace) {
+            self.position += 1;
+        }
+        self.position > start
+    }
+
+    fn selector_list(&mut self) -> Result<Selector, SelectorError> {
+        let mut complexes = vec![self.complex()?];
+
+        while self.peek() == Some(',') {
+            self.position += 1;
+            complexes.push(self.complex()?);
+        }
+
+        Ok(Selector { complexes })
+    }
+
+    fn complex(&mut self) -> Result<Complex, SelectorError> {
+        self.skip_whitespace();
+        let mut complex = Complex {
+            compounds: vec![self.compound()?],
+            combinators: Vec::new(),
+        };
+
+        loop {
+            let whitespace = self.skip_whitespace();
+            let combinator = match self.peek() {
+                Some('>') => Combinator::Child,
+                Some('+') => Combinator::NextSibling,
+                Some('~') => Combinator::SubsequentSibling,
+                // the end of the selector or of the argument of :not()
+                None | Some(',' | ')') => return Ok(complex),
+                Some(_) if whitespace => Combinator::Descendant,
+                Some(c) => return Err(SelectorError::UnexpectedCharacter(c)),
+            };
+            if combinator != Combinator::Descendant {
+                self.position += 1;
+                self.skip_whitespace();
+            }
+
+            complex.combinators.push(combinator);
+            complex.compounds.push(self.compound()?);
+        }
+    }
+
+    fn compound(&mut self) -> Result<Compound, SelectorError> {
+        let mut compound = Compound::default();
+        let is_universal = self.peek() == Some('*');
+
+        if is_universal {
+            self.position += 1;
+        } else if self.peek().map_or(false, Self::is_name_char) {
+            compound.name = Some(self.name()?.to_ascii_lowercase());
+        }
+
+        loop {
+            match self.peek() {
+                Some('#') => {
+                    self.position += 1;
+                    compound.ids.push(self.name()?);
+                }
+                Some('.') => {
+                    self.position += 1;
+                    compound.classes.push(self.name()?);
+                }
+                Some('[') => {
+                    self.position += 1;
+                    compound.attributes.push(self.attribute()?);
+                }
+                Some(':') => {
+                    self.position += 1;
+                    self.pseudo_class(&mut compound)?;
+                }
+                _ => break,
+            }
+        }
+
+        if compound.is_empty() && !is_universal {
+            return Err(match self.peek() {
+                Some(c) => SelectorError::UnexpectedCharacter(c),
+                None if self.input.iter().all(|c| c.is_whitespace()) => SelectorError::Empty,
+                None => SelectorError::UnexpectedEnd,
+            });
+        }
+        Ok(compound)
+    }
+
+    const fn is_name_char(c: char) -> bool {
+        c.is_ascii_alphanumeric() || c == '-' || c == '_' || !c.is_ascii()
+    }
+
+    fn name(&mut self) -> Result<String, SelectorError> {
+        let start = self.position;
+        while self.peek().map_or(false, Self::is_name_char) {
+            self.position += 1;
+        }
+
+        if self.position == start {
+            return Err(self.peek().map_or(
+                SelectorError::UnexpectedEnd,
+                SelectorError::UnexpectedCharacter,
+            ));
+        }
+        Ok(self.input[start..self.position].iter().collect())
+    }
+
+    fn attribute(&mut self) -> Result<(String, AttributeOperator), SelectorError> {
+        self.skip_whitespace();
+        let name = self.name()?.to_ascii_lowercase();
+        self.skip_whitespace();
+
+        let operator: fn(String) -> AttributeOperator = match self.next() {
+            Some(']') => return Ok((name, AttributeOperator::Exists)),
+            Some('=') => AttributeOperator::Equals,
+            Some(c @ ('~' | '|' | '^' | '$' | '*')) => {
+                self.expect('=')?;
+                match c {
+                    '~' => AttributeOperator::Includes,
+                    '|' => AttributeOperator::DashMatch,
+                    '^' => AttributeOperator::Prefix,
+                    '$' => AttributeOperator::Suffix,
+                    _ => AttributeOperator::Substring,
+                }
+            }
+            Some(c) => return Err(SelectorError::UnexpectedCharacter(c)),
+            None => return Err(SelectorError::UnexpectedEnd),
+        };
+
+        self.skip_whitespace();
+        let value = match self.peek() {
+            Some(quote @ ('"' | '\'')) => {
+                self.position += 1;
+                let start = self.position;
+                while self.peek().map_or(false, |c| c != quote) {
+                    self.position += 1;
+                }
+                let value = self.input[start..self.position].iter().collect();
+                self.expect(quote)?;
+                value
+            }
+            _ => self.name()?,
+        };
+        self.skip_whitespace();
+        self.expect(']')?;
+
+        Ok((name, operator(value)))
+    }
+
+    fn pseudo_class(&mut self, compound: &mut Compound) -> Result<(), SelectorError> {
+        let name = self.name()?.to_ascii_lowercase();
+
+        match name.as_str() {
+            "nth-child" => {
+                self.expect('(')?;
+                let start = self.position;
+                while self.peek().map_or(false, |c| c != ')') {
+                    self.position += 1;
+                }
+                let argument: String = self.input[start..self.position].iter().collect();
+                self.expect(')')?;
+
+                compound
+                    .nth_child
+                    .push(Self::nth(&argument).ok_or(SelectorError::InvalidNthChild(argument))?);
+            }
+            "not" => {
+                self.expect('(')?;
+                compound.not.push(self.selector_list()?);
+                self.skip_whitespace();
+                self.expect(')')?;
+            }
+            _ => return Err(SelectorError::UnsupportedPseudoClass(name)),
+        }
+
+        Ok(())
+    }
+
+    // Parses the an+b notation, see https://www.w3.org/TR/css-syntax-3/#anb-microsyntax. Values
+    // of a and b beyond i32 are rejected like other invalid arguments.
+    fn nth(argument: &str) -> Option<(i32, i32)> {
+        let argument: String = argument
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .collect::<String>()
+            .to_ascii_lowercase();
+
+        match argument.as_str() {
+            "odd" => return Some((2, 1)),
+            "even" => return Some((2, 0)),
+            _ => {}
+        }
+
+        let Some((a, b)) = argument.split_once('n') else {
+            return argument.parse().ok().map(|b| (0, b));
+        };
+        let a = match a {
+            "" | "+" => 1,
+            "-" => -1,
+            a => a.parse().ok()?,
+        };
+        let b = match b {
+            "" => 0,
+            // the sign is required between "n" and b
+            b if b.starts_with(['+', '-']) => b.parse().ok()?,
+            _ => return None,
+        };
+
+        Some((a, b))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tree_builder::TreeBuilder;
+
+    const HTML: &str = "<div id=main class='content wide'>\
+        <h1 lang=en-US>Title</h1>\
+        <p class=intro>Intro</p>\
+        <ul><li>One<li class=x>Two<li>Three<li data-id=item-4>Four</ul>\
+        <p>Text <a href=https://example.com/a.pdf>link</a></p>\
+        </div><p>Outside</p>";
+
+    // The text of the matching elements
+    fn select(selector: &str) -> Vec<String> {
+        let document = TreeBuilder::build(HTML);
+        let selector = Selector::parse(selector).unwrap();
+
+        document
+            .descendants(Document::ROOT)
+            .into_iter()
+            .filter(|&id| selector.matches(&document, id))
+            .map(|id| document.text_content(id))
+            .collect()
+    }
+
+    #[test]
+    fn type_and_universal() {
+        assert_eq!(vec!["Intro", "Text link", "Outside"], select("p"));
+        assert_eq!(vec!["Title"], select("H1"));
+        assert_eq!(11, select("body *").len());
+    }
+
+    #[test]
+    fn class_and_id() {
+        assert_eq!(vec!["Intro"], select(".intro"));
+        assert_eq!(vec!["Intro"], select("p.intro"));
+        assert_eq!(select("div"), select("#main"));
+        assert_eq!(select("div"), select("div#main.wide.content"));
+        assert!(select(".intro.x").is_empty());
+    }
+
+    #[test]
+    fn attributes() {
+        assert_eq!(vec!["Four"], select("[data-id]"));
+        assert_eq!(vec!["Four"], select("li[data-id=\"item-4\"]"));
+        assert_eq!(vec!["Four"], select("[data-id^=item]"));
+        assert_eq!(vec!["link"], select("a[href$='.pdf']"));
+        assert_eq!(vec!["link"], select("a[href*=example]"));
+        assert_eq!(select("div"), select("[class~=wide]"));
+        assert_eq!(vec!["Title"], select("[lang|=en]"));
+        assert!(select("[class=wide]").is_empty());
+    }
+
+    #[test]
+    fn combinators() {
+        assert_eq!(vec!["Two", "Four"], select("#main li:nth-child(2n)"));
+        assert_eq!(vec!["Intro", "Text link"], select("div > p"));
+        assert_eq!(vec!["link"], select("div p a"));
+        assert!(select("div > a").is_empty());
+        assert_eq!(vec!["Three"], select("li.x + li"));
+        assert_eq!(vec!["Three", "Four"], select("li.x ~ li"));
+        assert_eq!(vec!["Text link"], select("h1 ~ ul + p"));
+    }
+
+    #[test]
+    fn selector_list() {
+        assert_eq!(vec!["Title", "Four"], select("h1, li[data-id]"));
+    }
+
+    #[test]
+    fn nth_child() {
+        assert_eq!(vec!["One", "Three"], select("li:nth-child(odd)"));
+        assert_eq!(vec!["Two", "Four"], select("li:nth-child(even)"));
+        assert_eq!(vec!["Three"], select("li:nth-child(3)"));
+        assert_eq!(vec!["One", "Two"], select("li:nth-child(-n + 2)"));
+        assert_eq!(vec!["Three", "Four"], select("li:nth-child(n+3)"));
+        assert_eq!(vec!["One"], select("li:nth-child(-2147483648n+1)"));
+        assert!(select("li:nth-child(-n-2147483648)").is_empty());
+    }
+
+    #[test]
+    fn not() {
+        assert_eq!(vec!["One", "Three", "Four"], select("li:not(.x)"));
+        assert_eq!(vec!["One", "Four"], select("li:not(.x, :nth-child(3))"));
+        assert_eq!(vec!["Outside"], select("p:not(div p)"));
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(Err(SelectorError::Empty), Selector::parse(" "));
+        assert_eq!(Err(SelectorError::UnexpectedEnd), Selector::parse("div >"));
+        assert_eq!(
+            Err(SelectorError::UnexpectedCharacter('!')),
+            Selector::parse("div!")
+        );
+        assert_eq!(
+            Err(SelectorError::UnexpectedCharacter(',')),
+            Selector::parse("a,,b")
+        );
+        assert_eq!(Err(SelectorError::UnexpectedEnd), Selector::parse("[href"));
+        assert_eq!(
+            Err(SelectorError::UnsupportedPseudoClass(String::from("hover"))),
+            Selector::parse("a:hover")
+        );
+        assert_eq!(
+            Err(SelectorError::InvalidNthChild(String::from("2n1"))),
+            Selector::parse("li:nth-child(2n1)")
+        );
+        assert_eq!(
+            Err(SelectorError::InvalidNthChild(String::from(
+                "-n-9223372036854775807"
+            ))),
+            Selector::parse("li:nth-child(-n-9223372036854775807)")
+        );
+    }
+}
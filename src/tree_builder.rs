@@ -0,0 +1,993 @@
+use crate::dom::{Document, Element, NodeData, NodeId};
+use crate::html_tokenizer::{HtmlTokenizer, Tag, Token};
+
+// Where the tree builder is in the document, a simplified version of the insertion modes of
+// https://html.spec.whatwg.org/multipage/parsing.html#tree-construction
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Mode {
+    BeforeHtml,
+    BeforeHead,
+    InHead,
+    AfterHead,
+    InBody,
+}
+
+// Builds a document from HTML. Like browsers do, it accepts any input: missing html, head and body
+// elements are inserted, elements whose end tag is optional are closed when the next one starts,
+// and formatting elements closed in the wrong order are reopened where they still apply.
+//
+// Tables are built as written, apart from implied tbody and tr elements. Content which the
+// standard moves in front of a table ("foster parenting") stays in it.
+pub struct TreeBuilder {
+    document: Document,
+    mode: Mode,
+    // the stack of open elements, the current node is the last one
+    open_elements: Vec<NodeId>,
+    // formatting elements like <b> which are reopened if they are closed implicitly, None is a
+    // marker for the start of an element like a table cell, which the elements before it don't
+    // apply to
+    active_formatting: Vec<Option<NodeId>>,
+    html: Option<NodeId>,
+    head: Option<NodeId>,
+    body: Option<NodeId>,
+}
+
+impl TreeBuilder {
+    // elements without content and end tag
+    const VOID: [&'static str; 15] = [
+        "area", "base", "br", "col", "embed", "hr", "img", "input", "keygen", "link", "meta",
+        "param", "source", "track", "wbr",
+    ];
+
+    const FORMATTING: [&'static str; 14] = [
+        "a", "b", "big", "code", "em", "font", "i", "nobr", "s", "small", "strike", "strong", "tt",
+        "u",
+    ];
+
+    // elements which belong into the head if they appear before the body
+    const HEAD_CONTENT: [&'static str; 9] = [
+        "base", "basefont", "bgsound", "link", "meta", "noscript", "script", "style", "title",
+    ];
+
+    // elements whose start tag closes an open <p>
+    const CLOSES_P: [&'static str; 41] = [
+        "address",
+        "article",
+        "aside",
+        "blockquote",
+        "center",
+        "dd",
+        "details",
+        "dialog",
+        "dir",
+        "div",
+        "dl",
+        "dt",
+        "fieldset",
+        "figcaption",
+        "figure",
+        "footer",
+        "form",
+        "h1",
+        "h2",
+        "h3",
+        "h4",
+        "h5",
+        "h6",
+        "header",
+        "hgroup",
+        "hr",
+        "li",
+        "listing",
+        "main",
+        "menu",
+        "nav",
+        "ol",
+        "p",
+        "plaintext",
+        "pre",
+        "search",
+        "section",
+        "summary",
+        "table",
+        "ul",
+        "xmp",
+    ];
+
+    // elements whose end tag is implied by the end of their parent
+    const IMPLIED_END: [&'static str; 10] = [
+        "dd", "dt", "li", "optgroup", "option", "p", "rb", "rp", "rt", "rtc",
+    ];
+
+    // elements which bound the search for an element to close, see
+    // https://html.spec.whatwg.org/multipage/parsing.html#has-an-element-in-scope
+    const SCOPE: [&'static str; 9] = [
+        "applet", "caption", "html", "marquee", "object", "table", "td", "template", "th",
+    ];
+
+    // elements an unknown end tag can't close, see
+    // https://html.spec.whatwg.org/multipage/parsing.html#special
+    const SPECIAL: [&'static str; 81] = [
+        "address",
+        "applet",
+        "area",
+        "article",
+        "aside",
+        "base",
+        "basefont",
+        "bgsound",
+        "blockquote",
+        "body",
+        "br",
+        "button",
+        "caption",
+        "center",
+        "col",
+        "colgroup",
+        "dd",
+        "details",
+        "dir",
+        "div",
+        "dl",
+        "dt",
+        "embed",
+        "fieldset",
+        "figcaption",
+        "figure",
+        "footer",
+        "form",
+        "frame",
+        "frameset",
+        "h1",
+        "h2",
+        "h3",
+        "h4",
+        "h5",
+        "h6",
+        "head",
+        "header",
+        "hgroup",
+        "hr",
+        "html",
+        "iframe",
+        "img",
+        "input",
+        "keygen",
+        "li",
+        "link",
+        "listing",
+        "main",
+        "marquee",
+        "menu",
+        "meta",
+        "nav",
+        "noembed",
+        "noframes",
+        "noscript",
+        "object",
+        "ol",
+        "p",
+        "param",
+        "plaintext",
+        "pre",
+        "script",
+        "search",
+        "section",
+        "select",
+        "source",
+        "style",
+        "summary",
+        "table",
+        "tbody",
+        "td",
+        "template",
+        "textarea",
+        "tfoot",
+        "th",
+        "thead",
+        "title",
+        "tr",
+        "track",
+        "ul",
+    ];
+
+    // elements which formatting from outside doesn't apply to
+    const FORMATTING_BOUNDARY: [&'static str; 7] = [
+        "applet", "caption", "marquee", "object", "td", "template", "th",
+    ];
+
+    const HEADINGS: [&'static str; 6] = ["h1", "h2", "h3", "h4", "h5", "h6"];
+
+    // Like in Blink, elements are not nested deeper than this. Beyond it, the current node is
+    // closed and the new element becomes its sibling, so looking through the open elements stays
+    // cheap even for pages with thousands of unclosed tags.
+    const MAX_DEPTH: usize = 512;
+
+    pub fn build(input: &str) -> Document {
+        let mut builder = Self {
+            document: Document::new(),
+            mode: Mode::BeforeHtml,
+            open_elements: Vec::new(),
+            active_formatting: Vec::new(),
+            html: None,
+            head: None,
+            body: None,
+        };
+
+        for token in HtmlTokenizer::new(input) {
+            builder.process(token);
+        }
+        // even an empty document has a body
+        builder.ensure_body();
+
+        builder.document
+    }
+
+    fn process(&mut self, token: Token) {
+        match token {
+            // without scripting and styling there is nothing the doctype would change
+            Token::Doctype(_) => {}
+            Token::Comment(comment) => {
+                let parent = self.current_node().unwrap_or(Document::ROOT);
+                self.document.append(parent, NodeData::Comment(comment));
+            }
+            Token::Text(text) => self.text(&text),
+            Token::StartTag(tag) => self.start_tag(&tag),
+            Token::EndTag(name) => self.end_tag(&name),
+        }
+    }
+
+    fn current_node(&self) -> Option<NodeId> {
+        self.open_elements.last().copied()
+    }
+
+    fn is_current(&self, names: &[&str]) -> bool {
+        self.current_node()
+            .and_then(|id| self.document.element(id))
+            .map_or(false, |element| names.contains(&element.name.as_str()))
+    }
+
+    fn name(&self, id: NodeId) -> &str {
+        self.document
+            .element(id)
+            .map_or("", |element| element.name.as_str())
+    }
+
+    fn insert(&mut self, tag: &Tag) -> NodeId {
+        if self.open_elements.len() >= Self::MAX_DEPTH {
+            if let Some(closed) = self.open_elements.pop() {
+                self.active_formatting
+                    .retain(|&entry| entry != Some(closed));
+            }
+        }
+
+        let parent = self.current_node().unwrap_or(Document::ROOT);
+        let id = self.document.append(
+            parent,
+            NodeData::Element(Element {
+                name: tag.name.clone(),
+                attributes: tag.attributes.clone(),
+            }),
+        );
+
+        if !Self::VOID.contains(&tag.name.as_str()) {
+            self.open_elements.push(id);
+        }
+        id
+    }
+
+    fn insert_implied(&mut self, name: &str) -> NodeId {
+        self.insert(&Tag {
+            name: name.to_string(),
+            ..Tag::default()
+        })
+    }
+
+    // Attributes of a repeated <html> or <body> start tag are added to the existing element
+    fn merge_attributes(&mut self, id: NodeId, tag: &Tag) {
+        if let Some(element) = self.document.element_mut(id) {
+            for (name, value) in &tag.attributes {
+                if element.attribute(name).is_none() {
+                    element.attributes.push((name.clone(), value.clone()));
+                }
+            }
+        }
+    }
+
+    fn ensure_html(&mut self) {
+        if self.mode == Mode::BeforeHtml {
+            self.html = Some(self.insert_implied("html"));
+            self.mode = Mode::BeforeHead;
+        }
+    }
+
+    fn ensure_head(&mut self) {
+        self.ensure_html();
+
+        if self.mode == Mode::BeforeHead {
+            self.head = Some(self.insert_implied("head"));
+            self.mode = Mode::InHead;
+        }
+    }
+
+    fn ensure_body(&mut self) {
+        self.ensure_head();
+
+        if self.mode == Mode::InHead {
+            self.close_head();
+        }
+        if self.mode == Mode::AfterHead {
+            self.body = Some(self.insert_implied("body"));
+            self.mode = Mode::InBody;
+        }
+    }
+
+    fn close_head(&mut self) {
+        if let Some(position) = self
+            .open_elements
+            .iter()
+            .position(|&id| Some(id) == self.head)
+        {
+            self.open_elements.truncate(position);
+        }
+        self.mode = Mode::AfterHead;
+    }
+
+    const fn is_whitespace(c: char) -> bool {
+        matches!(c, '\t' | '\n' | '\x0c' | ' ')
+    }
+
+    fn text(&mut self, mut text: &str) {
+        match self.mode {
+            Mode::InBody => self.reconstruct_formatting(),
+            // text in elements like <title> is the content of the element
+            Mode::InHead | Mode::AfterHead if !self.is_current(&["head", "html"]) => {}
+            _ => {
+                // whitespace before the body stays where it is, or is dropped if there is no
+                // element yet, anything else starts the body
+                let trimmed = text.trim_start_matches(Self::is_whitespace);
+                if matches!(self.mode, Mode::InHead | Mode::AfterHead) {
+                    self.append_text(&text[..text.len() - trimmed.len()]);
+                }
+                if trimmed.is_empty() {
+                    return;
+                }
+
+                self.ensure_body();
+                self.reconstruct_formatting();
+                text = trimmed;
+            }
+        }
+
+        self.append_text(text);
+    }
+
+    fn append_text(&mut self, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+
+        let parent = self.current_node().unwrap_or(Document::ROOT);
+        self.document
+            .append(parent, NodeData::Text(text.to_string()));
+    }
+
+    #[allow(clippy::too_many_lines)]
+    fn start_tag(&mut self, tag: &Tag) {
+        let name = tag.name.as_str();
+
+        match name {
+            "html" => {
+                self.ensure_html();
+                if let Some(html) = self.html {
+                    self.merge_attributes(html, tag);
+                }
+                return;
+            }
+            "head" => {
+                if self.mode == Mode::BeforeHtml || self.mode == Mode::BeforeHead {
+                    self.ensure_html();
+                    self.head = Some(self.insert(tag));
+                    self.mode = Mode::InHead;
+                }
+                return;
+            }
+            "body" => {
+                if self.mode == Mode::InBody {
+                    if let Some(body) = self.body {
+                        self.merge_attributes(body, tag);
+                    }
+                } else {
+                    self.ensure_head();
+                    self.close_head();
+                    self.body = Some(self.insert(tag));
+                    self.mode = Mode::InBody;
+                }
+                return;
+            }
+            _ if Self::HEAD_CONTENT.contains(&name) && self.mode != Mode::InBody => {
+                self.ensure_head();
+
+                if self.mode == Mode::AfterHead {
+                    // the head is reopened for elements after it, but before the body
+                    if let Some(head) = self.head {
+                        self.open_elements.push(head);
+                        self.insert(tag);
+                        self.open_elements.retain(|&id| id != head);
+                    }
+                } else {
+                    self.insert(tag);
+                }
+                return;
+            }
+            _ => self.ensure_body(),
+        }
+
+        if Self::CLOSES_P.contains(&name) && self.in_scope("p", &["button"]) {
+            self.close("p");
+        }
+
+        match name {
+            _ if Self::HEADINGS.contains(&name) && self.is_current(&Self::HEADINGS) => {
+                self.open_elements.pop();
+            }
+            "li" => self.close_list_item(&["li"], &["ol", "ul"]),
+            "dd" | "dt" => self.close_list_item(&["dd", "dt"], &[]),
+            "option" | "optgroup" => {
+                if self.is_current(&["option"]) {
+                    self.open_elements.pop();
+                }
+                if name == "optgroup" && self.is_current(&["optgroup"]) {
+                    self.open_elements.pop();
+                }
+            }
+            "tr" => {
+                self.close_in_table_scope(&["tr"]);
+                if self.is_current(&["table"]) {
+                    self.insert_implied("tbody");
+                }
+            }
+            "td" | "th" => {
+                self.close_in_table_scope(&["td", "th"]);
+                if self.is_current(&["table"]) {
+                    self.insert_implied("tbody");
+                }
+                if self.is_current(&["table", "tbody", "thead", "tfoot"]) {
+                    self.insert_implied("tr");
+                }
+            }
+            "tbody" | "thead" | "tfoot" => {
+                self.close_in_table_scope(&["tbody", "thead", "tfoot"]);
+            }
+            // links can't be nested, an open one is closed first
+            "a" if self.active_formatting_element("a").is_some() => self.end_formatting("a"),
+            _ => {}
+        }
+
+        // formatting applies to inline content, not to blocks like <p>
+        if !Self::SPECIAL.contains(&name)
+            || matches!(
+                name,
+                "applet"
+                    | "area"
+                    | "br"
+                    | "button"
+                    | "embed"
+                    | "img"
+                    | "input"
+                    | "keygen"
+                    | "marquee"
+                    | "object"
+                    | "select"
+                    | "wbr"
+            )
+        {
+            self.reconstruct_formatting();
+        }
+
+        let id = self.insert(tag);
+
+        if Self::FORMATTING.contains(&name) {
+            self.limit_identical_formatting(id);
+            self.active_formatting.push(Some(id));
+        } else if Self::FORMATTING_BOUNDARY.contains(&name) {
+            self.active_formatting.push(None);
+        }
+    }
+
+    fn end_tag(&mut self, name: &str) {
+        match name {
+            "head" if self.mode == Mode::InHead => {
+                self.close_head();
+                return;
+            }
+            "body" | "html" => {
+                // content after the body still belongs into it, the elements stay open
+                self.ensure_body();
+                return;
+            }
+            "br" => {
+                // </br> is treated like <br>
+                self.start_tag(&Tag {
+                    name: name.to_string(),
+                    ..Tag::default()
+                });
+                return;
+            }
+            _ if matches!(self.mode, Mode::InHead | Mode::AfterHead)
+                && self.is_current(&[name]) =>
+            {
+                self.open_elements.pop();
+                return;
+            }
+            _ if self.mode != Mode::InBody => {
+                // an end tag before the body is ignored, unless it closes the head implicitly
+                if name != "p" {
+                    return;
+                }
+                self.ensure_body();
+            }
+            _ => {}
+        }
+
+        if Self::FORMATTING.contains(&name) {
+            self.end_formatting(name);
+        } else if name == "p" {
+            if !self.in_scope("p", &["button"]) {
+                // </p> without an open <p> creates an empty paragraph
+                self.insert_implied("p");
+            }
+            self.close("p");
+        } else if name == "li" {
+            if self.in_scope("li", &["ol", "ul"]) {
+                self.close("li");
+            }
+        } else if matches!(
+            name,
+            "td" | "th" | "tr" | "tbody" | "thead" | "tfoot" | "table"
+        ) {
+            self.close_in_table_scope(&[name]);
+        } else if Self::SPECIAL.contains(&name) {
+            if self.in_scope(name, &[]) {
+                self.close(name);
+            }
+        } else {
+            self.end_other(name);
+        }
+    }
+
+    // Whether an element with the name is open, without one of the scope elements or the extra
+    // ones in between
+    fn in_scope(&self, name: &str, extra: &[&str]) -> bool {
+        for &id in self.open_elements.iter().rev() {
+            let element_name = self.name(id);
+
+            if element_name == name {
+                return true;
+            } else if Self::SCOPE.contains(&element_name) || extra.contains(&element_name) {
+                return false;
+            }
+        }
+
+        false
+    }
+
+    // Closes the innermost element with the name and all elements opened after it
+    fn close(&mut self, name: &str) {
+        self.generate_implied_end_tags(Some(name));
+
+        if let Some(position) = self
+            .open_elements
+            .iter()
+            .rposition(|&id| self.name(id) == name)
+        {
+            let closed = self.open_elements.split_off(position);
+            if closed
+                .iter()
+                .any(|&id| Self::FORMATTING_BOUNDARY.contains(&self.name(id)))
+            {
+                self.clear_formatting_to_marker();
+            }
+        }
+    }
+
+    fn generate_implied_end_tags(&mut self, except: Option<&str>) {
+        while let Some(id) = self.current_node() {
+            let name = self.name(id);
+            if !Self::IMPLIED_END.contains(&name) || Some(name) == except {
+                break;
+            }
+            self.open_elements.pop();
+        }
+    }
+
+    // A new <li>, <dd> or <dt> closes the open one, if there is one in the current list
+    fn close_list_item(&mut self, names: &[&str], list_scope: &[&str]) {
+        for &id in self.open_elements.iter().rev() {
+            let name = self.name(id);
+
+            if names.contains(&name) {
+                let name = name.to_string();
+                self.close(&name);
+                return;
+            } else if (Self::SPECIAL.contains(&name) && !matches!(name, "address" | "div" | "p"))
+                || list_scope.contains(&name)
+            {
+                return;
+            }
+        }
+    }
+
+    // Closes the innermost of the elements if it is in the current table
+    fn close_in_table_scope(&mut self, names: &[&str]) {
+        for &id in self.open_elements.iter().rev() {
+            let name = self.name(id);
+
+            if names.contains(&name) {
+                let name = name.to_string();
+                self.close(&name);
+                return;
+            } else if matches!(name, "html" | "table" | "template") {
+                return;
+            }
+        }
+    }
+
+    // Closes an element which is neither special nor formatting, if it is open and no special
+    // element is in between
+    fn end_other(&mut self, name: &str) {
+        for (position, &id) in self.open_elements.iter().enumerate().rev() {
+            let element_name = self.name(id);
+
+            if element_name == name {
+                self.generate_implied_end_tags(Some(name));
+                self.open_elements.truncate(position);
+                return;
+            } else if Self::SPECIAL.contains(&element_name) {
+                return;
+            }
+        }
+    }
+
+    fn active_formatting_element(&self, name: &str) -> Option<usize> {
+        for (position, entry) in self.active_formatting.iter().enumerate().rev() {
+            match entry {
+                Some(id) if self.name(*id) == name => return Some(position),
+                Some(_) => {}
+                None => return None,
+            }
+        }
+
+        None
+    }
+
+    // A simplified adoption agency algorithm: the formatting element is closed together with the
+    // elements opened after it. Formatting elements among those are reopened by the next content,
+    // so <b><i>x</b>y</i> becomes <b><i>x</i></b><i>y</i>.
+    fn end_formatting(&mut self, name: &str) {
+        let Some(position) = self.active_formatting_element(name) else {
+            self.end_other(name);
+            return;
+        };
+        let Some(id) = self.active_formatting.remove(position) else {
+            return;
+        };
+
+        if let Some(open) = self.open_elements.iter().rposition(|&open| open == id) {
+            // an element like a table in between isn't closed by a formatting end tag
+            let blocked = self.open_elements[open + 1..]
+                .iter()
+                .any(|&id| Self::SCOPE.contains(&self.name(id)));
+            if !blocked {
+                self.open_elements.truncate(open);
+            }
+        }
+    }
+
+    // The "Noah's Ark" clause: of the elements with the same name and attributes since the last
+    // marker, only the last three are kept, so repeated tags can't make the list grow unbounded
+    fn limit_identical_formatting(&mut self, id: NodeId) {
+        let element = self.document.element(id);
+        let identical: Vec<usize> = self
+            .active_formatting
+            .iter()
+            .enumerate()
+            .rev()
+            .map_while(|(position, entry)| entry.map(|entry| (position, entry)))
+            .filter(|&(_, entry)| self.document.element(entry) == element)
+            .map(|(position, _)| position)
+            .collect();
+
+        if identical.len() >= 3 {
+            self.active_formatting
+                .remove(identical[identical.len() - 1]);
+        }
+    }
+
+    // Reopens the formatting elements which have been closed implicitly, see
+    // https://html.spec.whatwg.org/multipage/parsing.html#reconstruct-the-active-formatting-elements
+    fn reconstruct_formatting(&mut self) {
+        // the elements after the last marker or open element are reopened, as the ones before
+        // it are still open
+        let mut start = self.active_formatting.len();
+        while let Some(&Some(id)) = start
+            .checked_sub(1)
+            .and_then(|position| self.active_formatting.get(position))
+        {
+            if self.open_elements.iter().rev().any(|&open| open == id) {
+                break;
+            }
+            start -= 1;
+        }
+
+        // inserting might remove an entry at the maximum depth, hence they are looked up by id
+        let closed: Vec<NodeId> = self.active_formatting[start..]
+            .iter()
+            .flatten()
+            .copied()
+            .collect();
+        for id in closed {
+            let Some(element) = self.document.element(id).cloned() else {
+                continue;
+            };
+            let reopened = self.insert(&Tag {
+                name: element.name,
+                attributes: element.attributes,
+                self_closing: false,
+            });
+
+            if let Some(entry) = self
+                .active_formatting
+                .iter_mut()
+                .rfind(|entry| **entry == Some(id))
+            {
+                *entry = Some(reopened);
+            }
+        }
+    }
+
+    fn clear_formatting_to_marker(&mut self) {
+        while let Some(entry) = self.active_formatting.pop() {
+            if entry.is_none() {
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    // Writes the tree as HTML with all end tags, which makes its structure visible
+    fn serialize(document: &Document, id: NodeId) -> String {
+        let node = document.node(id);
+        let children: String = node
+            .children
+            .iter()
+            .map(|&child| serialize(document, child))
+            .collect();
+
+        match &node.data {
+            NodeData::Document => children,
+            NodeData::Element(element) => {
+                let attributes: String = element
+                    .attributes
+                    .iter()
+                    .map(|(name, value)| format!(" {}=\"{}\"", name, value))
+                    .collect();
+
+                if TreeBuilder::VOID.contains(&element.name.as_str()) {
+                    format!("<{}{}>", element.name, attributes)
+                } else {
+                    format!(
+                        "<{}{}>{}</{}>",
+                        element.name, attributes, children, element.name
+                    )
+                }
+            }
+            NodeData::Text(text) => text.clone(),
+            NodeData::Comment(comment) => format!("<!--{}-->", comment),
+        }
+    }
+
+    fn build(input: &str) -> String {
+        serialize(&TreeBuilder::build(input), Document::ROOT)
+    }
+
+    #[test]
+    fn implied_html_head_and_body() {
+        assert_eq!("<html><head></head><body></body></html>", build(""));
+        assert_eq!(
+            "<html><head></head><body>Hello</body></html>",
+            build("Hello")
+        );
+        assert_eq!(
+            "<html><head><title>T</title></head><body><p>a</p></body></html>",
+            build("<!DOCTYPE html><title>T</title><p>a")
+        );
+    }
+
+    #[test]
+    fn explicit_structure() {
+        assert_eq!(
+            "<html lang=\"en\"><head>\n<meta charset=\"utf-8\">\n</head>\n\
+             <body class=\"a\">\n<p>a</p>\n\n</body></html>",
+            build(
+                "<html lang=en>\n<head>\n<meta charset=utf-8>\n</head>\n\
+                 <body class=a>\n<p>a</p>\n</body>\n</html>"
+            )
+        );
+    }
+
+    #[test]
+    fn text_before_body_starts_it() {
+        assert_eq!(
+            "<html><head><title>T</title> </head><body>text<p>a</p></body></html>",
+            build("<title>T</title> text<p>a</p>")
+        );
+    }
+
+    #[test]
+    fn head_content_after_head() {
+        assert_eq!(
+            "<html><head><style>p {}</style></head><body>a</body></html>",
+            build("<head></head><style>p {}</style>a")
+        );
+    }
+
+    #[test]
+    fn comments() {
+        assert_eq!(
+            "<!--a--><html><head></head><body><!--b--></body></html>",
+            build("<!--a--><body><!--b-->")
+        );
+    }
+
+    #[test]
+    fn paragraphs_are_closed_by_blocks() {
+        assert_eq!(
+            "<html><head></head><body><p>a</p><p>b</p><div>c</div><p></p></body></html>",
+            build("<p>a<p>b<div>c</div></p>")
+        );
+    }
+
+    #[test]
+    fn list_items_are_closed() {
+        assert_eq!(
+            "<html><head></head><body><ul><li>a</li><li>b<ol><li>c</li><li>d</li></ol></li>\
+             </ul><dl><dt>e</dt><dd>f</dd></dl></body></html>",
+            build("<ul><li>a<li>b<ol><li>c<li>d</ol></ul><dl><dt>e<dd>f</dl>")
+        );
+    }
+
+    #[test]
+    fn options_are_closed() {
+        assert_eq!(
+            "<html><head></head><body><select><optgroup><option>a</option><option>b</option>\
+             </optgroup><optgroup><option>c</option></optgroup></select></body></html>",
+            build("<select><optgroup><option>a<option>b<optgroup><option>c</select>")
+        );
+    }
+
+    #[test]
+    fn table_cells_are_closed() {
+        assert_eq!(
+            "<html><head></head><body><table><tbody><tr><td>a</td><td>b</td></tr>\
+             <tr><th>c</th></tr></tbody></table></body></html>",
+            build("<table><tr><td>a<td>b<tr><th>c</table>")
+        );
+        assert_eq!(
+            "<html><head></head><body><table><tbody><tr><td>a</td></tr></tbody></table>\
+             </body></html>",
+            build("<table><td>a</table>")
+        );
+    }
+
+    #[test]
+    fn void_elements() {
+        assert_eq!(
+            "<html><head></head><body>a<br>b<img src=\"x\">c<hr><input><br></body></html>",
+            build("a<br>b<img src=x>c<hr/><input></input></br>")
+        );
+    }
+
+    #[test]
+    fn end_br_is_a_line_break() {
+        assert_eq!(
+            "<html><head></head><body>a<br>b</body></html>",
+            build("a</br>b")
+        );
+    }
+
+    #[test]
+    fn misnested_formatting_elements() {
+        assert_eq!(
+            "<html><head></head><body><b><i>a</i></b><i>b</i></body></html>",
+            build("<b><i>a</b>b</i>")
+        );
+        assert_eq!(
+            "<html><head></head><body><p><b>a</b></p><p><b>b</b></p></body></html>",
+            build("<p><b>a<p>b")
+        );
+    }
+
+    #[test]
+    fn identical_formatting_elements_are_limited() {
+        assert_eq!(
+            "<html><head></head><body><p><b><b><b><b>a</b></b></b></b></p>\
+             <p><b><b><b>b</b></b></b></p></body></html>",
+            build("<p><b><b><b><b>a<p>b")
+        );
+        // different attributes make elements differ
+        assert_eq!(
+            "<html><head></head><body><p><b><b><b><b class=\"x\">a</b></b></b></b></p>\
+             <p><b><b><b><b class=\"x\">b</b></b></b></b></p></body></html>",
+            build("<p><b><b><b><b class=x>a<p>b")
+        );
+    }
+
+    #[test]
+    fn depth_is_limited() {
+        let document = TreeBuilder::build(&format!("{}x", "<div>".repeat(600)));
+        let divs = document.query_selector_all("div").unwrap();
+        assert_eq!(600, divs.len());
+
+        // the ancestors of the deepest div are the other open elements
+        let depth = |id| document.ancestors(id).len();
+        assert_eq!(
+            TreeBuilder::MAX_DEPTH - 1,
+            divs.iter().map(|&id| depth(id)).max().unwrap()
+        );
+        // the last divs are siblings, the text is in the last one
+        assert_eq!(document.parent(divs[598]), document.parent(divs[599]));
+        assert_eq!("x", document.text_content(divs[599]));
+    }
+
+    #[test]
+    fn deep_nesting_is_parsed_in_linear_time() {
+        // without the depth limit, this takes more than 15 seconds in a debug build
+        let start = Instant::now();
+        TreeBuilder::build(&"<div><p><b>".repeat(10_000));
+        TreeBuilder::build(
+            &(0..10_000)
+                .map(|i| format!("<i id={}>", i))
+                .collect::<String>(),
+        );
+        assert!(start.elapsed() < Duration::from_secs(8));
+    }
+
+    #[test]
+    fn nested_links_are_closed() {
+        assert_eq!(
+            "<html><head></head><body><a href=\"1\">a</a><a href=\"2\">b</a></body></html>",
+            build("<a href=1>a<a href=2>b</a>")
+        );
+    }
+
+    #[test]
+    fn unmatched_end_tags_are_ignored() {
+        assert_eq!(
+            "<html><head></head><body><div><span>a</span>b</div>c</body></html>",
+            build("<div><span>a</span></em>b</section></div>c</span>")
+        );
+    }
+
+    #[test]
+    fn formatting_does_not_leave_cells() {
+        assert_eq!(
+            "<html><head></head><body><table><tbody><tr><td><b>a</b></td><td>b</td></tr>\
+             </tbody></table></body></html>",
+            build("<table><tr><td><b>a<td>b</table>")
+        );
+    }
+
+    #[test]
+    fn content_after_body() {
+        assert_eq!(
+            "<html><head></head><body><p>a</p>b</body></html>",
+            build("<body><p>a</p></body></html>b")
+        );
+    }
+}
@@ -4,7 +4,10 @@ use crate::Url;
 #[derive(Debug)]
 pub enum UrlType {
     Http(Url),
+    File(Url),
     ViewSource(Url),
+    // the name of an internal page, e.g. "blank" for about:blank
+    About(String),
     Data {
         mediatype: Option<String>,
         base64: bool,
@@ -18,6 +21,10 @@ impl UrlParser {
     pub fn parse(url: &str) -> Result<UrlType, UrlError> {
         if url.starts_with("http://") || url.starts_with("https://") {
             Ok(UrlType::Http(Url::new(url)?))
+        } else if url.starts_with("file://") {
+            Ok(UrlType::File(Url::new(url)?))
+        } else if let Some(stripped) = url.strip_prefix("about:") {
+            Ok(UrlType::About(stripped.to_ascii_lowercase()))
         } else if let Some(stripped) = url.strip_prefix("view-source:") {
             Ok(UrlType::ViewSource(Url::new(stripped)?))
         } else {
@@ -153,6 +160,24 @@ mod tests {
         assert!(UrlParser::parse("data:nodata").is_err());
     }
 
+    #[test]
+    fn parse_file_url() {
+        let result = UrlParser::parse("file:///tmp/index.html").unwrap();
+        let expected = Url::new("file:///tmp/index.html").unwrap();
+        match result {
+            UrlType::File(url) => assert_eq!(expected, url),
+            _ => assert!(false),
+        }
+    }
+
+    #[test]
+    fn parse_about_url() {
+        match UrlParser::parse("about:Cache").unwrap() {
+            UrlType::About(page) => assert_eq!("cache", page),
+            _ => assert!(false),
+        }
+    }
+
     #[test]
     fn parse_url_with_unknown_scheme() {
         assert!(UrlParser::parse("mailto:x@y.com").is_err());
@@ -0,0 +1,39 @@
+#!/usr/bin/env python3
+# Generates src/hsts_preload.rs from Chromium's HSTS preload list:
+#
+#   curl -o transport_security_state_static.json \
+#     https://raw.githubusercontent.com/chromium/chromium/main/net/http/transport_security_state_static.json
+#   python3 tools/hsts_preload.py transport_security_state_static.json > src/hsts_preload.rs
+#
+# The file is JSON with // comments. Only the entries which force https are kept, entries which
+# only pin keys don't affect the scheme.
+
+import json
+import sys
+
+SOURCE = ("https://chromium.googlesource.com/chromium/src/+/main/net/http/"
+          "transport_security_state_static.json")
+
+
+def main():
+    with open(sys.argv[1], encoding="utf-8") as file:
+        lines = [line for line in file if not line.lstrip().startswith("//")]
+    entries = json.loads("".join(lines))["entries"]
+
+    hosts = {}
+    for entry in entries:
+        if entry.get("mode") == "force-https":
+            hosts[entry["name"].lower()] = bool(entry.get("include_subdomains", False))
+
+    print("// The HSTS preload list of Chromium, generated by tools/hsts_preload.py from")
+    print("// " + SOURCE + ".")
+    print("// The boolean is include_subdomains. Sorted by name, for binary search.")
+    print()
+    print("pub static HSTS_PRELOAD: [(&str, bool); {}] = [".format(len(hosts)))
+    for name in sorted(hosts):
+        print('    ("{}", {}),'.format(name, "true" if hosts[name] else "false"))
+    print("];")
+
+
+if __name__ == "__main__":
+    main()